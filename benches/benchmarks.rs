@@ -9,7 +9,11 @@ mod bench {
     use std::hint::black_box;
 
     use diol::prelude::*;
-    use oxeylyzer_core::{optimization::*, prelude::*};
+    use oxeylyzer_core::{
+        analyzer_data::{AnalyzerData, TrigramStorage},
+        optimization::*,
+        prelude::*,
+    };
     use rand::{distributions::Alphanumeric, Rng};
 
     pub(super) fn main() -> std::io::Result<()> {
@@ -22,17 +26,31 @@ mod bench {
         // );
         // bench.register(generate_real_data, ["monkeyracer", "shai"]);
         bench.register(find_best_swap, ["rstn-oxey"]);
+        bench.register_many(
+            list![
+                find_best_swap_depth,
+                find_pruned_best_swap_depth,
+                par_find_best_swap_depth
+            ],
+            [2],
+        );
         bench.register(analyze_swap, swaps);
         bench.register(init_cached_layout, ["rstn-oxey", "colemak-dh", "sturdy"]);
+        bench.register_many(
+            list![init_analyzer_data, trigram_lookup],
+            [TrigramStorage::Dense, TrigramStorage::Sparse],
+        );
         bench.register(
             optimize,
             [
                 OptimizationMethod::Greedy,
-                OptimizationMethod::GreedyDepth2,
-                OptimizationMethod::GreedyDepth3,
-                // OptimizationMethod::GreedyDepth4,
+                OptimizationMethod::GreedyDepth2(Default::default()),
+                OptimizationMethod::GreedyDepth3(Default::default()),
+                // OptimizationMethod::GreedyDepth4(Default::default()),
                 OptimizationMethod::GreedyAlternative,
                 OptimizationMethod::GreedyAlternativeD3,
+                OptimizationMethod::SimulatedAnnealing(Default::default()),
+                OptimizationMethod::Tabu(Default::default()),
             ],
         );
 
@@ -45,7 +63,7 @@ mod bench {
         let layout = layout.random();
 
         bencher.bench(|| {
-            black_box(method.optimize(&analyzer, layout.clone()));
+            black_box(method.optimize(&analyzer, layout.clone(), &[], 0));
         })
     }
 
@@ -57,6 +75,39 @@ mod bench {
         })
     }
 
+    fn init_analyzer_data(bencher: Bencher, storage: TrigramStorage) {
+        let data = Data::load("./data/shai.json").expect("this should exist");
+        let weights = oxeylyzer_core::weights::dummy_weights();
+
+        let memory = AnalyzerData::with_trigram_storage(&data, &weights, storage)
+            .expect("shai has few enough chars")
+            .trigram_memory();
+        println!("{storage:?} trigrams use {} KiB", memory / 1024);
+
+        bencher.bench(|| black_box(AnalyzerData::with_trigram_storage(&data, &weights, storage)))
+    }
+
+    fn trigram_lookup(bencher: Bencher, storage: TrigramStorage) {
+        let data = Data::load("./data/shai.json").expect("this should exist");
+        let weights = oxeylyzer_core::weights::dummy_weights();
+        let data = AnalyzerData::with_trigram_storage(&data, &weights, storage)
+            .expect("shai has few enough chars");
+
+        let len = data.len() as u16;
+
+        bencher.bench(|| {
+            let mut total = 0;
+            for u1 in 0..len {
+                for u2 in 0..len {
+                    for u3 in 0..len {
+                        total += data.get_trigram_u(black_box([u1, u2, u3]));
+                    }
+                }
+            }
+            black_box(total)
+        })
+    }
+
     fn analyze_swap(bencher: Bencher, swap: PosPair) {
         let (analyzer, layout) = util::analyzer_layout("shai", "rstn-oxey");
         let cache = analyzer.cached_layout(layout, &[]);
@@ -108,6 +159,36 @@ mod bench {
             analyzer.best_swap(&mut cache);
         })
     }
+
+    fn find_best_swap_depth(bencher: Bencher, depth: usize) {
+        let (analyzer, layout) = util::analyzer_layout("shai", "rstn-oxey");
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+
+        bencher.bench(|| {
+            black_box(analyzer.best_swap_depth(&mut cache, depth, false));
+        })
+    }
+
+    fn find_pruned_best_swap_depth(bencher: Bencher, depth: usize) {
+        let (analyzer, layout) = util::analyzer_layout("shai", "rstn-oxey");
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+
+        bencher.bench(|| {
+            black_box(analyzer.best_swap_depth(&mut cache, depth, true));
+        })
+    }
+
+    fn par_find_best_swap_depth(bencher: Bencher, depth: usize) {
+        let (analyzer, layout) = util::analyzer_layout("shai", "rstn-oxey");
+
+        let cache = analyzer.cached_layout(layout, &[]);
+
+        bencher.bench(|| {
+            black_box(analyzer.par_best_swap_depth(&cache, depth, false));
+        })
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
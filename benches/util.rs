@@ -9,7 +9,7 @@ pub fn analyzer_layout(corpus: &str, layout: &str) -> (Analyzer, Layout) {
 
     let weights = oxeylyzer_core::weights::dummy_weights();
 
-    let analyzer = Analyzer::new(data, weights);
+    let analyzer = Analyzer::new(data, weights).expect("this has few enough chars");
 
     let layout = Layout::load(format!("./layouts/{layout}.dof"))
         .expect("this layout is valid and exists, soooo");
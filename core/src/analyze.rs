@@ -1,49 +1,138 @@
+use std::{borrow::Cow, sync::Arc};
+
+use indexmap::IndexMap;
 use itertools::Itertools;
 use libdof::dofinitions::Finger;
 
 use crate::{
-    analyzer_data::AnalyzerData,
+    analyzer_data::{AnalyzerData, KeyTrigram},
     cached_layout::*,
     char_mapping::CharMapping,
+    constraints::ConstraintIndices,
     data::Data,
     layout::*,
-    trigrams::TRIGRAMS,
+    progress::{OptimizationControl, RunProgress},
+    trigrams::TrigramTable,
     weights::{FingerWeights, Weights},
+    Result, MAGIC_KEY, REPEAT_KEY,
 };
 
+/// Scoring a swap goes over every combination of positions when the swapped keys are in more than
+/// this many times the squared amount of keys trigrams of the corpus, which is about as much work.
+const SPARSE_TRIGRAM_COST: usize = 3;
+
+/// Trigram frequency of every category of the trigram table, in the order of the table.
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct TrigramData {
-    pub sft: i64,
-    pub sfb: i64,
-    pub inroll: i64,
-    pub outroll: i64,
-    pub alternate: i64,
-    pub redirect: i64,
-    pub onehandin: i64,
-    pub onehandout: i64,
-    pub thumb: i64,
-    pub invalid: i64,
+pub struct TrigramData(pub IndexMap<String, i64>);
+
+impl TrigramData {
+    pub fn get(&self, category: &str) -> i64 {
+        self.0.get(category).copied().unwrap_or_default()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Analyzer {
     pub data: AnalyzerData,
     pub weights: Weights,
+    /// Corpus the data was made from, shared with the analyzers made by `with_weights`.
+    pub(crate) corpus: Arc<Data>,
     pub analyze_bigrams: bool,
     pub analyze_trigrams: bool,
+    pub trigram_table: TrigramTable,
+    trigram_weights: Box<[i64; 1000]>,
+    finger_usage_targets: [Option<i64>; 10],
+    left_hand_target: Option<i64>,
+    /// Used to cancel optimizations and observe their progress.
+    pub control: OptimizationControl,
 }
 
 impl Analyzer {
-    pub fn new(data: Data, weights: Weights) -> Self {
-        let data = AnalyzerData::new(data, &weights);
+    /// Returns an error if `data` has more than [`MAX_CHARS`](crate::MAX_CHARS) chars.
+    pub fn new(data: Data, weights: Weights) -> Result<Self> {
+        let analyzer_data = AnalyzerData::new(&data, &weights)?;
+
+        Ok(Self::with_data(Arc::new(data), analyzer_data, weights))
+    }
+
+    /// Builds an analyzer from already converted `data`, computing only what depends on
+    /// `weights`.
+    fn with_data(corpus: Arc<Data>, data: AnalyzerData, weights: Weights) -> Self {
         let analyze_bigrams = weights.has_bigram_weights();
         let analyze_trigrams = weights.has_trigram_weights();
+        let trigram_table = TrigramTable::new(&weights.trigram_table);
+        let trigram_weights = trigram_table.map(|category| weights.trigram_weight(category));
+
+        let to_freq = |target: f64| (target * data.char_total) as i64;
+        let finger_usage_targets =
+            Finger::FINGERS.map(|f| weights.finger_usage.get(f).map(to_freq));
+        let left_hand_target = weights.finger_usage.left_hand.map(to_freq);
 
         Self {
             data,
             weights,
+            corpus,
             analyze_bigrams,
             analyze_trigrams,
+            trigram_table,
+            trigram_weights,
+            finger_usage_targets,
+            left_hand_target,
+            control: OptimizationControl::default(),
+        }
+    }
+
+    /// Returns an analyzer in which every char that is typed on another layer of `layout` is
+    /// replaced by the keys it is typed with. If `layout` has a repeat key, every repeated char is
+    /// typed with it, and if it has a magic key, every char its rules output is typed with it,
+    /// unless the corpus already contains these keys. If none of this applies, this analyzer is
+    /// used as is. Returns an error if this adds too many chars to the corpus.
+    pub fn for_layout(&self, layout: &Layout) -> Result<Cow<'_, Self>> {
+        let sequences = layout.key_sequences();
+
+        let expand = sequences.keys().any(|c| self.corpus.chars.contains_key(c));
+        let repeat = self.uses_key(layout, REPEAT_KEY);
+        let magic = self.uses_key(layout, MAGIC_KEY) && !layout.magic_rules.is_empty();
+
+        if !expand && !repeat && !magic {
+            return Ok(Cow::Borrowed(self));
+        }
+
+        let mut data = Cow::Borrowed(&*self.corpus);
+
+        if repeat {
+            data = Cow::Owned(data.rewrite_previous(REPEAT_KEY, Some));
+        }
+
+        if magic {
+            let rules = &layout.magic_rules;
+            data = Cow::Owned(data.rewrite_previous(MAGIC_KEY, |c| rules.get(&c).copied()));
+        }
+
+        if expand {
+            data = Cow::Owned(data.expand_sequences(&sequences));
+        }
+
+        Ok(Cow::Owned(Self {
+            control: self.control.clone(),
+            ..Self::new(data.into_owned(), self.weights.clone())?
+        }))
+    }
+
+    /// Whether `layout` has `key` and it has to be added to the corpus, because the corpus
+    /// doesn't contain it yet.
+    pub(crate) fn uses_key(&self, layout: &Layout, key: char) -> bool {
+        layout.keys.contains(&key) && !self.corpus.chars.contains_key(&key)
+    }
+
+    /// Returns an analyzer for the same corpus that scores with `weights`.
+    pub fn with_weights(&self, weights: Weights) -> Self {
+        let mut data = self.data.clone();
+        data.reweight(&weights);
+
+        Self {
+            control: self.control.clone(),
+            ..Self::with_data(self.corpus.clone(), data, weights)
         }
     }
 
@@ -54,8 +143,19 @@ impl Analyzer {
     }
 
     pub fn score_cache(&self, cache: &CachedLayout) -> i64 {
-        // more metrics will obviously also go here
         cache.weighted_bigrams.total
+            + cache.weighted_trigrams.total
+            + cache.stretch_bigrams.total
+            + cache.scissor_bigrams.total
+            + cache.finger_usage.total
+            + cache.effort.total
+            + self.constraint_penalty(cache)
+    }
+
+    /// Penalty for every constraint the layout violates, which makes sure no optimizer ever
+    /// prefers a layout that violates more of them.
+    pub fn constraint_penalty(&self, cache: &CachedLayout) -> i64 {
+        cache.constraint_indices.penalty(cache.violations.total)
     }
 
     pub fn mapping(&self) -> &CharMapping {
@@ -63,42 +163,90 @@ impl Analyzer {
     }
 
     pub fn cached_layout(&self, layout: Layout, pins: &[usize]) -> CachedLayout {
+        let constraints = layout.constraints;
+        let constraint_indices =
+            ConstraintIndices::new(&constraints, &layout.fingers, &self.data.mapping);
+
+        // chars that can't be scored are kept where they are, so their constraints stay satisfied
+        let mut fixed = constraints.fixed_positions(pins);
+        fixed.extend(constraints.unmapped_positions(&layout.keys, &self.data.mapping));
+
         let keys = layout
             .keys
             .iter()
             .map(|&c| self.data.mapping.get_u(c))
             .collect::<Box<_>>();
+        let chars = layout.keys;
 
         let name = layout.name;
         let fingers = layout.fingers;
         let shape = layout.shape;
+        let board = layout.board;
+        let layers = layout.layers;
+        let magic_rules = layout.magic_rules;
         let char_mapping = self.data.mapping.clone();
         let keyboard = layout.keyboard;
 
-        let possible_swaps = (0..(keys.len() as u8))
-            .filter(|v| !pins.contains(&(*v as usize)))
+        let key_positions = KeyPositions::new(&keys);
+
+        let violations = ViolationCache {
+            total: constraint_indices.violations(&keys, &key_positions, &fingers),
+        };
+
+        // if the constraints are satisfied, swaps that move a key outside of its region are left
+        // out. keys can still leave their region after other swaps, which the penalty prevents.
+        let possible_swaps = (0..(keys.len() as u16))
+            .filter(|v| !fixed.contains(&(*v as usize)))
             .tuple_combinations::<(_, _)>()
+            .filter(|&(a, b)| {
+                violations.total > 0 || constraint_indices.can_swap(&keys, a as usize, b as usize)
+            })
             .map(Into::into)
             .collect();
 
         let unweighted_sfb_indices =
             SfbIndices::new(&fingers, &keyboard, &FingerWeights::default());
         let weighted_sfb_indices = SfbIndices::new(&fingers, &keyboard, &self.weights.fingers);
-        let stretch_indices = Default::default(); //StretchIndices::new(&layout.keys, &fingers, &keyboard);
+        let scissor_indices = ScissorIndices::new(&fingers, &keyboard);
+        let stretch_indices = StretchIndices::new(&fingers, &keyboard);
+
+        let rows = Row::classify(&fingers, &keyboard);
+        // an effort map that doesn't fit the layout is ignored, which the repl warns about when
+        // loading layouts.
+        let effort_map = board
+            .as_ref()
+            .and_then(|b| self.weights.effort.board(b, keys.len()).ok().flatten())
+            .map(|effort| effort.iter().map(|e| (e * 100.0) as i64).collect())
+            .unwrap_or_else(|| effort_map(&fingers, &keyboard, &rows));
 
         let mut cache = CachedLayout {
             name,
             keys,
+            chars,
+            key_positions,
             fingers,
             keyboard,
             possible_swaps,
             weighted_sfb_indices,
             unweighted_sfb_indices,
+            scissor_indices,
             stretch_indices,
             shape,
+            board,
+            layers,
+            magic_rules,
+            constraints,
+            constraint_indices,
+            violations,
             char_mapping,
             weighted_bigrams: Default::default(),
-            // stretch_bigrams: Default::default(),
+            weighted_trigrams: Default::default(),
+            stretch_bigrams: Default::default(),
+            scissor_bigrams: Default::default(),
+            finger_usage: Default::default(),
+            rows,
+            effort_map,
+            effort: Default::default(),
         };
 
         let per_finger = Box::new(Finger::FINGERS.map(|f| self.finger_weighted_bigrams(&cache, f)));
@@ -106,27 +254,61 @@ impl Analyzer {
 
         cache.weighted_bigrams = BigramCache { total, per_finger };
 
+        if self.analyze_trigrams {
+            let total = self.weighted_trigrams(&cache);
+
+            cache.weighted_trigrams = TrigramCache { total };
+        }
+
+        cache.stretch_bigrams = StretchCache {
+            total: self.weighted_lsbs(&cache),
+        };
+
+        cache.scissor_bigrams = ScissorCache {
+            total: self.weighted_scissors(&cache),
+        };
+
+        let per_finger = self.finger_use(&cache);
+        let total = self.weighted_finger_usage(&per_finger);
+
+        cache.finger_usage = FingerUsageCache { total, per_finger };
+
+        cache.effort = EffortCache {
+            total: self.weighted_effort(&cache),
+        };
+
         cache
     }
 
     pub fn greedy_improve(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
         let mut cache = self.cached_layout(layout, pins);
         let mut best_score = self.score_cache(&cache);
+        let mut progress = RunProgress::new(&self.control);
 
         while let Some((swap, score)) = self.best_swap(&mut cache) {
-            if score <= best_score {
+            if score <= best_score || progress.is_cancelled() {
                 break;
             }
 
             best_score = score;
             cache.swap(swap);
             self.update_cache(&mut cache, swap);
+            progress.report(best_score);
         }
 
         (cache.into(), best_score)
     }
 
     pub fn best_swap(&self, cache: &mut CachedLayout) -> Option<(PosPair, i64)> {
+        self.best_swap_where(cache, |_, _| true)
+    }
+
+    /// Best swap out of the ones `allowed` returns true for, given the swap and the score after it.
+    pub fn best_swap_where(
+        &self,
+        cache: &mut CachedLayout,
+        allowed: impl Fn(PosPair, i64) -> bool,
+    ) -> Option<(PosPair, i64)> {
         let swaps = std::mem::take(&mut cache.possible_swaps);
 
         let res = swaps
@@ -137,6 +319,7 @@ impl Analyzer {
                 cache.swap(pair);
                 (pair, score)
             })
+            .filter(|&(pair, score)| allowed(pair, score))
             .max_by(|(_, s1), (_, s2)| s1.cmp(s2));
 
         cache.possible_swaps = swaps;
@@ -194,6 +377,30 @@ impl Analyzer {
         if self.analyze_bigrams {
             self.update_cache_weighted_bigrams(cache, swap);
         }
+
+        if self.analyze_trigrams {
+            self.update_cache_weighted_trigrams(cache, swap);
+        }
+
+        if self.weights.lsbs != 0 {
+            self.update_cache_lsbs(cache, swap);
+        }
+
+        if self.weights.scissors != 0 {
+            self.update_cache_scissors(cache, swap);
+        }
+
+        if self.weights.finger_usage.weight != 0 {
+            self.update_cache_finger_usage(cache, swap);
+        }
+
+        if self.weights.effort.weight != 0 {
+            self.update_cache_effort(cache, swap);
+        }
+
+        if !cache.constraint_indices.is_empty() {
+            cache.violations.total += self.violations_swap_diff(cache, swap);
+        }
     }
 
     fn update_cache_weighted_bigrams(&self, cache: &mut CachedLayout, PosPair(a, b): PosPair) {
@@ -220,16 +427,127 @@ impl Analyzer {
         }
     }
 
+    fn update_cache_weighted_trigrams(&self, cache: &mut CachedLayout, swap: PosPair) {
+        cache.weighted_trigrams.total += self.weighted_trigrams_swap_diff(cache, swap);
+    }
+
+    fn update_cache_lsbs(&self, cache: &mut CachedLayout, swap: PosPair) {
+        cache.stretch_bigrams.total += self.lsbs_swap_diff(cache, swap);
+    }
+
+    fn update_cache_scissors(&self, cache: &mut CachedLayout, swap: PosPair) {
+        cache.scissor_bigrams.total += self.scissors_swap_diff(cache, swap);
+    }
+
+    fn update_cache_finger_usage(&self, cache: &mut CachedLayout, swap: PosPair) {
+        let per_finger = self.swapped_finger_use(cache, swap);
+
+        cache.finger_usage.total = self.weighted_finger_usage(&per_finger);
+        cache.finger_usage.per_finger = per_finger;
+    }
+
+    fn update_cache_effort(&self, cache: &mut CachedLayout, swap: PosPair) {
+        cache.effort.total += self.effort_swap_diff(cache, swap);
+    }
+
     pub fn score_cached_swap(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
         self.score_swap_weighted_bigrams(cache, swap)
+            + self.score_swap_weighted_trigrams(cache, swap)
+            + self.score_swap_lsbs(cache, swap)
+            + self.score_swap_scissors(cache, swap)
+            + self.score_swap_finger_usage(cache, swap)
+            + self.score_swap_effort(cache, swap)
+            + self.score_swap_constraints(cache, swap)
+    }
+
+    fn score_swap_constraints(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if cache.constraint_indices.is_empty() {
+            return 0;
+        }
+
+        let violations = cache.violations.total + self.violations_swap_diff(cache, swap);
+
+        cache.constraint_indices.penalty(violations)
+    }
+
+    /// Change in the amount of constraints `cache` violates caused by `swap`, which has already
+    /// been applied to it.
+    pub(crate) fn violations_swap_diff(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        cache
+            .constraint_indices
+            .swap_diff(&cache.keys, &cache.key_positions, &cache.fingers, swap)
+    }
+
+    fn score_swap_effort(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if self.weights.effort.weight == 0 {
+            return 0;
+        }
+
+        cache.effort.total + self.effort_swap_diff(cache, swap)
+    }
+
+    fn effort_swap_diff(&self, cache: &CachedLayout, PosPair(a, b): PosPair) -> i64 {
+        let (a, b) = (a as usize, b as usize);
+
+        let freq_diff = self.data.get_char_u(cache.keys[a]) - self.data.get_char_u(cache.keys[b]);
+        let effort_diff = cache.effort_map[a] - cache.effort_map[b];
+
+        freq_diff * effort_diff * self.weights.effort.weight
+    }
+
+    fn score_swap_finger_usage(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if self.weights.finger_usage.weight == 0 {
+            return 0;
+        }
+
+        self.weighted_finger_usage(&self.swapped_finger_use(cache, swap))
+    }
+
+    /// Finger usage after `swap`, which has already been applied to `cache`. Only the fingers of
+    /// the two swapped keys can change.
+    fn swapped_finger_use(&self, cache: &CachedLayout, PosPair(a, b): PosPair) -> [i64; 10] {
+        let mut per_finger = cache.finger_usage.per_finger;
+
+        let fa = cache.fingers[a as usize] as usize;
+        let fb = cache.fingers[b as usize] as usize;
+
+        if fa != fb {
+            let diff = self.data.get_char_u(cache.keys[a as usize])
+                - self.data.get_char_u(cache.keys[b as usize]);
+
+            per_finger[fa] += diff;
+            per_finger[fb] -= diff;
+        }
+
+        per_finger
+    }
+
+    /// Total deviation from the finger usage targets, multiplied by their weight.
+    pub fn weighted_finger_usage(&self, per_finger: &[i64; 10]) -> i64 {
+        self.finger_usage_deviation(per_finger) * self.weights.finger_usage.weight
+    }
+
+    pub fn finger_usage_deviation(&self, per_finger: &[i64; 10]) -> i64 {
+        let fingers = per_finger
+            .iter()
+            .zip(&self.finger_usage_targets)
+            .filter_map(|(usage, target)| target.map(|t| usage.abs_diff(t) as i64))
+            .sum::<i64>();
+
+        let left_hand = match self.left_hand_target {
+            Some(t) => per_finger[..5].iter().sum::<i64>().abs_diff(t) as i64,
+            None => 0,
+        };
+
+        fingers + left_hand
     }
 
     fn score_swap_weighted_bigrams(&self, cache: &CachedLayout, PosPair(a, b): PosPair) -> i64 {
-        if self.weights.sfbs == 0 {
+        if !self.analyze_bigrams {
             return 0;
         }
         if a == b {
-            return cache.weighted_bigrams.total * self.weights.sfbs;
+            return cache.weighted_bigrams.total;
         }
 
         let f1 = cache.fingers[a as usize];
@@ -252,6 +570,215 @@ impl Analyzer {
         }
     }
 
+    fn score_swap_lsbs(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if self.weights.lsbs == 0 {
+            return 0;
+        }
+
+        cache.stretch_bigrams.total + self.lsbs_swap_diff(cache, swap)
+    }
+
+    fn lsbs_swap_diff(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        self.weights.lsbs
+            * self.per_key_pairs_swap_diff(cache, &cache.stretch_indices.per_key, swap)
+    }
+
+    fn score_swap_scissors(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if self.weights.scissors == 0 {
+            return 0;
+        }
+
+        cache.scissor_bigrams.total + self.scissors_swap_diff(cache, swap)
+    }
+
+    fn scissors_swap_diff(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        self.weights.scissors
+            * self.per_key_pairs_swap_diff(cache, &cache.scissor_indices.per_key, swap)
+    }
+
+    /// Difference in `freq * dist` over every pair in `per_key` that contains one of the positions
+    /// of `swap`, which has already been applied to `cache`.
+    fn per_key_pairs_swap_diff(
+        &self,
+        cache: &CachedLayout,
+        per_key: &[Box<[BigramPair]>],
+        swap: PosPair,
+    ) -> i64 {
+        let PosPair(a, b) = swap;
+
+        if a == b {
+            return 0;
+        }
+
+        let unswapped = cache.unswapped_key(swap);
+
+        per_key[a as usize]
+            .iter()
+            .chain(
+                per_key[b as usize]
+                    .iter()
+                    .filter(|BigramPair { pair, .. }| pair.0 != a && pair.1 != a),
+            )
+            .map(|pair| {
+                self.pair_freq_dist(pair, |p| cache.keys[p]) - self.pair_freq_dist(pair, &unswapped)
+            })
+            .sum()
+    }
+
+    #[inline]
+    fn pair_freq_dist(
+        &self,
+        BigramPair {
+            pair: PosPair(a, b),
+            dist,
+        }: &BigramPair,
+        key: impl Fn(usize) -> u16,
+    ) -> i64 {
+        let u1 = key(*a as usize);
+        let u2 = key(*b as usize);
+
+        (self.data.get_bigram_u([u1, u2]) + self.data.get_bigram_u([u2, u1])) * dist
+    }
+
+    fn score_swap_weighted_trigrams(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        if !self.analyze_trigrams {
+            return 0;
+        }
+
+        cache.weighted_trigrams.total + self.weighted_trigrams_swap_diff(cache, swap)
+    }
+
+    /// Difference in weighted trigram score caused by `swap`, which has already been applied to
+    /// `cache`. Only trigrams that contain at least one of the swapped positions are evaluated,
+    /// either by going over every trigram of the corpus that contains one of the swapped keys, or
+    /// over every combination of positions, whichever is less work.
+    fn weighted_trigrams_swap_diff(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        let PosPair(a, b) = swap;
+        let (k1, k2) = (cache.keys[a as usize], cache.keys[b as usize]);
+
+        if a == b || k1 == k2 {
+            return 0;
+        }
+
+        let len = cache.keys.len();
+        let sparse = self.data.trigrams_with(k1).len() + self.data.trigrams_with(k2).len();
+
+        match sparse < SPARSE_TRIGRAM_COST * len * len {
+            true => self.sparse_weighted_trigrams_swap_diff(cache, swap),
+            false => {
+                self.weighted_trigrams_touching(cache, swap, |p| cache.keys[p])
+                    - self.weighted_trigrams_touching(cache, swap, cache.unswapped_key(swap))
+            }
+        }
+    }
+
+    /// Difference in weighted trigram score caused by `swap`, going over every trigram of the
+    /// corpus that contains one of the swapped keys.
+    fn sparse_weighted_trigrams_swap_diff(&self, cache: &CachedLayout, swap: PosPair) -> i64 {
+        let PosPair(a, b) = swap;
+        let (k1, k2) = (cache.keys[a as usize], cache.keys[b as usize]);
+
+        if a == b || k1 == k2 {
+            return 0;
+        }
+
+        let finger = |p: u16| cache.fingers[p as usize] as usize;
+        let unswapped_finger = |p: u16| match p {
+            p if p == a => finger(b),
+            p if p == b => finger(a),
+            p => finger(p),
+        };
+
+        let diff = |&(trigram, freq): &KeyTrigram| {
+            let new = self.trigram_weight(cache, trigram, finger);
+            let old = self.trigram_weight(cache, trigram, unswapped_finger);
+
+            (new - old) * freq
+        };
+
+        let with_k1 = self.data.trigrams_with(k1).map(diff);
+        let with_k2 = self
+            .data
+            .trigrams_with(k2)
+            .filter(|(trigram, _)| !trigram.contains(&k1))
+            .map(diff);
+
+        with_k1.chain(with_k2).sum()
+    }
+
+    /// Weighted score of every trigram containing position `a` or `b`, where `key` returns the key
+    /// that should be considered to be on a given position. Every trigram is counted exactly once.
+    fn weighted_trigrams_touching(
+        &self,
+        cache: &CachedLayout,
+        PosPair(a, b): PosPair,
+        key: impl Fn(usize) -> u16,
+    ) -> i64 {
+        let (a, b) = (a as usize, b as usize);
+        let len = cache.keys.len();
+
+        let mut res = 0;
+
+        for p1 in 0..len {
+            let k1 = key(p1);
+            let f1 = cache.fingers[p1] as usize * 100;
+
+            for p2 in 0..len {
+                let k2 = key(p2);
+                let f12 = f1 + cache.fingers[p2] as usize * 10;
+
+                let mut score = |p3: usize| {
+                    let weight = self.trigram_weights[f12 + cache.fingers[p3] as usize];
+
+                    if weight != 0 {
+                        res += self.data.get_trigram_u([k1, k2, key(p3)]) * weight;
+                    }
+                };
+
+                if p1 == a || p1 == b || p2 == a || p2 == b {
+                    (0..len).for_each(&mut score);
+                } else {
+                    score(a);
+                    score(b);
+                }
+            }
+        }
+
+        res
+    }
+
+    /// Weight of a trigram typed with `fingers`.
+    #[inline]
+    pub(crate) fn finger_trigram_weight(&self, [f1, f2, f3]: [Finger; 3]) -> i64 {
+        self.trigram_weights[f1 as usize * 100 + f2 as usize * 10 + f3 as usize]
+    }
+
+    /// Sum of the weights of every way `trigram` can be typed on `cache`, where `finger` returns
+    /// the finger a position should be considered to be typed with.
+    #[inline]
+    fn trigram_weight(
+        &self,
+        cache: &CachedLayout,
+        [k1, k2, k3]: [u16; 3],
+        finger: impl Fn(u16) -> usize,
+    ) -> i64 {
+        let mut res = 0;
+
+        for &p1 in cache.key_positions.get(k1) {
+            let f1 = finger(p1) * 100;
+
+            for &p2 in cache.key_positions.get(k2) {
+                let f12 = f1 + finger(p2) * 10;
+
+                for &p3 in cache.key_positions.get(k3) {
+                    res += self.trigram_weights[f12 + finger(p3)];
+                }
+            }
+        }
+
+        res
+    }
+
     pub fn sfbs(&self, cache: &CachedLayout) -> i64 {
         cache
             .weighted_sfb_indices
@@ -290,6 +817,103 @@ impl Analyzer {
             .sum()
     }
 
+    pub fn weighted_lsbs(&self, cache: &CachedLayout) -> i64 {
+        let lsbs = cache
+            .stretch_indices
+            .all
+            .iter()
+            .map(|pair| self.pair_freq_dist(pair, |p| cache.keys[p]))
+            .sum::<i64>();
+
+        lsbs * self.weights.lsbs
+    }
+
+    pub fn lsbs(&self, cache: &CachedLayout) -> i64 {
+        self.finger_lsbs(cache).iter().sum::<i64>() / 2
+    }
+
+    /// Lateral stretch bigram frequency per finger. Every stretch involves two fingers, so each
+    /// bigram is counted for both of them.
+    pub fn finger_lsbs(&self, cache: &CachedLayout) -> [i64; 10] {
+        let mut res = [0; 10];
+
+        for BigramPair {
+            pair: PosPair(a, b),
+            ..
+        } in cache.stretch_indices.all.iter()
+        {
+            let u1 = cache.keys[*a as usize];
+            let u2 = cache.keys[*b as usize];
+
+            let freq = self.data.get_bigram_u([u1, u2]) + self.data.get_bigram_u([u2, u1]);
+
+            res[cache.fingers[*a as usize] as usize] += freq;
+            res[cache.fingers[*b as usize] as usize] += freq;
+        }
+
+        res
+    }
+
+    pub fn weighted_scissors(&self, cache: &CachedLayout) -> i64 {
+        let scissors = cache
+            .scissor_indices
+            .full
+            .iter()
+            .chain(cache.scissor_indices.half.iter())
+            .map(|pair| self.pair_freq_dist(pair, |p| cache.keys[p]))
+            .sum::<i64>();
+
+        scissors * self.weights.scissors
+    }
+
+    pub fn scissors(&self, cache: &CachedLayout) -> i64 {
+        self.pairs_freq(cache, &cache.scissor_indices.full)
+    }
+
+    pub fn half_scissors(&self, cache: &CachedLayout) -> i64 {
+        self.pairs_freq(cache, &cache.scissor_indices.half)
+    }
+
+    fn pairs_freq(&self, cache: &CachedLayout, pairs: &[BigramPair]) -> i64 {
+        pairs
+            .iter()
+            .map(
+                |BigramPair {
+                     pair: PosPair(a, b),
+                     ..
+                 }| {
+                    let u1 = cache.keys[*a as usize];
+                    let u2 = cache.keys[*b as usize];
+
+                    self.data.get_bigram_u([u1, u2]) + self.data.get_bigram_u([u2, u1])
+                },
+            )
+            .sum()
+    }
+
+    pub fn effort(&self, cache: &CachedLayout) -> i64 {
+        cache
+            .keys
+            .iter()
+            .zip(cache.effort_map.iter())
+            .map(|(&k, &effort)| self.data.get_char_u(k) * effort)
+            .sum()
+    }
+
+    pub fn weighted_effort(&self, cache: &CachedLayout) -> i64 {
+        self.effort(cache) * self.weights.effort.weight
+    }
+
+    pub fn row_use(&self, cache: &CachedLayout) -> [i64; 4] {
+        let mut res = [0; 4];
+
+        for (&k, &r) in cache.keys.iter().zip(cache.rows.iter()) {
+            res[r as usize] += self.data.get_char_u(k);
+        }
+
+        res
+    }
+
     pub fn finger_use(&self, cache: &CachedLayout) -> [i64; 10] {
         let mut res = [0; 10];
 
@@ -334,55 +958,117 @@ impl Analyzer {
             .sum()
     }
 
-    pub fn trigrams(&self, cache: &CachedLayout) -> TrigramData {
-        use crate::trigrams::TrigramType::*;
+    /// Weighted score of every trigram, going over every trigram of the corpus or over every
+    /// combination of positions, whichever is less work.
+    pub fn weighted_trigrams(&self, cache: &CachedLayout) -> i64 {
+        match self.data.trigram_list().len() < cache.keys.len().pow(3) {
+            true => self.sparse_weighted_trigrams(cache),
+            false => self.dense_weighted_trigrams(cache),
+        }
+    }
+
+    fn sparse_weighted_trigrams(&self, cache: &CachedLayout) -> i64 {
+        let finger = |p: u16| cache.fingers[p as usize] as usize;
+
+        self.data
+            .trigram_list()
+            .iter()
+            .map(|&(trigram, freq)| self.trigram_weight(cache, trigram, finger) * freq)
+            .sum()
+    }
 
-        let mut trigrams = TrigramData::default();
+    fn dense_weighted_trigrams(&self, cache: &CachedLayout) -> i64 {
+        let mut res = 0;
 
         for (&c1, &f1) in cache.keys.iter().zip(&cache.fingers) {
             for (&c2, &f2) in cache.keys.iter().zip(&cache.fingers) {
                 for (&c3, &f3) in cache.keys.iter().zip(&cache.fingers) {
-                    let freq = self.data.get_trigram_u([c1, c2, c3]);
-                    let ttype = TRIGRAMS[f1 as usize * 100 + f2 as usize * 10 + f3 as usize];
-
-                    match ttype {
-                        Sft => trigrams.sft += freq,
-                        Sfb => trigrams.sfb += freq,
-                        Inroll => trigrams.inroll += freq,
-                        Outroll => trigrams.outroll += freq,
-                        Alternate => trigrams.alternate += freq,
-                        Redirect => trigrams.redirect += freq,
-                        OnehandIn => trigrams.onehandin += freq,
-                        OnehandOut => trigrams.onehandout += freq,
-                        Thumb => trigrams.thumb += freq,
-                        Invalid => trigrams.invalid += freq,
+                    let weight =
+                        self.trigram_weights[f1 as usize * 100 + f2 as usize * 10 + f3 as usize];
+
+                    if weight != 0 {
+                        res += self.data.get_trigram_u([c1, c2, c3]) * weight;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    pub fn trigrams(&self, cache: &CachedLayout) -> TrigramData {
+        let categories = self.trigram_table.categories();
+        let mut freqs = vec![0; categories.len()];
+
+        if self.data.trigram_list().len() >= cache.keys.len().pow(3) {
+            for (&c1, &f1) in cache.keys.iter().zip(&cache.fingers) {
+                for (&c2, &f2) in cache.keys.iter().zip(&cache.fingers) {
+                    for (&c3, &f3) in cache.keys.iter().zip(&cache.fingers) {
+                        let freq = self.data.get_trigram_u([c1, c2, c3]);
+                        let i = f1 as usize * 100 + f2 as usize * 10 + f3 as usize;
+
+                        freqs[self.trigram_table.get(i)] += freq;
                     }
                 }
             }
+
+            return TrigramData(categories.iter().cloned().zip(freqs).collect());
         }
 
-        trigrams
+        let fingers = |k: u16| {
+            cache
+                .key_positions
+                .get(k)
+                .iter()
+                .map(|&p| cache.fingers[p as usize] as usize)
+        };
+
+        for &([k1, k2, k3], freq) in self.data.trigram_list() {
+            for f1 in fingers(k1) {
+                for f2 in fingers(k2) {
+                    for f3 in fingers(k3) {
+                        freqs[self.trigram_table.get(f1 * 100 + f2 * 10 + f3)] += freq;
+                    }
+                }
+            }
+        }
+
+        TrigramData(categories.iter().cloned().zip(freqs).collect())
     }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests {
-    use crate::weights::dummy_weights;
+    use crate::{test_util::analyzer_layout, weights::dummy_weights};
 
     use super::*;
 
-    fn analyzer_layout() -> (Analyzer, Layout) {
-        let data = Data::load("../data/shai.json").expect("this should exist");
+    #[test]
+    fn sparse_trigrams() {
+        let (analyzer, layout) = analyzer_layout();
 
-        let weights = dummy_weights();
+        let mut cache = analyzer.cached_layout(layout, &[]);
+
+        assert_eq!(
+            analyzer.sparse_weighted_trigrams(&cache),
+            analyzer.dense_weighted_trigrams(&cache)
+        );
 
-        let analyzer = Analyzer::new(data, weights);
+        for swap in cache.possible_swaps.clone() {
+            cache.swap(swap);
 
-        let layout = Layout::load("../layouts/rstn-oxey.dof")
-            .expect("this layout is valid and exists, soooo");
+            let dense = analyzer.weighted_trigrams_touching(&cache, swap, |p| cache.keys[p])
+                - analyzer.weighted_trigrams_touching(&cache, swap, cache.unswapped_key(swap));
 
-        (analyzer, layout)
+            assert_eq!(
+                analyzer.sparse_weighted_trigrams_swap_diff(&cache, swap),
+                dense,
+                "{swap:?}"
+            );
+
+            cache.swap(swap);
+        }
     }
 
     #[test]
@@ -409,4 +1095,139 @@ mod tests {
             assert_eq!(cache, reference, "iteration {i}: ");
         }
     }
+
+    #[test]
+    fn update_cache_lsbs() {
+        let (analyzer, layout) = analyzer_layout();
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+        let reference = cache.clone();
+
+        let possible_swaps = cache.possible_swaps.clone();
+
+        for (i, &swap) in possible_swaps.iter().enumerate() {
+            cache.swap(swap);
+            analyzer.update_cache_lsbs(&mut cache, swap);
+
+            assert_eq!(
+                cache.stretch_bigrams.total,
+                analyzer.weighted_lsbs(&cache),
+                "iteration {i}: "
+            );
+
+            cache.swap(swap);
+            analyzer.update_cache_lsbs(&mut cache, swap);
+
+            assert_eq!(cache, reference, "iteration {i}: ");
+        }
+    }
+
+    #[test]
+    fn update_cache_scissors() {
+        let (analyzer, layout) = analyzer_layout();
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+        let reference = cache.clone();
+
+        let possible_swaps = cache.possible_swaps.clone();
+
+        for (i, &swap) in possible_swaps.iter().enumerate() {
+            cache.swap(swap);
+            analyzer.update_cache_scissors(&mut cache, swap);
+
+            assert_eq!(
+                cache.scissor_bigrams.total,
+                analyzer.weighted_scissors(&cache),
+                "iteration {i}: "
+            );
+
+            cache.swap(swap);
+            analyzer.update_cache_scissors(&mut cache, swap);
+
+            assert_eq!(cache, reference, "iteration {i}: ");
+        }
+    }
+
+    #[test]
+    fn update_cache_finger_usage() {
+        let (analyzer, layout) = analyzer_layout();
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+        let reference = cache.clone();
+
+        let possible_swaps = cache.possible_swaps.clone();
+
+        for (i, &swap) in possible_swaps.iter().enumerate() {
+            cache.swap(swap);
+            analyzer.update_cache_finger_usage(&mut cache, swap);
+
+            let per_finger = analyzer.finger_use(&cache);
+
+            assert_eq!(cache.finger_usage.per_finger, per_finger, "iteration {i}: ");
+            assert_eq!(
+                cache.finger_usage.total,
+                analyzer.weighted_finger_usage(&per_finger),
+                "iteration {i}: "
+            );
+
+            cache.swap(swap);
+            analyzer.update_cache_finger_usage(&mut cache, swap);
+
+            assert_eq!(cache, reference, "iteration {i}: ");
+        }
+    }
+
+    #[test]
+    fn update_cache_effort() {
+        let (analyzer, layout) = analyzer_layout();
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+        let reference = cache.clone();
+
+        let possible_swaps = cache.possible_swaps.clone();
+
+        for (i, &swap) in possible_swaps.iter().enumerate() {
+            cache.swap(swap);
+            analyzer.update_cache_effort(&mut cache, swap);
+
+            assert_eq!(
+                cache.effort.total,
+                analyzer.weighted_effort(&cache),
+                "iteration {i}: "
+            );
+
+            cache.swap(swap);
+            analyzer.update_cache_effort(&mut cache, swap);
+
+            assert_eq!(cache, reference, "iteration {i}: ");
+        }
+    }
+
+    #[test]
+    fn update_cache_trigrams() {
+        let (analyzer, layout) = analyzer_layout();
+
+        let mut cache = analyzer.cached_layout(layout, &[]);
+        let reference = cache.clone();
+
+        let possible_swaps = cache.possible_swaps.clone();
+
+        for (i, &swap) in possible_swaps.iter().enumerate() {
+            cache.swap(swap);
+            let score = analyzer.score_cached_swap(&cache, swap);
+            analyzer.update_cache(&mut cache, swap);
+
+            assert_eq!(
+                cache.weighted_trigrams.total,
+                analyzer.weighted_trigrams(&cache),
+                "iteration {i}: "
+            );
+            assert_eq!(score, analyzer.score_cache(&cache), "iteration {i}: ");
+
+            cache.swap(swap);
+            analyzer.update_cache(&mut cache, swap);
+
+            assert_eq!(cache, reference, "iteration {i}: ");
+        }
+    }
 }
@@ -1,6 +1,87 @@
 use std::sync::Arc;
 
-use crate::{char_mapping::CharMapping, data::Data, weights::Weights};
+use itertools::Itertools;
+
+use crate::{
+    char_mapping::CharMapping, data::Data, weights::Weights, OxeylyzerError, Result, MAX_CHARS,
+};
+
+/// Corpora with up to this many possible trigrams store them in an array with an entry for every
+/// one of them. Corpora with more chars only store the trigrams that occur.
+pub const MAX_DENSE_TRIGRAMS: usize = 1 << 21;
+
+/// Keys of a trigram, along with its frequency.
+pub type KeyTrigram = ([u16; 3], i64);
+
+/// How the frequencies of trigrams are stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrigramStorage {
+    /// An array with an entry for every possible trigram, which is the fastest to look up.
+    Dense,
+    /// Only the trigrams that occur, sorted and indexed by their first key.
+    Sparse,
+}
+
+impl TrigramStorage {
+    /// Storage for a corpus with `len` chars.
+    pub const fn for_len(len: usize) -> Self {
+        match len.saturating_pow(3) <= MAX_DENSE_TRIGRAMS {
+            true => Self::Dense,
+            false => Self::Sparse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Trigrams {
+    Dense(Box<[i64]>),
+    /// Where the trigrams starting with every key start in the trigram list.
+    Sparse(Box<[usize]>),
+}
+
+impl Default for Trigrams {
+    fn default() -> Self {
+        Self::Dense(Box::default())
+    }
+}
+
+/// Trigrams containing every key, as indices into the trigram list. The indices of key `k` are at
+/// `offsets[k]..offsets[k + 1]`, so a trigram is only stored once no matter how many keys it has.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct KeyTrigramIndex {
+    offsets: Box<[usize]>,
+    indices: Box<[u32]>,
+}
+
+impl KeyTrigramIndex {
+    fn new(trigram_list: &[KeyTrigram], len: usize) -> Self {
+        assert!(
+            trigram_list.len() <= u32::MAX as usize,
+            "Too many trigrams to index, max is {}",
+            u32::MAX
+        );
+
+        let mut per_key = vec![Vec::new(); len];
+
+        for (i, (trigram, _)) in trigram_list.iter().enumerate() {
+            for u in trigram.iter().unique() {
+                per_key[*u as usize].push(i as u32);
+            }
+        }
+
+        let offsets = std::iter::once(0)
+            .chain(per_key.iter().scan(0, |end, indices| {
+                *end += indices.len();
+                Some(*end)
+            }))
+            .collect();
+
+        Self {
+            offsets,
+            indices: per_key.concat().into(),
+        }
+    }
+}
 
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct AnalyzerData {
@@ -8,7 +89,9 @@ pub struct AnalyzerData {
     chars: Box<[i64]>,
     bigrams: Box<[i64]>,
     skipgrams: Box<[i64]>,
-    trigrams: Box<[i64]>,
+    trigrams: Trigrams,
+    trigram_list: Box<[KeyTrigram]>,
+    key_trigrams: KeyTrigramIndex,
     weighted_bigrams: Box<[i64]>,
     pub char_total: f64,
     pub bigram_total: f64,
@@ -18,16 +101,33 @@ pub struct AnalyzerData {
 }
 
 impl AnalyzerData {
-    pub fn new(data: Data, weights: &Weights) -> Self {
-        let mut chars = vec![0; data.chars.len() + 2];
+    pub fn new(data: &Data, weights: &Weights) -> Result<Self> {
+        // the mapping can contain a few chars that aren't in the corpus
+        let len = data.chars.len() + CharMapping::new().len();
+        let storage = TrigramStorage::for_len(len);
+
+        Self::with_trigram_storage(data, weights, storage)
+    }
+
+    /// Returns an error if `data` has more than [`MAX_CHARS`] chars.
+    pub fn with_trigram_storage(
+        data: &Data,
+        weights: &Weights,
+        storage: TrigramStorage,
+    ) -> Result<Self> {
+        if data.chars.len() > MAX_CHARS {
+            return Err(OxeylyzerError::TooManyChars(data.chars.len()));
+        }
+
         let mut mapping = CharMapping::new();
+        let mut chars = vec![0; data.chars.len() + mapping.len()];
 
         let char_total = data.char_total as f64 / 100.0;
         let bigram_total = data.bigram_total as f64 / 100.0;
         let skipgram_total = data.skipgram_total as f64 / 100.0;
         let trigram_total = data.trigram_total as f64 / 100.0;
 
-        for (c, f) in data.chars {
+        for (&c, &f) in &data.chars {
             mapping.push(c);
 
             let i = mapping.get_u(c) as usize;
@@ -42,7 +142,7 @@ impl AnalyzerData {
 
         let mut bigrams = vec![0; len.pow(2)];
 
-        for ([c1, c2], f) in data.bigrams {
+        for (&[c1, c2], &f) in &data.bigrams {
             let u1 = mapping.get_u(c1) as usize;
             let u2 = mapping.get_u(c2) as usize;
 
@@ -53,7 +153,7 @@ impl AnalyzerData {
 
         let mut skipgrams = vec![0; len.pow(2)];
 
-        for ([c1, c2], f) in data.skipgrams {
+        for (&[c1, c2], &f) in &data.skipgrams {
             let u1 = mapping.get_u(c1) as usize;
             let u2 = mapping.get_u(c2) as usize;
 
@@ -62,32 +162,50 @@ impl AnalyzerData {
             skipgrams[i] = (f * skipgram_total) as i64;
         }
 
-        let mut trigrams = vec![0; len.pow(3)];
+        let mut trigram_list = data
+            .trigrams
+            .iter()
+            .map(|(&[c1, c2, c3], &f)| {
+                let trigram = [c1, c2, c3].map(|c| mapping.get_u(c));
+                (trigram, (f * trigram_total) as i64)
+            })
+            .filter(|&(_, f)| f != 0)
+            .collect::<Vec<_>>();
 
-        for ([c1, c2, c3], f) in data.trigrams {
-            let u1 = mapping.get_u(c1) as usize;
-            let u2 = mapping.get_u(c2) as usize;
-            let u3 = mapping.get_u(c3) as usize;
+        trigram_list.sort_unstable_by_key(|&(trigram, _)| trigram);
+        debug_assert!(trigram_list.windows(2).all(|w| w[0].0 != w[1].0));
 
-            let i = u1 * len.pow(2) + u2 * len + u3;
-            debug_assert_eq!(trigrams[i], 0);
-            trigrams[i] = (f * trigram_total) as i64;
-        }
+        let key_trigrams = KeyTrigramIndex::new(&trigram_list, len);
 
-        let weighted_bigrams = bigrams
-            .iter()
-            .zip(&skipgrams)
-            .map(|(&b, &s)| weights.sfbs * b + weights.sfs * s)
-            .collect::<Box<_>>();
+        let trigrams = match storage {
+            TrigramStorage::Dense => {
+                let mut trigrams = vec![0; len.pow(3)];
+
+                for &([u1, u2, u3], f) in &trigram_list {
+                    trigrams[u1 as usize * len.pow(2) + u2 as usize * len + u3 as usize] = f;
+                }
+
+                Trigrams::Dense(trigrams.into())
+            }
+            TrigramStorage::Sparse => Trigrams::Sparse(
+                (0..=len)
+                    .map(|u| trigram_list.partition_point(|([u1, ..], _)| (*u1 as usize) < u))
+                    .collect(),
+            ),
+        };
+
+        let weighted_bigrams = Self::weighted_bigrams(&bigrams, &skipgrams, weights);
 
         let mapping = Arc::new(mapping);
 
-        Self {
-            name: data.name,
+        Ok(Self {
+            name: data.name.clone(),
             chars: chars.into(),
             bigrams: bigrams.into(),
             skipgrams: skipgrams.into(),
-            trigrams: trigrams.into(),
+            trigrams,
+            trigram_list: trigram_list.into(),
+            key_trigrams,
             weighted_bigrams,
 
             char_total,
@@ -96,7 +214,21 @@ impl AnalyzerData {
             trigram_total,
 
             mapping,
-        }
+        })
+    }
+
+    /// Recomputes everything that depends on the weights, so the corpus doesn't have to be
+    /// converted again.
+    pub fn reweight(&mut self, weights: &Weights) {
+        self.weighted_bigrams = Self::weighted_bigrams(&self.bigrams, &self.skipgrams, weights);
+    }
+
+    fn weighted_bigrams(bigrams: &[i64], skipgrams: &[i64], weights: &Weights) -> Box<[i64]> {
+        bigrams
+            .iter()
+            .zip(skipgrams)
+            .map(|(&b, &s)| weights.sfbs * b + weights.sfs * s)
+            .collect()
     }
 
     pub fn len(&self) -> usize {
@@ -133,12 +265,52 @@ impl AnalyzerData {
     }
 
     pub fn get_trigram(&self, [c1, c2, c3]: [char; 3]) -> i64 {
-        let u1 = self.mapping.get_u(c1) as usize;
-        let u2 = self.mapping.get_u(c2) as usize;
-        let u3 = self.mapping.get_u(c3) as usize;
+        self.get_trigram_u([c1, c2, c3].map(|c| self.mapping.get_u(c)))
+    }
+
+    pub fn trigram_storage(&self) -> TrigramStorage {
+        match self.trigrams {
+            Trigrams::Dense(_) => TrigramStorage::Dense,
+            Trigrams::Sparse(_) => TrigramStorage::Sparse,
+        }
+    }
 
-        let i = u1 * self.len().pow(2) + u2 * self.len() + u3;
-        self.trigrams[i]
+    /// Amount of bytes used to store trigrams, which includes the trigram list and the trigrams of
+    /// every key besides the trigram frequencies themselves.
+    pub fn trigram_memory(&self) -> usize {
+        use std::mem::size_of_val;
+
+        let lookup = match &self.trigrams {
+            Trigrams::Dense(trigrams) => size_of_val::<[i64]>(trigrams),
+            Trigrams::Sparse(offsets) => size_of_val::<[usize]>(offsets),
+        };
+
+        lookup
+            + size_of_val::<[KeyTrigram]>(&self.trigram_list)
+            + size_of_val::<[usize]>(&self.key_trigrams.offsets)
+            + size_of_val::<[u32]>(&self.key_trigrams.indices)
+    }
+
+    /// Every trigram that occurs in the corpus, with its frequency.
+    pub fn trigram_list(&self) -> &[KeyTrigram] {
+        &self.trigram_list
+    }
+
+    /// Every trigram containing `key` that occurs in the corpus, with its frequency. Trigrams
+    /// containing `key` more than once are only included once.
+    #[inline]
+    pub fn trigrams_with(&self, key: u16) -> impl ExactSizeIterator<Item = &KeyTrigram> {
+        let KeyTrigramIndex { offsets, indices } = &self.key_trigrams;
+        let k = key as usize;
+
+        let range = match offsets.get(k..k + 2) {
+            Some(&[start, end]) => start..end,
+            _ => 0..0,
+        };
+
+        indices[range]
+            .iter()
+            .map(|&i| &self.trigram_list[i as usize])
     }
 
     pub fn get_weighted_bigram(&self, [c1, c2]: [char; 2]) -> i64 {
@@ -150,12 +322,12 @@ impl AnalyzerData {
     }
 
     #[inline]
-    pub fn get_char_u(&self, c: u8) -> i64 {
+    pub fn get_char_u(&self, c: u16) -> i64 {
         self.chars[c as usize]
     }
 
     #[inline]
-    pub fn get_bigram_u(&self, [c1, c2]: [u8; 2]) -> i64 {
+    pub fn get_bigram_u(&self, [c1, c2]: [u16; 2]) -> i64 {
         let u1 = c1 as usize;
         let u2 = c2 as usize;
 
@@ -164,7 +336,7 @@ impl AnalyzerData {
     }
 
     #[inline]
-    pub fn get_skipgram_u(&self, [c1, c2]: [u8; 2]) -> i64 {
+    pub fn get_skipgram_u(&self, [c1, c2]: [u16; 2]) -> i64 {
         let u1 = c1 as usize;
         let u2 = c2 as usize;
 
@@ -173,17 +345,28 @@ impl AnalyzerData {
     }
 
     #[inline]
-    pub fn get_trigram_u(&self, [c1, c2, c3]: [u8; 3]) -> i64 {
-        let u1 = c1 as usize;
-        let u2 = c2 as usize;
-        let u3 = c3 as usize;
-
-        let i = u1 * self.len().pow(2) + u2 * self.len() + u3;
-        self.trigrams[i]
+    pub fn get_trigram_u(&self, [c1, c2, c3]: [u16; 3]) -> i64 {
+        match &self.trigrams {
+            Trigrams::Dense(trigrams) => {
+                let u1 = c1 as usize;
+                let u2 = c2 as usize;
+                let u3 = c3 as usize;
+
+                let i = u1 * self.len().pow(2) + u2 * self.len() + u3;
+                trigrams[i]
+            }
+            Trigrams::Sparse(offsets) => {
+                let trigrams = &self.trigram_list[offsets[c1 as usize]..offsets[c1 as usize + 1]];
+
+                trigrams
+                    .binary_search_by_key(&[c1, c2, c3], |&(trigram, _)| trigram)
+                    .map_or(0, |i| trigrams[i].1)
+            }
+        }
     }
 
     #[inline]
-    pub fn get_weighted_bigram_u(&self, [c1, c2]: [u8; 2]) -> i64 {
+    pub fn get_weighted_bigram_u(&self, [c1, c2]: [u16; 2]) -> i64 {
         let u1 = c1 as usize;
         let u2 = c2 as usize;
 
@@ -191,3 +374,98 @@ impl AnalyzerData {
         self.weighted_bigrams[i]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weights::dummy_weights;
+
+    #[test]
+    fn trigram_storage() {
+        let data = "the quick brown fox jumps over the lazy dog, then jumps again"
+            .chars()
+            .collect::<Data>();
+        let weights = dummy_weights();
+
+        let dense =
+            AnalyzerData::with_trigram_storage(&data, &weights, TrigramStorage::Dense).unwrap();
+        let sparse =
+            AnalyzerData::with_trigram_storage(&data, &weights, TrigramStorage::Sparse).unwrap();
+
+        assert_eq!(dense.trigram_storage(), TrigramStorage::Dense);
+        assert_eq!(sparse.trigram_storage(), TrigramStorage::Sparse);
+        assert!(sparse.trigram_memory() < dense.trigram_memory());
+        assert_eq!(
+            dense.trigram_memory() - sparse.trigram_memory(),
+            dense.len().pow(3) * std::mem::size_of::<i64>()
+                - (dense.len() + 1) * std::mem::size_of::<usize>()
+        );
+
+        let len = dense.len() as u16;
+        for trigram in (0..len)
+            .flat_map(|u1| (0..len).flat_map(move |u2| (0..len).map(move |u3| [u1, u2, u3])))
+        {
+            assert_eq!(dense.get_trigram_u(trigram), sparse.get_trigram_u(trigram));
+        }
+
+        assert!(dense.get_trigram(['t', 'h', 'e']) > 0);
+        assert_eq!(
+            sparse.get_trigram(['t', 'h', 'e']),
+            dense.get_trigram(['t', 'h', 'e'])
+        );
+    }
+
+    #[test]
+    fn trigrams_with() {
+        let data = "the quick brown fox jumps over the lazy dog, then jumps again"
+            .chars()
+            .collect::<Data>();
+        let data = AnalyzerData::new(&data, &dummy_weights()).unwrap();
+
+        for key in 0..data.len() as u16 {
+            let expected = data
+                .trigram_list()
+                .iter()
+                .filter(|(trigram, _)| trigram.contains(&key))
+                .collect::<Vec<_>>();
+
+            assert_eq!(data.trigrams_with(key).collect::<Vec<_>>(), expected);
+        }
+
+        assert_eq!(data.trigrams_with(data.len() as u16).len(), 0);
+    }
+
+    #[test]
+    fn reweight() {
+        let data = "the quick brown fox jumps over the lazy dog, then jumps again"
+            .chars()
+            .collect::<Data>();
+        let weights = dummy_weights();
+        let other = Weights {
+            sfbs: weights.sfbs * 3,
+            sfs: weights.sfs + 5,
+            ..weights.clone()
+        };
+
+        let mut reweighted = AnalyzerData::new(&data, &weights).unwrap();
+        reweighted.reweight(&other);
+
+        assert_eq!(reweighted, AnalyzerData::new(&data, &other).unwrap());
+    }
+
+    #[test]
+    fn too_many_chars() {
+        let data = ('\u{4e00}'..).take(MAX_CHARS + 1).collect::<Data>();
+
+        assert!(matches!(
+            AnalyzerData::new(&data, &dummy_weights()),
+            Err(OxeylyzerError::TooManyChars(len)) if len == MAX_CHARS + 1
+        ));
+    }
+
+    #[test]
+    fn storage_for_len() {
+        assert_eq!(TrigramStorage::for_len(100), TrigramStorage::Dense);
+        assert_eq!(TrigramStorage::for_len(200), TrigramStorage::Sparse);
+    }
+}
@@ -3,36 +3,78 @@ use libdof::{
     // dofinitions::Hand,
     prelude::{Finger, PhysicalKey, Shape},
 };
-use std::sync::Arc;
-
-use crate::{char_mapping::CharMapping, layout::PosPair, weights::FingerWeights};
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{
+    char_mapping::CharMapping,
+    constraints::{ConstraintIndices, Constraints},
+    layout::{LayoutLayer, PosPair},
+    weights::FingerWeights,
+    MAX_KEYS,
+};
 
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct CachedLayout {
     pub name: String,
-    pub keys: Box<[u8]>,
+    pub keys: Box<[u16]>,
+    /// Char of every key. Chars that aren't in the corpus all have key 0, which has no frequency,
+    /// so these keep track of them.
+    pub chars: Box<[char]>,
+    pub key_positions: KeyPositions,
     pub fingers: Box<[Finger]>,
     pub keyboard: Box<[PhysicalKey]>,
     pub shape: Shape,
+    pub board: Option<String>,
+    pub layers: Box<[LayoutLayer]>,
+    pub magic_rules: BTreeMap<char, char>,
+    pub constraints: Constraints,
+    pub constraint_indices: ConstraintIndices,
+    pub violations: ViolationCache,
     pub char_mapping: Arc<CharMapping>,
     pub possible_swaps: Box<[PosPair]>,
     pub weighted_sfb_indices: SfbIndices,
     pub unweighted_sfb_indices: SfbIndices,
+    pub scissor_indices: ScissorIndices,
     pub weighted_bigrams: BigramCache,
+    pub weighted_trigrams: TrigramCache,
     pub stretch_indices: StretchIndices,
-    // pub stretch_bigrams: StretchCache,
+    pub stretch_bigrams: StretchCache,
+    pub scissor_bigrams: ScissorCache,
+    pub finger_usage: FingerUsageCache,
+    pub rows: Box<[Row]>,
+    pub effort_map: Box<[i64]>,
+    pub effort: EffortCache,
 }
 
 impl CachedLayout {
     #[inline]
     pub fn swap(&mut self, PosPair(k1, k2): PosPair) {
+        let keys = [self.keys[k1 as usize], self.keys[k2 as usize]];
+        self.key_positions.swap(keys, PosPair(k1, k2));
         self.keys.swap(k1 as usize, k2 as usize);
+        self.chars.swap(k1 as usize, k2 as usize);
+
+        for layer in self.layers.iter_mut() {
+            layer.keys.swap(k1 as usize, k2 as usize);
+        }
+    }
+
+    /// Returns a function giving the key that was on a position before `swap` was applied.
+    #[inline]
+    pub fn unswapped_key(&self, PosPair(a, b): PosPair) -> impl Fn(usize) -> u16 + '_ {
+        let (a, b) = (a as usize, b as usize);
+
+        move |p| match p {
+            p if p == a => self.keys[b],
+            p if p == b => self.keys[a],
+            p => self.keys[p],
+        }
     }
 }
 
 impl std::fmt::Display for CachedLayout {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut iter = self.keys.iter().map(|&u| self.char_mapping.get_c(u));
+        let mut iter = self.chars.iter();
 
         for l in self.shape.inner().iter() {
             let mut i = 0;
@@ -51,6 +93,46 @@ impl std::fmt::Display for CachedLayout {
     }
 }
 
+/// Every position each key is on, by key.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyPositions(Box<[Box<[u16]>]>);
+
+impl KeyPositions {
+    pub fn new(keys: &[u16]) -> Self {
+        let len = keys.iter().max().map_or(0, |&k| k as usize + 1);
+        let mut positions = vec![Vec::new(); len];
+
+        for (p, &k) in (0u16..).zip(keys) {
+            positions[k as usize].push(p);
+        }
+
+        Self(positions.into_iter().map(Into::into).collect())
+    }
+
+    #[inline]
+    pub fn get(&self, key: u16) -> &[u16] {
+        self.0.get(key as usize).map_or(&[], |positions| positions)
+    }
+
+    /// Moves `k1` from `p1` to `p2`, and `k2` from `p2` to `p1`.
+    #[inline]
+    fn swap(&mut self, [k1, k2]: [u16; 2], PosPair(p1, p2): PosPair) {
+        if k1 == k2 {
+            return;
+        }
+
+        for (k, from, to) in [(k1, p1, p2), (k2, p2, p1)] {
+            if let Some(p) = self
+                .0
+                .get_mut(k as usize)
+                .and_then(|positions| positions.iter_mut().find(|p| **p == from))
+            {
+                *p = to;
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct BigramPair {
     pub pair: PosPair,
@@ -74,9 +156,8 @@ impl SfbIndices {
         finger_weights: &FingerWeights,
     ) -> Self {
         assert!(
-            fingers.len() <= u8::MAX as usize,
-            "Too many keys to index with u8, max is {}",
-            u8::MAX
+            fingers.len() <= MAX_KEYS,
+            "Too many keys to index, max is {MAX_KEYS}"
         );
         assert_eq!(
             fingers.len(),
@@ -89,7 +170,7 @@ impl SfbIndices {
                 fingers
                     .iter()
                     .zip(keyboard)
-                    .zip(0u8..)
+                    .zip(0u16..)
                     .filter_map(|((f, k), i)| (f == &finger).then_some((k, i)))
                     .tuple_combinations::<(_, _)>()
                     .map(|((k1, i1), (k2, i2))| BigramPair {
@@ -111,23 +192,262 @@ impl SfbIndices {
     }
 }
 
+/// Bigrams on adjacent fingers of the same hand that jump rows. A full scissor jumps (at least) two
+/// rows, a half scissor jumps one row with the shorter finger of the two being on the higher key.
+/// The `dist` of each pair is 100 for full scissors and 50 for half scissors.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScissorIndices {
+    pub per_key: Box<[Box<[BigramPair]>]>,
+    pub full: Box<[BigramPair]>,
+    pub half: Box<[BigramPair]>,
+}
+
+impl ScissorIndices {
+    pub fn get_key(&self, pos: u16) -> &[BigramPair] {
+        &self.per_key[pos as usize]
+    }
+
+    pub fn new(fingers: &[Finger], keyboard: &[PhysicalKey]) -> Self {
+        assert!(
+            fingers.len() <= MAX_KEYS,
+            "Too many keys to index, max is {MAX_KEYS}"
+        );
+        assert_eq!(
+            fingers.len(),
+            keyboard.len(),
+            "finger len is not the same as keyboard len: "
+        );
+
+        let (full, half): (Vec<_>, Vec<_>) = keyboard
+            .iter()
+            .zip(fingers)
+            .zip(0u16..)
+            .tuple_combinations::<(_, _)>()
+            .filter(|(((_, f1), _), ((_, f2), _))| {
+                is_left(f1) == is_left(f2)
+                    && (**f1 as u8).abs_diff(**f2 as u8) == 1
+                    && !f1.is_thumb()
+                    && !f2.is_thumb()
+            })
+            .filter_map(|(((k1, f1), i1), ((k2, f2), i2))| {
+                let dy = (k1.y() + k1.height() / 2.0) - (k2.y() + k2.height() / 2.0);
+
+                let dist = if dy.abs() >= 1.5 {
+                    100
+                } else if dy.abs() >= 0.5 {
+                    let (higher, lower) = if dy < 0.0 { (f1, f2) } else { (f2, f1) };
+
+                    if finger_length(higher) < finger_length(lower) {
+                        50
+                    } else {
+                        return None;
+                    }
+                } else {
+                    return None;
+                };
+
+                Some(BigramPair {
+                    pair: PosPair(i1, i2),
+                    dist,
+                })
+            })
+            .partition(|BigramPair { dist, .. }| *dist == 100);
+
+        let per_key = (0..(fingers.len() as u16))
+            .map(|i| {
+                full.iter()
+                    .chain(&half)
+                    .filter(|BigramPair { pair, .. }| pair.0 == i || pair.1 == i)
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            per_key,
+            full: full.into(),
+            half: half.into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScissorCache {
+    pub total: i64,
+}
+
+/// Rough order of finger length, used to find out whether a row jump is uncomfortable. Only
+/// adjacent fingers are compared, so the pinky is shorter than the ring finger, and the ring and
+/// index fingers are both shorter than the middle finger.
+#[inline]
+fn finger_length(f: &Finger) -> u8 {
+    match f {
+        Finger::LP | Finger::RP => 0,
+        Finger::LR | Finger::RR => 1,
+        Finger::LI | Finger::RI => 2,
+        Finger::LM | Finger::RM => 3,
+        Finger::LT | Finger::RT => 0,
+    }
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct BigramCache {
     pub total: i64,
     pub per_finger: Box<[i64; 10]>,
 }
 
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EffortCache {
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ViolationCache {
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Row {
+    Top,
+    Home,
+    Bottom,
+    Thumb,
+}
+
+impl Row {
+    pub const ROWS: [Self; 4] = [Self::Top, Self::Home, Self::Bottom, Self::Thumb];
+
+    /// Classifies every key. Keys pressed by a thumb are on the thumb row, and the home row is the
+    /// row with the most non-thumb keys. Every row above it counts as top, and below as bottom.
+    pub fn classify(fingers: &[Finger], keyboard: &[PhysicalKey]) -> Box<[Self]> {
+        let row_of = |k: &PhysicalKey| k.y().round() as i64;
+
+        let counts = fingers
+            .iter()
+            .zip(keyboard)
+            .filter(|(f, _)| !f.is_thumb())
+            .map(|(_, k)| row_of(k))
+            .counts();
+
+        let max = counts.values().copied().max().unwrap_or_default();
+        let candidates = counts
+            .into_iter()
+            .filter_map(|(row, count)| (count == max).then_some(row))
+            .sorted()
+            .collect::<Vec<_>>();
+
+        let home = candidates
+            .get(candidates.len() / 2)
+            .copied()
+            .unwrap_or_default();
+
+        fingers
+            .iter()
+            .zip(keyboard)
+            .map(|(f, k)| match row_of(k) {
+                _ if f.is_thumb() => Self::Thumb,
+                row if row < home => Self::Top,
+                row if row > home => Self::Bottom,
+                _ => Self::Home,
+            })
+            .collect()
+    }
+}
+
+/// Generates the effort of pressing every key, in hundredths of key units. Every finger has a base
+/// effort, to which the distance from the home key of that finger is added, scaled by how well
+/// that finger handles reaching. Pinkies are additionally penalized for reaching to the top row.
+pub fn effort_map(fingers: &[Finger], keyboard: &[PhysicalKey], rows: &[Row]) -> Box<[i64]> {
+    let center = |k: &PhysicalKey| (k.x() + k.width() / 2.0, k.y() + k.height() / 2.0);
+
+    let home_keys = Finger::FINGERS.map(|finger| {
+        let hand_center = fingers
+            .iter()
+            .zip(keyboard)
+            .zip(rows)
+            .filter(|((f, _), r)| {
+                **f != finger && is_left(f) == is_left(&finger) && **r == Row::Home
+            })
+            .map(|((_, k), _)| center(k).0)
+            .collect::<Vec<_>>();
+
+        let hand_center = match hand_center.len() {
+            0 => return None,
+            len => hand_center.iter().sum::<f64>() / len as f64,
+        };
+
+        fingers
+            .iter()
+            .zip(keyboard)
+            .zip(rows)
+            .filter(|((f, _), r)| **f == finger && (**r == Row::Home || finger.is_thumb()))
+            .map(|((_, k), _)| center(k))
+            .min_by(|(x1, _), (x2, _)| {
+                (x1 - hand_center)
+                    .abs()
+                    .total_cmp(&(x2 - hand_center).abs())
+            })
+    });
+
+    fingers
+        .iter()
+        .zip(keyboard)
+        .zip(rows)
+        .map(|((f, k), r)| {
+            let (base, reach_factor) = match f {
+                Finger::LP | Finger::RP => (0.5, 1.5),
+                Finger::LR | Finger::RR => (0.25, 1.3),
+                Finger::LM | Finger::RM => (0.0, 1.1),
+                Finger::LI | Finger::RI => (0.0, 1.0),
+                Finger::LT | Finger::RT => (0.25, 1.2),
+            };
+
+            let (x, y) = center(k);
+
+            let reach = match home_keys[*f as usize] {
+                Some((hx, hy)) => (x - hx).hypot(y - hy),
+                None => 0.0,
+            };
+
+            let pinky_top = match f.is_pinky() && *r == Row::Top {
+                true => 0.5,
+                false => 0.0,
+            };
+
+            ((base + reach * reach_factor + pinky_top) * 100.0) as i64
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FingerUsageCache {
+    pub total: i64,
+    pub per_finger: [i64; 10],
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrigramCache {
+    pub total: i64,
+}
+
+/// Stretches shorter than this (in key units) are too small to be considered a lateral stretch.
+const MIN_STRETCH: f64 = 0.5;
+
 #[derive(Debug, Clone, Default, PartialEq)]
 pub struct StretchIndices {
-    per_key: Box<[Box<[BigramPair]>]>,
+    pub per_key: Box<[Box<[BigramPair]>]>,
+    pub all: Box<[BigramPair]>,
 }
 
 impl StretchIndices {
-    pub fn new(keys: &[char], fingers: &[Finger], keyboard: &[PhysicalKey]) -> Self {
+    pub fn get_key(&self, pos: u16) -> &[BigramPair] {
+        &self.per_key[pos as usize]
+    }
+
+    pub fn new(fingers: &[Finger], keyboard: &[PhysicalKey]) -> Self {
         assert!(
-            fingers.len() <= u8::MAX as usize,
-            "Too many keys to index with u8, max is {}",
-            u8::MAX
+            fingers.len() <= MAX_KEYS,
+            "Too many keys to index, max is {MAX_KEYS}"
         );
         assert_eq!(
             fingers.len(),
@@ -135,44 +455,52 @@ impl StretchIndices {
             "finger len is not the same as keyboard len: "
         );
 
-        #[cfg(target_arch = "wasm32")]
-        return Self::default();
-
-        keyboard
+        let all = keyboard
             .iter()
             .zip(fingers)
-            .zip(keys)
+            .zip(0u16..)
             .tuple_combinations::<(_, _)>()
             .filter(|(((_, f1), _), ((_, f2), _))| {
-                f1 != f2 && (!((**f1 as u8) < 5) && !((**f2 as u8) < 5))
+                f1 != f2 && is_left(f1) == is_left(f2) && !f1.is_thumb() && !f2.is_thumb()
             })
-            .for_each(|(((k1, f1), c1), ((k2, f2), c2))| {
+            .filter_map(|(((k1, f1), i1), ((k2, f2), i2))| {
                 let fd = (*f1 as u8).abs_diff(*f2 as u8) as f64 * 1.3;
-                // let fd = match f1.is_thumb() || f2.is_thumb() {
-                //     true => fd + 4.2,
-                //     false => fd,
-                // };
                 let fd = match f1.is_pinky() || f2.is_pinky() {
                     true => fd + 0.6,
                     false => fd,
                 };
 
-                // let td = dist(k1, k2, &Finger::LP, &Finger::LP);
-                let fad = dist(k1, k2, f1, f2) - fd;
+                let stretch = dist(k1, k2, f1, f2) - fd;
 
-                if fad > 0.0 {
-                    println!("{c1}{c2}  {:.2}", fad);
-                }
-            });
+                (stretch > MIN_STRETCH).then_some(BigramPair {
+                    pair: PosPair(i1, i2),
+                    dist: (stretch * 100.0) as i64,
+                })
+            })
+            .collect::<Box<_>>();
 
-        // Self { per_key }
+        let per_key = (0..(fingers.len() as u16))
+            .map(|i| {
+                all.iter()
+                    .filter(|BigramPair { pair, .. }| pair.0 == i || pair.1 == i)
+                    .cloned()
+                    .collect()
+            })
+            .collect();
 
-        todo!();
+        Self { per_key, all }
     }
 }
 
 #[derive(Debug, Clone, Default, PartialEq)]
-pub struct StretchCache {}
+pub struct StretchCache {
+    pub total: i64,
+}
+
+#[inline]
+fn is_left(f: &Finger) -> bool {
+    (*f as u8) < 5
+}
 
 fn dist(k1: &PhysicalKey, k2: &PhysicalKey, f1: &Finger, f2: &Finger) -> f64 {
     let flen = |f: &Finger| match f {
@@ -259,3 +587,37 @@ fn thing() {
     print_key_info(&layout, 'b');
     print_key_info(&layout, '␣');
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scissors(fingers: [Finger; 2], rows: [u8; 2]) -> (usize, usize) {
+        let keyboard = rows
+            .iter()
+            .zip(0..)
+            .map(|(row, col)| format!("{col} {row} 1 1").parse::<PhysicalKey>().unwrap())
+            .collect::<Vec<_>>();
+        let indices = ScissorIndices::new(&fingers, &keyboard);
+
+        (indices.full.len(), indices.half.len())
+    }
+
+    #[test]
+    fn half_scissor_direction() {
+        use Finger::*;
+
+        assert_eq!(scissors([LP, LR], [0, 1]), (0, 1));
+        assert_eq!(scissors([LP, LR], [1, 0]), (0, 0));
+        assert_eq!(scissors([LR, LM], [0, 1]), (0, 1));
+        assert_eq!(scissors([LR, LM], [1, 0]), (0, 0));
+        assert_eq!(scissors([LM, LI], [1, 0]), (0, 1));
+        assert_eq!(scissors([LM, LI], [0, 1]), (0, 0));
+        assert_eq!(scissors([RI, RM], [0, 1]), (0, 1));
+        assert_eq!(scissors([RI, RM], [1, 0]), (0, 0));
+
+        assert_eq!(scissors([LR, LM], [0, 2]), (1, 0));
+        assert_eq!(scissors([LM, LI], [0, 2]), (1, 0));
+        assert_eq!(scissors([LR, LI], [0, 1]), (0, 0));
+    }
+}
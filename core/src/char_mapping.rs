@@ -2,16 +2,19 @@ use indexmap::IndexMap;
 
 use crate::{REPLACEMENT_CHAR, SHIFT_CHAR, SPACE_CHAR};
 
+/// Chars every mapping contains, whether they are in the corpus or not.
+pub const RESERVED_CHARS: [char; 3] = [REPLACEMENT_CHAR, SHIFT_CHAR, SPACE_CHAR];
+
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub struct CharMapping(IndexMap<char, u8>);
+pub struct CharMapping(IndexMap<char, u16>);
 
 impl CharMapping {
     pub fn new() -> Self {
         let mut map = Self::default();
 
-        map.push(REPLACEMENT_CHAR);
-        map.push(SHIFT_CHAR);
-        map.push(SPACE_CHAR);
+        for c in RESERVED_CHARS {
+            map.push(c);
+        }
 
         map
     }
@@ -20,26 +23,27 @@ impl CharMapping {
 impl CharMapping {
     pub fn push(&mut self, c: char) {
         if !self.0.contains_key(&c) {
-            self.0.insert(c, self.len() as u8);
+            let id = u16::try_from(self.len()).expect("corpora have at most MAX_CHARS chars");
+            self.0.insert(c, id);
         }
     }
 
-    pub fn remove(&mut self, c: char) -> Option<u8> {
+    pub fn remove(&mut self, c: char) -> Option<u16> {
         self.0.swap_remove(&c)
     }
 
-    pub fn pop(&mut self) -> Option<(char, u8)> {
+    pub fn pop(&mut self) -> Option<(char, u16)> {
         self.0.pop()
     }
 
-    pub fn get_u(&self, c: char) -> u8 {
+    pub fn get_u(&self, c: char) -> u16 {
         match self.0.get(&c) {
             Some(c) => *c,
             None => 0,
         }
     }
 
-    pub fn get_c(&self, u: u8) -> char {
+    pub fn get_c(&self, u: u16) -> char {
         match self.0.get_index(u as usize) {
             Some((c, _)) => *c,
             None => REPLACEMENT_CHAR,
@@ -55,11 +59,11 @@ impl CharMapping {
         self.len() == 0
     }
 
-    pub fn map_cs<'a>(&'a self, s: &'a str) -> impl Iterator<Item = u8> + 'a {
+    pub fn map_cs<'a>(&'a self, s: &'a str) -> impl Iterator<Item = u16> + 'a {
         s.chars().map(|c| self.get_u(c))
     }
 
-    pub fn map_us<'a>(&'a self, u: &'a [u8]) -> impl Iterator<Item = char> + 'a {
+    pub fn map_us<'a>(&'a self, u: &'a [u16]) -> impl Iterator<Item = char> + 'a {
         u.iter().map(|u| self.get_c(*u))
     }
 }
@@ -123,4 +127,20 @@ mod tests {
 
         assert_eq!(c, "this is epic�")
     }
+
+    #[test]
+    fn many_chars() {
+        let chars = ('\u{4e00}'..).take(1000).collect::<String>();
+        let mapping = chars.chars().collect::<CharMapping>();
+
+        assert_eq!(mapping.len(), 1003);
+        assert_eq!(mapping.get_u('\u{4e00}'), 3);
+        assert_eq!(mapping.get_u(char::from_u32(0x4e00 + 999).unwrap()), 1002);
+        assert_eq!(
+            mapping
+                .map_us(&mapping.map_cs(&chars).collect::<Vec<_>>())
+                .collect::<String>(),
+            chars
+        );
+    }
 }
@@ -0,0 +1,712 @@
+use libdof::prelude::Finger;
+use nanorand::{Rng as _, WyRand};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    cached_layout::KeyPositions,
+    char_mapping::CharMapping,
+    layout::{Layout, PosPair},
+    OxeylyzerError, Result, REPLACEMENT_CHAR,
+};
+
+/// Score added for every constraint a layout violates. Large enough that removing a violation is
+/// always an improvement, small enough that many of them don't overflow.
+pub const VIOLATION_PENALTY: i64 = -(1 << 56);
+
+/// Amount of violations after which [`VIOLATION_PENALTY`] isn't added anymore.
+const MAX_PENALIZED_VIOLATIONS: i64 = 64;
+
+/// Amount of times the chars of a random layout are moved around to satisfy the constraints before
+/// giving up, in which case the optimizer has to fix the remaining violations.
+const ARRANGE_PASSES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hand {
+    Left,
+    Right,
+}
+
+impl Hand {
+    pub const fn of(finger: Finger) -> Self {
+        match finger as u8 {
+            0..=4 => Self::Left,
+            _ => Self::Right,
+        }
+    }
+}
+
+/// Chars that can only be placed on some positions: those on `hand`, those typed with one of
+/// `fingers` and the ones in `positions`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Region {
+    pub chars: String,
+    pub hand: Option<Hand>,
+    #[serde(
+        serialize_with = "serialize_fingers",
+        deserialize_with = "deserialize_fingers"
+    )]
+    pub fingers: Vec<Finger>,
+    pub positions: Vec<usize>,
+}
+
+impl Region {
+    pub fn allows(&self, pos: usize, finger: Finger) -> bool {
+        self.positions.contains(&pos)
+            || self.fingers.contains(&finger)
+            || self.hand == Some(Hand::of(finger))
+    }
+}
+
+/// Restrictions on where chars can be placed when generating layouts. A char that is in multiple
+/// regions has to be in all of them. Pairs are written as a string of two chars, like `"ie"`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Constraints {
+    pub regions: Vec<Region>,
+    #[serde(
+        serialize_with = "serialize_pairs",
+        deserialize_with = "deserialize_pairs"
+    )]
+    pub same_finger: Vec<[char; 2]>,
+    #[serde(
+        serialize_with = "serialize_pairs",
+        deserialize_with = "deserialize_pairs"
+    )]
+    pub same_hand: Vec<[char; 2]>,
+    /// Positions whose key has to stay empty.
+    pub empty: Vec<usize>,
+}
+
+impl Constraints {
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+            && self.same_finger.is_empty()
+            && self.same_hand.is_empty()
+            && self.empty.is_empty()
+    }
+
+    /// Whether `c` can be placed on position `pos`, which is typed with `finger`.
+    pub fn allows(&self, c: char, pos: usize, finger: Finger) -> bool {
+        self.regions
+            .iter()
+            .filter(|r| r.chars.contains(c))
+            .all(|r| r.allows(pos, finger))
+    }
+
+    /// Positions that can't change: the pinned ones and the ones that have to stay empty.
+    pub fn fixed_positions(&self, pins: &[usize]) -> Vec<usize> {
+        let mut fixed = pins.to_vec();
+        fixed.extend(self.empty.iter().filter(|p| !pins.contains(p)));
+        fixed
+    }
+
+    /// Positions of constrained chars on `keys` that aren't in `mapping`.
+    pub fn unmapped_positions(&self, keys: &[char], mapping: &CharMapping) -> Vec<usize> {
+        let constrained = |c: char| {
+            self.regions.iter().any(|r| r.chars.contains(c))
+                || self
+                    .same_finger
+                    .iter()
+                    .chain(self.same_hand.iter())
+                    .any(|pair| pair.contains(&c))
+        };
+
+        keys.iter()
+            .enumerate()
+            .filter(|&(_, &c)| c != REPLACEMENT_CHAR && mapping.get_u(c) == 0 && constrained(c))
+            .map(|(p, _)| p)
+            .collect()
+    }
+
+    /// Amount of constraints `layout` violates, not counting empty positions.
+    pub fn violations(&self, layout: &Layout) -> usize {
+        let keys = &layout.keys;
+        let fingers = &layout.fingers;
+
+        let regions = keys
+            .iter()
+            .zip(fingers.iter())
+            .enumerate()
+            .filter(|&(p, (&c, &f))| !self.allows(c, p, f))
+            .count();
+
+        let pairs = self.pair_positions(keys).filter(|&(same_finger, p1, p2)| {
+            !pair_satisfied(same_finger, fingers[p1], fingers[p2])
+        });
+
+        regions + pairs.count()
+    }
+
+    /// Checks if the constraints can be satisfied on `layout` with `pins`.
+    pub fn validate(&self, layout: &Layout, pins: &[usize]) -> Result<()> {
+        let unsatisfiable = |msg: String| Err(OxeylyzerError::UnsatisfiableConstraints(msg));
+        let len = layout.keys.len();
+
+        if let Some(p) = self
+            .empty
+            .iter()
+            .chain(self.regions.iter().flat_map(|r| &r.positions))
+            .find(|&&p| p >= len)
+        {
+            return unsatisfiable(format!(
+                "position {p} doesn't exist, the layout has {len} keys"
+            ));
+        }
+
+        if let Some(&p) = self
+            .empty
+            .iter()
+            .find(|&&p| layout.keys[p] != REPLACEMENT_CHAR)
+        {
+            let c = layout.keys[p];
+            return unsatisfiable(format!(
+                "position {p} has to stay empty, but contains '{c}'"
+            ));
+        }
+
+        let fixed = self.fixed_positions(pins);
+        let free = (0..len).filter(|p| !fixed.contains(p)).collect::<Vec<_>>();
+        let allowed_positions = |c: char| {
+            free.iter()
+                .filter(|&&p| self.allows(c, p, layout.fingers[p]))
+                .count()
+        };
+
+        for (p, &c) in layout.keys.iter().enumerate() {
+            if fixed.contains(&p) {
+                if !self.allows(c, p, layout.fingers[p]) {
+                    return unsatisfiable(format!("'{c}' is pinned outside of its region"));
+                }
+            } else if allowed_positions(c) == 0 {
+                return unsatisfiable(format!("'{c}' has no free position in its region"));
+            }
+        }
+
+        for region in self.regions.iter() {
+            let chars = free
+                .iter()
+                .filter(|&&p| region.chars.contains(layout.keys[p]))
+                .count();
+            let positions = free
+                .iter()
+                .filter(|&&p| region.allows(p, layout.fingers[p]))
+                .count();
+
+            if chars > positions {
+                return unsatisfiable(format!(
+                    "{chars} chars of '{}' have to fit on {positions} positions",
+                    region.chars
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the chars of a layout ordered by `order` around until they satisfy the constraints,
+    /// or until it takes too long. Only positions that aren't `fixed` are changed.
+    pub(crate) fn arrange(
+        &self,
+        layout: &Layout,
+        order: &mut [usize],
+        fixed: &[usize],
+        rng: &mut WyRand,
+    ) {
+        let free = (0..order.len())
+            .filter(|p| !fixed.contains(p))
+            .collect::<Vec<_>>();
+        let fingers = &layout.fingers;
+        let char_at = |order: &[usize], p: usize| layout.keys[order[p]];
+
+        for _ in 0..ARRANGE_PASSES {
+            let mut changed = false;
+
+            for &p in free.iter() {
+                let c = char_at(order, p);
+                if self.allows(c, p, fingers[p]) {
+                    continue;
+                }
+
+                let fits = |q: usize| self.allows(c, q, fingers[q]);
+                let exchanges = free
+                    .iter()
+                    .copied()
+                    .filter(|&q| fits(q) && self.allows(char_at(order, q), p, fingers[p]))
+                    .collect::<Vec<_>>();
+                // Without a position to exchange with, moving the char into its region at least
+                // moves the problem elsewhere, which may be solved in a later pass.
+                let candidates = match exchanges.is_empty() {
+                    true => free.iter().copied().filter(|&q| fits(q)).collect(),
+                    false => exchanges,
+                };
+
+                if !candidates.is_empty() {
+                    let q = candidates[rng.generate_range(0..candidates.len())];
+                    order.swap(p, q);
+                    changed = true;
+                }
+            }
+
+            let keys = order.iter().map(|&i| layout.keys[i]).collect::<Vec<_>>();
+            let broken = self
+                .pair_positions(&keys)
+                .filter(|&(same_finger, p1, p2)| {
+                    !pair_satisfied(same_finger, fingers[p1], fingers[p2])
+                })
+                .collect::<Vec<_>>();
+
+            for (same_finger, p1, p2) in broken {
+                let exchanges = |order: &[usize], moving: usize| {
+                    let c = char_at(order, moving);
+
+                    free.iter()
+                        .copied()
+                        .filter(|&q| {
+                            self.allows(c, q, fingers[q])
+                                && self.allows(char_at(order, q), moving, fingers[moving])
+                        })
+                        .collect::<Vec<_>>()
+                };
+
+                // Move the second char of the pair next to the first, or the other way around if
+                // that isn't possible. If neither is, move the first char somewhere else in its
+                // region so a later pass can try again from there.
+                let moves = [(p1, p2), (p2, p1)]
+                    .into_iter()
+                    .filter(|(_, moving)| !fixed.contains(moving))
+                    .map(|(stay, moving)| {
+                        let candidates = exchanges(order, moving)
+                            .into_iter()
+                            .filter(|&q| {
+                                q != stay && pair_satisfied(same_finger, fingers[stay], fingers[q])
+                            })
+                            .collect::<Vec<_>>();
+
+                        (moving, candidates)
+                    })
+                    .find(|(_, candidates)| !candidates.is_empty())
+                    .or_else(|| {
+                        let moving = [p1, p2].into_iter().find(|p| !fixed.contains(p))?;
+                        let candidates = exchanges(order, moving)
+                            .into_iter()
+                            .filter(|&q| q != p1 && q != p2)
+                            .collect::<Vec<_>>();
+
+                        Some((moving, candidates))
+                    });
+
+                if let Some((moving, candidates)) = moves.filter(|(_, c)| !c.is_empty()) {
+                    let q = candidates[rng.generate_range(0..candidates.len())];
+                    order.swap(moving, q);
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+    }
+
+    /// Positions of both chars of every pair that is on `keys`, along with whether the pair has to
+    /// be on the same finger rather than the same hand.
+    fn pair_positions<'a>(
+        &'a self,
+        keys: &'a [char],
+    ) -> impl Iterator<Item = (bool, usize, usize)> + 'a {
+        let position = |c: char| keys.iter().position(|&k| k == c);
+
+        let same_finger = self.same_finger.iter().map(|pair| (true, pair));
+        let same_hand = self.same_hand.iter().map(|pair| (false, pair));
+
+        same_finger
+            .chain(same_hand)
+            .filter_map(move |(same_finger, &[c1, c2])| {
+                Some((same_finger, position(c1)?, position(c2)?))
+            })
+    }
+}
+
+fn pair_satisfied(same_finger: bool, f1: Finger, f2: Finger) -> bool {
+    match same_finger {
+        true => f1 == f2,
+        false => Hand::of(f1) == Hand::of(f2),
+    }
+}
+
+/// Constraints resolved to the chars of a [`CachedLayout`](crate::cached_layout::CachedLayout),
+/// so they can be checked quickly while optimizing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConstraintIndices {
+    /// For every position, whether each key can be placed on it. Empty if there are no regions.
+    pub allowed: Box<[Box<[bool]>]>,
+    pub same_finger: Box<[(u16, u16)]>,
+    pub same_hand: Box<[(u16, u16)]>,
+}
+
+impl ConstraintIndices {
+    /// Chars that aren't in `mapping` all share the same index, so they can't be told apart. Those
+    /// are left out, and should be kept in place using [`Constraints::unmapped_positions`] instead.
+    pub fn new(constraints: &Constraints, fingers: &[Finger], mapping: &CharMapping) -> Self {
+        let mapped = |c: char| Some(mapping.get_u(c)).filter(|&u| u != 0);
+
+        let allowed = match constraints.regions.is_empty() {
+            true => Box::default(),
+            false => fingers
+                .iter()
+                .enumerate()
+                .map(|(p, &f)| {
+                    (0..mapping.len() as u16)
+                        .map(|u| u == 0 || constraints.allows(mapping.get_c(u), p, f))
+                        .collect()
+                })
+                .collect(),
+        };
+
+        let pairs = |pairs: &[[char; 2]]| {
+            pairs
+                .iter()
+                .filter_map(|&[c1, c2]| Some((mapped(c1)?, mapped(c2)?)))
+                .collect()
+        };
+
+        Self {
+            allowed,
+            same_finger: pairs(&constraints.same_finger),
+            same_hand: pairs(&constraints.same_hand),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allowed.is_empty() && self.same_finger.is_empty() && self.same_hand.is_empty()
+    }
+
+    /// Whether key `u` can be placed on position `p`.
+    #[inline]
+    pub fn allows(&self, u: u16, p: usize) -> bool {
+        self.allowed
+            .get(p)
+            .is_none_or(|allowed| allowed.get(u as usize).is_none_or(|&a| a))
+    }
+
+    /// Amount of constraints `keys` violates.
+    pub fn violations(
+        &self,
+        keys: &[u16],
+        key_positions: &KeyPositions,
+        fingers: &[Finger],
+    ) -> i64 {
+        let regions = (0..keys.len())
+            .filter(|&p| !self.allows(keys[p], p))
+            .count() as i64;
+
+        let position = |u: u16| key_positions.get(u).first().map(|&p| p as usize);
+        let pairs = self
+            .pairs()
+            .filter(
+                |&(same_finger, (u1, u2))| match (position(u1), position(u2)) {
+                    (Some(p1), Some(p2)) => !pair_satisfied(same_finger, fingers[p1], fingers[p2]),
+                    _ => false,
+                },
+            )
+            .count() as i64;
+
+        regions + pairs
+    }
+
+    /// Change in the amount of violations caused by `swap`, which has already been applied to
+    /// `keys` and `key_positions`. Only the swapped positions and the pairs containing one of the
+    /// swapped keys are checked.
+    pub fn swap_diff(
+        &self,
+        keys: &[u16],
+        key_positions: &KeyPositions,
+        fingers: &[Finger],
+        PosPair(a, b): PosPair,
+    ) -> i64 {
+        let (a, b) = (a as usize, b as usize);
+        let (ka, kb) = (keys[a], keys[b]);
+
+        if a == b || ka == kb {
+            return 0;
+        }
+
+        let broken_regions =
+            |at_a: u16, at_b: u16| !self.allows(at_a, a) as i64 + !self.allows(at_b, b) as i64;
+        let regions = broken_regions(ka, kb) - broken_regions(kb, ka);
+
+        let position = |u: u16| key_positions.get(u).first().map(|&p| p as usize);
+        let unswapped = |p: usize| match p {
+            p if p == a => b,
+            p if p == b => a,
+            p => p,
+        };
+        let pairs = self
+            .pairs()
+            .filter(|(_, (u1, u2))| [ka, kb].contains(u1) || [ka, kb].contains(u2))
+            .filter_map(|(same_finger, (u1, u2))| Some((same_finger, position(u1)?, position(u2)?)))
+            .map(|(same_finger, p1, p2)| {
+                let broken = |p1: usize, p2: usize| {
+                    !pair_satisfied(same_finger, fingers[p1], fingers[p2]) as i64
+                };
+
+                broken(p1, p2) - broken(unswapped(p1), unswapped(p2))
+            })
+            .sum::<i64>();
+
+        regions + pairs
+    }
+
+    /// Whether swapping `p1` and `p2` keeps both of their keys in their regions. Each key is
+    /// checked on its own, whatever is on the other position.
+    pub fn can_swap(&self, keys: &[u16], p1: usize, p2: usize) -> bool {
+        self.allows(keys[p1], p2) && self.allows(keys[p2], p1)
+    }
+
+    /// Score penalty of `violations`.
+    pub fn penalty(&self, violations: i64) -> i64 {
+        if self.is_empty() {
+            return 0;
+        }
+
+        violations.min(MAX_PENALIZED_VIOLATIONS) * VIOLATION_PENALTY
+    }
+
+    /// Every pair, along with whether it has to be on the same finger rather than the same hand.
+    fn pairs(&self) -> impl Iterator<Item = (bool, (u16, u16))> + '_ {
+        let same_finger = self.same_finger.iter().map(|&pair| (true, pair));
+        let same_hand = self.same_hand.iter().map(|&pair| (false, pair));
+
+        same_finger.chain(same_hand)
+    }
+}
+
+fn serialize_fingers<S: Serializer>(
+    fingers: &[Finger],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(fingers.iter().map(|f| format!("{f:?}").to_lowercase()))
+}
+
+fn serialize_pairs<S: Serializer>(
+    pairs: &[[char; 2]],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(pairs.iter().map(|pair| pair.iter().collect::<String>()))
+}
+
+fn deserialize_fingers<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<Finger>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|name| {
+            Finger::FINGERS
+                .into_iter()
+                .find(|finger| format!("{finger:?}").eq_ignore_ascii_case(name))
+                .ok_or_else(|| D::Error::custom(format!("'{name}' is not a finger")))
+        })
+        .collect()
+}
+
+fn deserialize_pairs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<[char; 2]>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|pair| match pair.chars().collect::<Vec<_>>()[..] {
+            [c1, c2] => Ok([c1, c2]),
+            _ => Err(D::Error::custom(format!(
+                "'{pair}' is not a pair of two chars"
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(keys: &str) -> Layout {
+        let fingers = [
+            Finger::LP,
+            Finger::LI,
+            Finger::LI,
+            Finger::RI,
+            Finger::RI,
+            Finger::RP,
+        ];
+
+        Layout {
+            name: keys.into(),
+            keys: keys.chars().collect(),
+            fingers: fingers
+                .into_iter()
+                .cycle()
+                .take(keys.chars().count())
+                .collect(),
+            keyboard: Default::default(),
+            shape: vec![keys.chars().count()].into(),
+            board: None,
+            layers: Default::default(),
+            magic_rules: Default::default(),
+            constraints: Default::default(),
+        }
+    }
+
+    fn constraints() -> Constraints {
+        let s = r#"
+            same_finger = ["ab"]
+            same_hand = ["ef"]
+            empty = [5]
+
+            [[regions]]
+            chars = "ae"
+            hand = "left"
+
+            [[regions]]
+            chars = "e"
+            fingers = ["lp", "RI"]
+            positions = [2]
+        "#;
+
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let constraints = constraints();
+
+        assert_eq!(constraints.same_finger, [['a', 'b']]);
+        assert_eq!(constraints.regions[1].fingers, [Finger::LP, Finger::RI]);
+        assert!(toml::from_str::<Constraints>("same_hand = [\"abc\"]").is_err());
+
+        let s = toml::to_string(&constraints).unwrap();
+        assert_eq!(toml::from_str::<Constraints>(&s).unwrap(), constraints);
+        assert!(toml::from_str::<Constraints>("[[regions]]\nfingers = [\"xy\"]").is_err());
+    }
+
+    #[test]
+    fn violations() {
+        let constraints = constraints();
+
+        assert!(constraints.allows('e', 0, Finger::LP));
+        assert!(constraints.allows('e', 2, Finger::LI));
+        assert!(!constraints.allows('e', 1, Finger::LI));
+        assert!(!constraints.allows('e', 3, Finger::RI));
+
+        assert_eq!(constraints.violations(&layout("eabcf\u{fffd}")), 1);
+        assert_eq!(constraints.violations(&layout("cfabe\u{fffd}")), 3);
+    }
+
+    #[test]
+    fn swap_diff() {
+        let constraints = constraints();
+
+        for layout in [layout("cfabe\u{fffd}ghijk"), layout("eabcd\u{fffd}fghij")] {
+            let mut mapping = CharMapping::new();
+            layout.keys.iter().for_each(|&c| mapping.push(c));
+
+            let indices = ConstraintIndices::new(&constraints, &layout.fingers, &mapping);
+            let keys = layout
+                .keys
+                .iter()
+                .map(|&c| mapping.get_u(c))
+                .collect::<Vec<_>>();
+            let violations = indices.violations(&keys, &KeyPositions::new(&keys), &layout.fingers);
+            assert_eq!(violations, constraints.violations(&layout) as i64);
+
+            for a in 0..keys.len() {
+                for b in 0..keys.len() {
+                    let mut swapped = keys.clone();
+                    swapped.swap(a, b);
+                    let positions = KeyPositions::new(&swapped);
+
+                    let pair = PosPair(a as u16, b as u16);
+                    let diff = indices.swap_diff(&swapped, &positions, &layout.fingers, pair);
+                    let expected = indices.violations(&swapped, &positions, &layout.fingers);
+
+                    assert_eq!(violations + diff, expected, "{} {a} {b}", layout.name);
+
+                    if violations == 0 && !indices.can_swap(&keys, a, b) {
+                        assert!(expected > 0, "{} {a} {b}", layout.name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn possible_swaps() {
+        use crate::{analyze::Analyzer, data::Data, weights::dummy_weights};
+
+        let keys = "eabcd\u{fffd}fghij";
+        let data = keys
+            .chars()
+            .filter(|&c| c != REPLACEMENT_CHAR)
+            .collect::<Data>();
+        let analyzer = Analyzer::new(data, dummy_weights()).unwrap();
+
+        let unconstrained = Layout {
+            keyboard: (0..keys.chars().count())
+                .map(|x| format!("{x} 0 1 1").parse().unwrap())
+                .collect(),
+            constraints: Constraints {
+                empty: constraints().empty,
+                ..Default::default()
+            },
+            ..layout(keys)
+        };
+        let layout = Layout {
+            constraints: constraints(),
+            ..unconstrained.clone()
+        };
+
+        let all = analyzer.cached_layout(layout.clone(), &[]).possible_swaps;
+        let unconstrained = analyzer.cached_layout(unconstrained, &[]).possible_swaps;
+
+        assert_eq!(constraints().violations(&layout), 0);
+        assert!(all.len() < unconstrained.len());
+
+        for &PosPair(a, b) in unconstrained.iter().filter(|swap| !all.contains(swap)) {
+            let mut swapped = layout.clone();
+            swapped.keys.swap(a as usize, b as usize);
+
+            assert!(constraints().violations(&swapped) > 0, "{a} {b}");
+        }
+    }
+
+    #[test]
+    fn validate() {
+        let constraints = constraints();
+
+        assert!(constraints.validate(&layout("cfabe\u{fffd}"), &[]).is_ok());
+        assert!(constraints.validate(&layout("cfabe\u{fffd}"), &[3]).is_ok());
+        assert!(constraints
+            .validate(&layout("cfabe\u{fffd}"), &[0, 1])
+            .is_err());
+        assert!(constraints.validate(&layout("cfabed"), &[]).is_err());
+        assert!(constraints
+            .validate(&layout("cfeba\u{fffd}"), &[2, 4])
+            .is_err());
+        assert!(constraints.validate(&layout("cfe"), &[]).is_err());
+    }
+
+    #[test]
+    fn arrange() {
+        let constraints = constraints();
+        let layout = Layout {
+            constraints: constraints.clone(),
+            ..layout("cfabe\u{fffd}ghijk\u{fffd}")
+        };
+
+        for seed in 0..20 {
+            let random = layout.random_with_seed(&[], seed);
+
+            assert_eq!(constraints.violations(&random), 0, "{}", random.name);
+            assert_eq!(random.keys[5], REPLACEMENT_CHAR);
+        }
+    }
+}
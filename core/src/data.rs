@@ -4,8 +4,9 @@ use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, serde_conv};
 
 use fxhash::FxHashMap as HashMap;
+use std::hash::Hash;
 
-use crate::{corpus_cleaner::CorpusCleanerIterator, OxeylyzerError, REPLACEMENT_CHAR};
+use crate::{corpus_cleaner::CorpusCleanerIterator, OxeylyzerError, MAX_CHARS, REPLACEMENT_CHAR};
 
 #[cfg(not(target_arch = "wasm32"))]
 mod exclude_wasm {
@@ -57,7 +58,7 @@ serde_conv!(
 );
 
 #[serde_as]
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 #[serde(into = "SaveData")]
 pub struct Data {
     pub name: String,
@@ -96,6 +97,253 @@ impl Data {
     pub fn get_trigram(&self, trigram: [char; 3]) -> Option<&f64> {
         self.trigrams.get(&trigram)
     }
+
+    /// Replaces every char in `sequences` by the keys it is typed with, for example a symbol by
+    /// the key of its layer followed by the key it is on. Every n-gram is replaced by the n-grams
+    /// of its expansion that end on its last char, so chars, bigrams and trigrams are counted as if
+    /// the corpus itself was expanded. Skipgrams assume their middle char is a single key.
+    pub fn expand_sequences(&self, sequences: &HashMap<char, Vec<char>>) -> Self {
+        self.expand_sequences_with_totals(sequences, None)
+    }
+
+    /// Same as [`Self::expand_sequences`] for a part of a corpus, which keeps the totals of
+    /// `whole`, the expansion of the entire corpus.
+    pub(crate) fn expand_part_sequences(
+        &self,
+        sequences: &HashMap<char, Vec<char>>,
+        whole: &Self,
+    ) -> Self {
+        let totals = [
+            whole.char_total,
+            whole.bigram_total,
+            whole.skipgram_total,
+            whole.trigram_total,
+        ];
+
+        self.expand_sequences_with_totals(sequences, Some(totals))
+    }
+
+    fn expand_sequences_with_totals(
+        &self,
+        sequences: &HashMap<char, Vec<char>>,
+        totals: Option<[i64; 4]>,
+    ) -> Self {
+        fn normalize<K: Eq + Hash>(
+            map: HashMap<K, f64>,
+            total: Option<i64>,
+        ) -> (HashMap<K, f64>, i64) {
+            let (sum, total) = match total {
+                Some(total) => (total as f64, total),
+                None => {
+                    let sum = map.values().sum::<f64>();
+                    (sum, sum.round() as i64)
+                }
+            };
+
+            let map = map.into_iter().map(|(k, f)| (k, f * 100.0 / sum)).collect();
+
+            (map, total)
+        }
+
+        // returns the expansion of an ngram and the index at which its last char starts.
+        let expand = |ngram: &[char]| {
+            let mut res = Vec::with_capacity(ngram.len() * 2);
+            let mut last = 0;
+
+            for c in ngram {
+                last = res.len();
+                match sequences.get(c) {
+                    Some(seq) => res.extend_from_slice(seq),
+                    None => res.push(*c),
+                }
+            }
+
+            (res, last)
+        };
+
+        let mut chars = HashMap::default();
+        let mut bigrams = HashMap::default();
+        let mut skipgrams = HashMap::default();
+        let mut trigrams = HashMap::default();
+
+        for (&c, &f) in &self.chars {
+            let freq = f * self.char_total as f64 / 100.0;
+
+            for k in expand(&[c]).0 {
+                add(&mut chars, k, freq);
+            }
+        }
+
+        for (&bigram, &f) in &self.bigrams {
+            let freq = f * self.bigram_total as f64 / 100.0;
+            let (seq, last) = expand(&bigram);
+
+            for w in seq[(last - 1)..].windows(2) {
+                add(&mut bigrams, [w[0], w[1]], freq);
+            }
+
+            for w in seq[(last.max(2) - 2)..].windows(3) {
+                add(&mut skipgrams, [w[0], w[2]], freq);
+            }
+        }
+
+        for (&[c1, c2], &f) in &self.skipgrams {
+            let freq = f * self.skipgram_total as f64 / 100.0;
+            let (seq1, _) = expand(&[c1]);
+            let (seq2, _) = expand(&[c2]);
+
+            add(&mut skipgrams, [seq1[seq1.len() - 1], seq2[0]], freq);
+        }
+
+        for (&trigram, &f) in &self.trigrams {
+            let freq = f * self.trigram_total as f64 / 100.0;
+            let (seq, last) = expand(&trigram);
+
+            for w in seq[(last - 2)..].windows(3) {
+                add(&mut trigrams, [w[0], w[1], w[2]], freq);
+            }
+        }
+
+        let totals = totals.map(|t| t.map(Some)).unwrap_or_default();
+
+        let (chars, char_total) = normalize(chars, totals[0]);
+        let (bigrams, bigram_total) = normalize(bigrams, totals[1]);
+        let (skipgrams, skipgram_total) = normalize(skipgrams, totals[2]);
+        let (trigrams, trigram_total) = normalize(trigrams, totals[3]);
+
+        Self {
+            name: self.name.clone(),
+            chars,
+            bigrams,
+            skipgrams,
+            trigrams,
+            char_total,
+            bigram_total,
+            skipgram_total,
+            trigram_total,
+        }
+    }
+
+    /// Replaces every char by `key` when `output` gives it for the char before it, as if it was
+    /// typed with a key whose output depends on the previous key. The first char of an n-gram has
+    /// no previous char, so it is replaced in proportion to how often it is replaced in the
+    /// trigrams ending in that bigram, or for other n-grams, in the bigrams ending in that char.
+    pub fn rewrite_previous(&self, key: char, output: impl Fn(char) -> Option<char>) -> Self {
+        self.rewrite_previous_where(key, output, |_| true)
+    }
+
+    /// Same as [`Self::rewrite_previous`], but only keeps the bigrams, skipgrams and trigrams for
+    /// which `keep` returns true. These are rewritten exactly like they are when rewriting the
+    /// entire corpus.
+    pub(crate) fn rewrite_previous_where(
+        &self,
+        key: char,
+        output: impl Fn(char) -> Option<char>,
+        keep: impl Fn(&[char]) -> bool,
+    ) -> Self {
+        let replaced = |prev: char, c: char| output(prev) == Some(c);
+
+        let mut replaced_freq = HashMap::<char, f64>::default();
+
+        for (&[c1, c2], &f) in &self.bigrams {
+            if replaced(c1, c2) {
+                add(&mut replaced_freq, c2, f);
+            }
+        }
+
+        let ratio = |c: char| match (replaced_freq.get(&c), self.chars.get(&c)) {
+            (Some(r), Some(&f)) if f > 0.0 => {
+                let replaced = r * self.bigram_total as f64;
+                let total = f * self.char_total as f64;
+
+                (replaced / total).min(1.0)
+            }
+            _ => 0.0,
+        };
+
+        let mut bigram_context = HashMap::<[char; 2], (f64, f64)>::default();
+
+        for (&[c1, c2, c3], &f) in &self.trigrams {
+            if !keep(&[c2, c3]) {
+                continue;
+            }
+
+            let (total, replaced_freq) = bigram_context.entry([c2, c3]).or_default();
+
+            *total += f;
+            if replaced(c1, c2) {
+                *replaced_freq += f;
+            }
+        }
+
+        let bigram_ratio = |bigram: [char; 2]| match bigram_context.get(&bigram) {
+            Some(&(total, r)) if total > 0.0 => r / total,
+            _ => ratio(bigram[0]),
+        };
+
+        // splits the first char of an ngram into the part that is and isn't replaced.
+        let split_with = |c: char, r: f64| [(c, 1.0 - r), (key, r)];
+        let split = |c: char| split_with(c, ratio(c));
+
+        let mut chars = HashMap::default();
+        let mut bigrams = HashMap::default();
+        let mut skipgrams = HashMap::default();
+        let mut trigrams = HashMap::default();
+
+        for (&c, &f) in &self.chars {
+            for (c, r) in split(c) {
+                add(&mut chars, c, f * r);
+            }
+        }
+
+        for (&[c1, c2], &f) in self.bigrams.iter().filter(|(b, _)| keep(*b)) {
+            let r = bigram_ratio([c1, c2]);
+            let c2 = if replaced(c1, c2) { key } else { c2 };
+
+            for (c1, r) in split_with(c1, r) {
+                add(&mut bigrams, [c1, c2], f * r);
+            }
+        }
+
+        for (&[c1, c2], &f) in self.skipgrams.iter().filter(|(s, _)| keep(*s)) {
+            for (c1, r1) in split(c1) {
+                for (c2, r2) in split(c2) {
+                    add(&mut skipgrams, [c1, c2], f * r1 * r2);
+                }
+            }
+        }
+
+        for (&[c1, c2, c3], &f) in self.trigrams.iter().filter(|(t, _)| keep(*t)) {
+            let c3 = if replaced(c2, c3) { key } else { c3 };
+            let c2 = if replaced(c1, c2) { key } else { c2 };
+
+            for (c1, r) in split(c1) {
+                add(&mut trigrams, [c1, c2, c3], f * r);
+            }
+        }
+
+        fn remove_empty<K>(map: &mut HashMap<K, f64>) {
+            map.retain(|_, f| *f > 0.0);
+        }
+
+        remove_empty(&mut chars);
+        remove_empty(&mut bigrams);
+        remove_empty(&mut skipgrams);
+        remove_empty(&mut trigrams);
+
+        Self {
+            name: self.name.clone(),
+            chars,
+            bigrams,
+            skipgrams,
+            trigrams,
+            ..*self
+        }
+    }
+}
+
+fn add<K: Eq + Hash>(map: &mut HashMap<K, f64>, key: K, freq: f64) {
+    *map.entry(key).or_default() += freq;
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -103,7 +351,7 @@ impl Data {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, OxeylyzerError> {
         let content = std::fs::read_to_string(path)?;
         let data = serde_json::from_str::<Self>(&content)?;
-        Ok(data)
+        data.checked()
     }
 
     pub fn from_path<P: AsRef<Path>>(
@@ -127,7 +375,7 @@ impl Data {
 
             new.name = name.to_string();
 
-            Ok(new.into())
+            Data::from(new).checked()
         } else {
             Err(OxeylyzerError::NotAFile)
         }
@@ -138,7 +386,7 @@ impl Data {
         name: &str,
         cleaner: &CorpusCleaner,
     ) -> Result<Data, OxeylyzerError> {
-        IntermediateData::from_file(file, name, cleaner).map(Into::into)
+        Data::from(IntermediateData::from_file(file, name, cleaner)?).checked()
     }
 
     pub fn save<P: AsRef<Path>>(&self, folder: P) -> Result<(), OxeylyzerError> {
@@ -170,7 +418,17 @@ impl Data {
 impl Data {
     pub async fn load(url: &str) -> Result<Self, OxeylyzerError> {
         let data = Request::get(url).send().await?.json::<Self>().await?;
-        Ok(data)
+        data.checked()
+    }
+}
+
+impl Data {
+    /// Returns an error if the corpus has more chars than can be analyzed.
+    fn checked(self) -> Result<Self, OxeylyzerError> {
+        match self.chars.len() > MAX_CHARS {
+            true => Err(OxeylyzerError::TooManyChars(self.chars.len())),
+            false => Ok(self),
+        }
     }
 }
 
@@ -493,3 +751,87 @@ impl FromParallelIterator<Vec<char>> for Data {
             .into()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_freqs_eq<K: Eq + Hash + std::fmt::Debug>(
+        left: &HashMap<K, f64>,
+        right: &HashMap<K, f64>,
+    ) {
+        assert_eq!(left.len(), right.len());
+
+        for (k, f) in left {
+            let other = right.get(k).copied().unwrap_or_default();
+            assert!((f - other).abs() < 1e-9, "{k:?}: {f} != {other}");
+        }
+    }
+
+    #[test]
+    fn expand_sequences() {
+        let sequences = HashMap::from_iter([('A', vec!['^', 'a']), ('!', vec!['1', 'b'])]);
+
+        let data = Data::from("abAbaA!cbaab!Ab");
+        let expanded = Data::from("ab^aba^a1bcbaab1b^ab");
+
+        let res = data.expand_sequences(&sequences);
+
+        assert_eq!(res.char_total, expanded.char_total);
+        assert_eq!(res.bigram_total, expanded.bigram_total);
+        assert_eq!(res.trigram_total, expanded.trigram_total);
+
+        assert_freqs_eq(&res.chars, &expanded.chars);
+        assert_freqs_eq(&res.bigrams, &expanded.bigrams);
+        assert_freqs_eq(&res.trigrams, &expanded.trigrams);
+    }
+
+    #[test]
+    fn rewrite_previous() {
+        let data = Data::from("abbcdddeab");
+        let repeated = Data::from("ab@cd@@eab");
+
+        let res = data.rewrite_previous('@', Some);
+
+        assert_eq!(res.char_total, repeated.char_total);
+        assert_freqs_eq(&res.chars, &repeated.chars);
+
+        assert_freqs_eq(&res.bigrams, &repeated.bigrams);
+
+        let magic = data.rewrite_previous('*', |c| (c == 'a').then_some('b'));
+
+        assert_eq!(magic.get_char('*'), Some(&20.0));
+        assert_eq!(magic.get_bigram(['a', '*']), Some(&(100.0 / 9.0 * 2.0)));
+    }
+
+    #[test]
+    fn rewrite_previous_where() {
+        let data = Data::from("abbcabdcbacbbdabca");
+        let output = |c| match c {
+            'a' => Some('b'),
+            'c' => Some('a'),
+            _ => None,
+        };
+        let keep = |ngram: &[char]| ngram[0] == 'b' || ngram.contains(&'c');
+
+        let res = data.rewrite_previous('*', output);
+        let kept = data.rewrite_previous_where('*', output, keep);
+        let rest = data.rewrite_previous_where('*', output, |ngram| !keep(ngram));
+
+        fn sum<K: Eq + Hash + Copy>(
+            left: &HashMap<K, f64>,
+            right: &HashMap<K, f64>,
+        ) -> HashMap<K, f64> {
+            let mut res = left.clone();
+            for (&k, &f) in right {
+                add(&mut res, k, f);
+            }
+            res
+        }
+
+        assert_freqs_eq(&kept.chars, &res.chars);
+        assert_freqs_eq(&sum(&kept.bigrams, &rest.bigrams), &res.bigrams);
+        assert_freqs_eq(&sum(&kept.skipgrams, &rest.skipgrams), &res.skipgrams);
+        assert_freqs_eq(&sum(&kept.trigrams, &rest.trigrams), &res.trigrams);
+    }
+}
@@ -1,17 +1,68 @@
+use std::{
+    cmp::Reverse,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
 use crate::{
     analyze::Analyzer,
     cached_layout::CachedLayout,
     layout::{Layout, PosPair},
+    optimization::parse_params,
+    progress::RunProgress,
+    OxeylyzerError,
 };
 
+/// Parameters of a greedy depth search. `prune` skips branches the way
+/// [`Analyzer::best_swap_depth`] describes, and `parallel` searches the branches of every first
+/// swap in parallel, which is ignored on wasm.
+///
+/// Can be parsed from a comma separated list of parameters, like `prune=true,parallel=true`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DepthConfig {
+    pub prune: bool,
+    pub parallel: bool,
+}
+
+impl FromStr for DepthConfig {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for param in parse_params(s) {
+            let param = param?;
+
+            match param.key {
+                "prune" => config.prune = param.parse()?,
+                "parallel" => config.parallel = param.parse()?,
+                _ => return Err(param.invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for DepthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "prune={},parallel={}", self.prune, self.parallel)
+    }
+}
+
 impl Analyzer {
     pub fn always_better_swap(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
         let mut cache = self.cached_layout(layout, pins);
         let mut best_score = self.score_cache(&cache);
 
         let swaps = std::mem::take(&mut cache.possible_swaps);
+        let mut progress = RunProgress::new(&self.control);
 
-        loop {
+        while !progress.is_cancelled() {
             let mut best_loop_score = i64::MIN;
 
             for &pair in swaps.iter() {
@@ -31,6 +82,7 @@ impl Analyzer {
             }
 
             best_score = best_loop_score;
+            progress.report(best_score);
         }
 
         (cache.into(), best_score)
@@ -47,37 +99,46 @@ impl Analyzer {
         self.greedy_depth3_improve(layout, pins)
     }
 
-    pub fn optimize_depth4(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
+    /// Improves the layout with greedy searches of depth 1 through 4, using `config` for each
+    /// search deeper than one swap.
+    pub fn optimize_depth4(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        config: &DepthConfig,
+    ) -> (Layout, i64) {
         let (layout, _) = self.greedy_improve(layout, pins);
-        let (layout, _) = self.greedy_depth2_improve(layout, pins);
-        let (layout, _) = self.greedy_depth3_improve(layout, pins);
-        self.greedy_depth4_improve(layout, pins)
+        let (layout, _) = self.greedy_depth_improve(layout, pins, 2, config);
+        let (layout, _) = self.greedy_depth_improve(layout, pins, 3, config);
+        self.greedy_depth_improve(layout, pins, 4, config)
     }
 
     pub fn greedy_depth2_improve(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
-        let mut cache = self.cached_layout(layout, pins);
-        let mut best_score = self.score_cache(&cache);
-
-        while let Some((swaps, score)) = self.best_swap_depth2(&mut cache) {
-            if score <= best_score {
-                break;
-            }
-
-            best_score = score;
-            for &swap in swaps.iter() {
-                cache.swap(swap);
-                self.update_cache(&mut cache, swap);
-            }
-        }
+        self.greedy_depth_improve(layout, pins, 2, &DepthConfig::default())
+    }
 
-        (cache.into(), best_score)
+    pub fn greedy_depth3_improve(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
+        self.greedy_depth_improve(layout, pins, 3, &DepthConfig::default())
     }
 
     pub fn greedy_depth4_improve(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
+        self.greedy_depth_improve(layout, pins, 4, &DepthConfig::default())
+    }
+
+    /// Keeps applying the best sequence of at most `depth` swaps until none improves the layout,
+    /// searching sequences the way `config` describes.
+    pub fn greedy_depth_improve(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        depth: usize,
+        config: &DepthConfig,
+    ) -> (Layout, i64) {
         let mut cache = self.cached_layout(layout, pins);
         let mut best_score = self.score_cache(&cache);
+        let mut progress = RunProgress::new(&self.control);
 
-        while let Some((swaps, score)) = self.best_swap_depth4(&mut cache) {
+        while let Some((swaps, score)) = self.search_swap_depth(&mut cache, depth, config) {
             if score <= best_score {
                 break;
             }
@@ -87,180 +148,278 @@ impl Analyzer {
                 cache.swap(swap);
                 self.update_cache(&mut cache, swap);
             }
-        }
+            progress.report(best_score);
 
-        (cache.into(), best_score)
-    }
-
-    pub fn greedy_depth3_improve(&self, layout: Layout, pins: &[usize]) -> (Layout, i64) {
-        let mut cache = self.cached_layout(layout, pins);
-        let mut best_score = self.score_cache(&cache);
-
-        while let Some((swaps, score)) = self.best_swap_depth3(&mut cache) {
-            if score <= best_score {
+            if progress.is_cancelled() {
                 break;
             }
-
-            best_score = score;
-            for &swap in swaps.iter() {
-                cache.swap(swap);
-                self.update_cache(&mut cache, swap);
-            }
         }
 
         (cache.into(), best_score)
     }
 
-    pub fn best_swap_depth4(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
-        let depth1 = self.best_swap(cache).map(|(s, score)| ([s].into(), score));
-        let depth2 = self.best_swap_depth2(cache);
-        let depth3 = self.best_swap_depth3(cache);
+    /// Best sequence of at most `depth` swaps, searched sequentially or in parallel depending on
+    /// `config`.
+    fn search_swap_depth(
+        &self,
+        cache: &mut CachedLayout,
+        depth: usize,
+        config: &DepthConfig,
+    ) -> Option<(Box<[PosPair]>, i64)> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if config.parallel {
+            return self.par_best_swap_depth(cache, depth, config.prune);
+        }
 
-        let possible_swaps = cache.possible_swaps.clone();
+        self.best_swap_depth(cache, depth, config.prune)
+    }
 
-        let mut depth4_score = i64::MIN;
-        let mut depth4_swap = None;
+    pub fn best_swap_depth4(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
+        self.best_swap_depth(cache, 4, false)
+    }
 
-        for (&swap1, i) in possible_swaps.iter().zip(1..) {
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+    pub fn best_swap_depth3(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
+        self.best_swap_depth(cache, 3, false)
+    }
 
-            for (&swap2, j) in possible_swaps.iter().zip(1..).skip(i) {
-                cache.swap(swap2);
-                self.update_cache(cache, swap2);
+    pub fn best_swap_depth2(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
+        self.best_swap_depth(cache, 2, false)
+    }
 
-                for (&swap3, k) in possible_swaps.iter().zip(1..).skip(j) {
-                    cache.swap(swap3);
-                    self.update_cache(cache, swap3);
+    /// Best sequence of at most `depth` swaps, along with the score after applying it.
+    ///
+    /// Swaps that don't share a position can be applied in any order, so only one order of them
+    /// is searched. `prune` is a heuristic that skips branches in which gaining the largest score
+    /// change of a single swap on the starting layout with every remaining swap wouldn't beat the
+    /// best sequence so far. Swaps can gain more than that after other swaps, so a pruned search
+    /// is faster but can miss the best sequence.
+    pub fn best_swap_depth(
+        &self,
+        cache: &mut CachedLayout,
+        depth: usize,
+        prune: bool,
+    ) -> Option<(Box<[PosPair]>, i64)> {
+        if depth == 0 {
+            return None;
+        }
 
-                    for &swap4 in possible_swaps.iter().skip(k) {
-                        cache.swap(swap4);
-                        let current_score = self.score_cached_swap(cache, swap4);
-                        cache.swap(swap4);
+        let swaps = std::mem::take(&mut cache.possible_swaps);
+        let (roots, max_gain) = self.depth_roots(cache, &swaps, prune);
+        let best_score = AtomicI64::new(roots.first().map_or(i64::MIN, |&(_, s)| s));
 
-                        if current_score > depth4_score {
-                            depth4_score = current_score;
-                            depth4_swap = Some([swap1, swap2, swap3, swap4]);
-                        }
-                    }
+        let best = roots
+            .iter()
+            .map(|&root| self.search_root(cache, &swaps, root, depth, max_gain, &best_score))
+            .max_by(compare_sequences);
 
-                    cache.swap(swap3);
-                    self.update_cache(cache, swap3);
-                }
+        let res = best.map(|(score, sequence)| (sequence_swaps(&swaps, &sequence), score));
+        cache.possible_swaps = swaps;
 
-                cache.swap(swap2);
-                self.update_cache(cache, swap2);
-            }
+        res
+    }
 
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+    /// Same as [`Self::best_swap_depth`], but searches the branches of every first swap in
+    /// parallel.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn par_best_swap_depth(
+        &self,
+        cache: &CachedLayout,
+        depth: usize,
+        prune: bool,
+    ) -> Option<(Box<[PosPair]>, i64)> {
+        if depth == 0 {
+            return None;
         }
 
-        let depth4 = depth4_swap.map(|s| (s.into(), depth4_score));
-
-        [depth1, depth2, depth3, depth4]
-            .into_iter()
-            .flatten()
-            .max_by(|(_, s1), (_, s2)| s1.cmp(s2))
+        let mut cache = cache.clone();
+        let swaps = std::mem::take(&mut cache.possible_swaps);
+        let (roots, max_gain) = self.depth_roots(&mut cache, &swaps, prune);
+        let best_score = AtomicI64::new(roots.first().map_or(i64::MIN, |&(_, s)| s));
+
+        let best = roots
+            .par_iter()
+            .map_init(
+                || cache.clone(),
+                |cache, &root| self.search_root(cache, &swaps, root, depth, max_gain, &best_score),
+            )
+            .max_by(compare_sequences);
+
+        best.map(|(score, sequence)| (sequence_swaps(&swaps, &sequence), score))
     }
 
-    pub fn best_swap_depth3(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
-        let depth1 = self.best_swap(cache).map(|(s, score)| ([s].into(), score));
-        let depth2 = self.best_swap_depth2(cache);
-
-        let possible_swaps = cache.possible_swaps.clone();
-
-        let mut depth3_score = i64::MIN;
-        let mut depth3_swap = None;
+    /// Scores every swap, returning them from best to worst along with the largest score change
+    /// any of them causes if branches should be pruned.
+    fn depth_roots(
+        &self,
+        cache: &mut CachedLayout,
+        swaps: &[PosPair],
+        prune: bool,
+    ) -> (Vec<(usize, i64)>, Option<i64>) {
+        let score = self.score_cache(cache);
+
+        let mut roots = swaps
+            .iter()
+            .enumerate()
+            .map(|(i, &swap)| {
+                cache.swap(swap);
+                let new_score = self.score_cached_swap(cache, swap);
+                cache.swap(swap);
+                (i, new_score)
+            })
+            .collect::<Vec<_>>();
 
-        for (&swap1, i) in possible_swaps.iter().zip(1..) {
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+        roots.sort_by_key(|&(i, s)| (Reverse(s), i));
 
-            for (&swap2, j) in possible_swaps.iter().zip(1..).skip(i) {
-                cache.swap(swap2);
-                self.update_cache(cache, swap2);
+        let max_gain = prune.then(|| {
+            roots
+                .iter()
+                .map(|&(_, s)| s.abs_diff(score).min(i64::MAX as u64) as i64)
+                .max()
+                .unwrap_or_default()
+        });
 
-                for &swap3 in possible_swaps.iter().skip(j) {
-                    cache.swap(swap3);
-                    let current_score = self.score_cached_swap(cache, swap3);
-                    cache.swap(swap3);
+        (roots, max_gain)
+    }
 
-                    if current_score > depth3_score {
-                        depth3_score = current_score;
-                        depth3_swap = Some([swap1, swap2, swap3]);
-                    }
-                }
+    /// Best sequence starting with the swap at index `root`.
+    fn search_root(
+        &self,
+        cache: &mut CachedLayout,
+        swaps: &[PosPair],
+        (root, root_score): (usize, i64),
+        depth: usize,
+        max_gain: Option<i64>,
+        best_score: &AtomicI64,
+    ) -> (i64, Vec<usize>) {
+        let mut best = (root_score, vec![root]);
+
+        if can_improve(root_score, depth - 1, max_gain, best_score) {
+            let swap = swaps[root];
+            let mut sequence = vec![root];
+
+            cache.swap(swap);
+            self.update_cache(cache, swap);
+
+            self.search_depth(
+                cache,
+                swaps,
+                &mut sequence,
+                depth - 1,
+                max_gain,
+                best_score,
+                &mut best,
+            );
+
+            cache.swap(swap);
+            self.update_cache(cache, swap);
+        }
 
-                cache.swap(swap2);
-                self.update_cache(cache, swap2);
-            }
+        best
+    }
 
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+    #[allow(clippy::too_many_arguments)]
+    fn search_depth(
+        &self,
+        cache: &mut CachedLayout,
+        swaps: &[PosPair],
+        sequence: &mut Vec<usize>,
+        depth: usize,
+        max_gain: Option<i64>,
+        best_score: &AtomicI64,
+        best: &mut (i64, Vec<usize>),
+    ) {
+        if depth == 0 || self.control.is_cancelled() {
+            return;
         }
 
-        let depth3 = depth3_swap.map(|s| (s.into(), depth3_score));
+        for (i, &swap) in swaps.iter().enumerate() {
+            if !is_canonical(swaps, sequence, i) {
+                continue;
+            }
 
-        [depth1, depth2, depth3]
-            .into_iter()
-            .flatten()
-            .max_by(|(_, s1), (_, s2)| s1.cmp(s2))
-    }
+            cache.swap(swap);
+            let score = self.score_cached_swap(cache, swap);
+            sequence.push(i);
 
-    pub fn best_swap_depth2(&self, cache: &mut CachedLayout) -> Option<(Box<[PosPair]>, i64)> {
-        let depth1 = self.best_swap(cache).map(|(s, score)| ([s].into(), score));
+            if score > best.0 {
+                *best = (score, sequence.clone());
+                best_score.fetch_max(score, Ordering::Relaxed);
+            }
 
-        let possible_swaps = cache.possible_swaps.clone();
+            if can_improve(score, depth - 1, max_gain, best_score) {
+                self.update_cache(cache, swap);
+                self.search_depth(
+                    cache,
+                    swaps,
+                    sequence,
+                    depth - 1,
+                    max_gain,
+                    best_score,
+                    best,
+                );
+                cache.swap(swap);
+                self.update_cache(cache, swap);
+            } else {
+                cache.swap(swap);
+            }
 
-        let mut depth2_score = i64::MIN;
-        let mut depth2_swap = None;
+            sequence.pop();
+        }
+    }
+}
 
-        for (&swap1, i) in possible_swaps.iter().zip(1usize..) {
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+/// Whether `remaining` more swaps, each gaining at most `max_gain`, could beat `best_score`. Every
+/// branch is searched when `max_gain` is `None`.
+fn can_improve(
+    score: i64,
+    remaining: usize,
+    max_gain: Option<i64>,
+    best_score: &AtomicI64,
+) -> bool {
+    remaining > 0
+        && max_gain.is_none_or(|max_gain| {
+            score.saturating_add(max_gain.saturating_mul(remaining as i64))
+                > best_score.load(Ordering::Relaxed)
+        })
+}
 
-            for &swap2 in possible_swaps.iter().skip(i) {
-                cache.swap(swap2);
-                let current_score = self.score_cached_swap(cache, swap2);
-                cache.swap(swap2);
+/// Whether appending the swap at index `next` to `sequence` gives a sequence that isn't just a
+/// reordering of another searched sequence. Swaps that don't share a position can be reordered,
+/// so those have to be in increasing order, and a swap directly undoing itself is left out.
+fn is_canonical(swaps: &[PosPair], sequence: &[usize], next: usize) -> bool {
+    let PosPair(a, b) = swaps[next];
 
-                if current_score > depth2_score {
-                    depth2_score = current_score;
-                    depth2_swap = Some([swap1, swap2]);
-                }
-            }
+    for &prev in sequence.iter().rev() {
+        let PosPair(c, d) = swaps[prev];
 
-            cache.swap(swap1);
-            self.update_cache(cache, swap1);
+        if prev == next {
+            return false;
+        }
+        if a == c || a == d || b == c || b == d {
+            return true;
         }
+        if prev > next {
+            return false;
+        }
+    }
 
-        let depth2 = depth2_swap.map(|s| (s.into(), depth2_score));
+    true
+}
 
-        [depth1, depth2]
-            .into_iter()
-            .flatten()
-            .max_by(|(_, s1), (_, s2)| s1.cmp(s2))
-    }
+/// Orders sequences by score, preferring the one that comes first when they score the same.
+fn compare_sequences(s1: &(i64, Vec<usize>), s2: &(i64, Vec<usize>)) -> std::cmp::Ordering {
+    s1.0.cmp(&s2.0).then_with(|| s2.1.cmp(&s1.1))
+}
+
+fn sequence_swaps(swaps: &[PosPair], sequence: &[usize]) -> Box<[PosPair]> {
+    sequence.iter().map(|&i| swaps[i]).collect()
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    fn analyzer_layout() -> (Analyzer, Layout) {
-        let data = crate::prelude::Data::load("../data/shai.json").expect("this should exist");
-
-        let weights = crate::weights::dummy_weights();
-
-        let analyzer = Analyzer::new(data, weights);
-
-        let layout = Layout::load("../layouts/rstn-oxey.dof")
-            .expect("this layout is valid and exists, soooo");
-
-        (analyzer, layout)
-    }
+    use crate::test_util::analyzer_layout;
 
     #[test]
     fn cache_intact() {
@@ -279,5 +438,86 @@ mod tests {
         analyzer.best_swap_depth3(&mut cache);
 
         assert_eq!(cache, reference);
+
+        let sequential = analyzer.best_swap_depth(&mut cache, 2, false);
+
+        assert_eq!(cache, reference);
+        assert_eq!(analyzer.par_best_swap_depth(&cache, 2, false), sequential);
+
+        analyzer.best_swap_depth(&mut cache, 2, true);
+
+        assert_eq!(cache, reference);
+    }
+
+    /// Best score of any sequence of at most `depth` swaps, trying every one of them except a
+    /// swap directly undoing the one before it.
+    fn brute_force_depth(
+        analyzer: &Analyzer,
+        cache: &mut CachedLayout,
+        depth: usize,
+        last: Option<PosPair>,
+    ) -> i64 {
+        let swaps = cache.possible_swaps.clone();
+        let mut best = i64::MIN;
+
+        for &swap in swaps.iter().filter(|&&swap| Some(swap) != last) {
+            cache.swap(swap);
+            analyzer.update_cache(cache, swap);
+
+            best = best.max(analyzer.score_cache(cache));
+            if depth > 1 {
+                best = best.max(brute_force_depth(analyzer, cache, depth - 1, Some(swap)));
+            }
+
+            cache.swap(swap);
+            analyzer.update_cache(cache, swap);
+        }
+
+        best
+    }
+
+    #[test]
+    fn best_swap_depth_brute_force() {
+        let (analyzer, layout) = analyzer_layout();
+
+        // every key except the first 8 is pinned, which keeps the brute force search small
+        let pins = (8..layout.keys.len()).collect::<Vec<_>>();
+        let mut cache = analyzer.cached_layout(layout, &pins);
+
+        for depth in [2, 3] {
+            let expected = brute_force_depth(&analyzer, &mut cache, depth, None);
+
+            let (_, score) = analyzer.best_swap_depth(&mut cache, depth, false).unwrap();
+            assert_eq!(score, expected);
+
+            let (_, score) = analyzer.par_best_swap_depth(&cache, depth, false).unwrap();
+            assert_eq!(score, expected);
+        }
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = "prune=true, parallel=true".parse::<DepthConfig>().unwrap();
+
+        assert!(config.prune && config.parallel);
+        assert_eq!(config.to_string().parse::<DepthConfig>().unwrap(), config);
+        assert_eq!("".parse::<DepthConfig>().unwrap(), DepthConfig::default());
+
+        assert!("prune=yes".parse::<DepthConfig>().is_err());
+        assert!("depth=3".parse::<DepthConfig>().is_err());
+    }
+
+    #[test]
+    fn canonical_sequences() {
+        let swaps = [PosPair(0, 1), PosPair(1, 2), PosPair(3, 4), PosPair(0, 4)];
+
+        assert!(is_canonical(&swaps, &[], 3));
+        assert!(is_canonical(&swaps, &[0], 2));
+        assert!(!is_canonical(&swaps, &[2], 0));
+        assert!(!is_canonical(&swaps, &[1], 1));
+        assert!(is_canonical(&swaps, &[1], 0));
+        assert!(!is_canonical(&swaps, &[0, 2], 1));
+        assert!(is_canonical(&swaps, &[2, 0], 1));
+        assert!(!is_canonical(&swaps, &[0, 2], 0));
     }
 }
@@ -0,0 +1,382 @@
+use std::{fmt, str::FromStr};
+
+use nanorand::{RandomGen, Rng, WyRand};
+
+use crate::{
+    analyze::Analyzer,
+    layout::{Layout, PosPair},
+    optimization::parse_params,
+    progress::RunProgress,
+    OxeylyzerError,
+};
+
+/// Amount of random individuals a parent is the best of.
+const TOURNAMENT_SIZE: usize = 3;
+
+/// How two parents are combined into a child. Both keep every key that is at the same position in
+/// both parents there, so pinned keys never move.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Crossover {
+    /// Partially mapped crossover: copies a random slice of the first parent, filling in the rest
+    /// from the second parent.
+    #[default]
+    Pmx,
+    /// Cycle crossover: every key is at the position it has in one of the parents.
+    Cycle,
+}
+
+impl Crossover {
+    /// Combines two orders of the keys of the same layout.
+    pub fn combine(&self, p1: &[usize], p2: &[usize], rng: &mut WyRand) -> Vec<usize> {
+        match self {
+            Self::Pmx => pmx(p1, p2, rng),
+            Self::Cycle => cycle_crossover(p1, p2),
+        }
+    }
+}
+
+impl FromStr for Crossover {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pmx" => Ok(Self::Pmx),
+            "cycle" | "cx" => Ok(Self::Cycle),
+            _ => Err(OxeylyzerError::InvalidOptimizationParameter(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for Crossover {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pmx => write!(f, "pmx"),
+            Self::Cycle => write!(f, "cycle"),
+        }
+    }
+}
+
+/// Parameters of a genetic algorithm run. Every generation keeps the `elite` best layouts and
+/// fills the rest of the population with children of parents picked by tournament selection.
+/// Children are mutated by a random swap as long as a random number is below `mutation`, after
+/// which they are improved with `greedy_improve` if `memetic` is set.
+///
+/// Can be parsed from a comma separated list of parameters, like
+/// `population=24,generations=40,crossover=cycle,mutation=0.3,memetic=false`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneticConfig {
+    pub population: usize,
+    pub generations: usize,
+    pub elite: usize,
+    pub mutation: f64,
+    pub crossover: Crossover,
+    pub memetic: bool,
+}
+
+impl Default for GeneticConfig {
+    fn default() -> Self {
+        Self {
+            population: 16,
+            generations: 20,
+            elite: 2,
+            mutation: 0.5,
+            crossover: Crossover::default(),
+            memetic: true,
+        }
+    }
+}
+
+impl FromStr for GeneticConfig {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for param in parse_params(s) {
+            let param = param?;
+
+            match param.key {
+                "population" | "p" => config.population = param.count()?.max(2),
+                "generations" | "g" => config.generations = param.count()?,
+                "elite" => config.elite = param.count()?,
+                "mutation" => {
+                    config.mutation = param
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|m| (0.0..1.0).contains(m))
+                        .ok_or_else(|| param.invalid())?
+                }
+                "crossover" => config.crossover = param.value.parse()?,
+                "memetic" => config.memetic = param.parse()?,
+                _ => return Err(param.invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for GeneticConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "population={},generations={},elite={},mutation={},crossover={},memetic={}",
+            self.population,
+            self.generations,
+            self.elite,
+            self.mutation,
+            self.crossover,
+            self.memetic
+        )
+    }
+}
+
+/// A layout of the population, along with the order of the keys of the starting layout it has.
+#[derive(Debug, Clone)]
+struct Individual {
+    layout: Layout,
+    order: Vec<usize>,
+    score: i64,
+}
+
+impl Analyzer {
+    pub fn genetic_improve(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        config: &GeneticConfig,
+        seed: u64,
+    ) -> (Layout, i64) {
+        let mut rng = WyRand::new_seed(seed);
+        let mut progress = RunProgress::new(&self.control);
+
+        let possible_swaps = self.cached_layout(layout.clone(), pins).possible_swaps;
+        if possible_swaps.is_empty() {
+            let score = self.score(&layout);
+            return (layout, score);
+        }
+
+        let mut population = Vec::with_capacity(config.population);
+        population.push(self.individual(&layout, layout.clone(), pins, config));
+
+        while population.len() < config.population && !progress.is_cancelled() {
+            let random = layout.random_with_seed(pins, rng.generate());
+            population.push(self.individual(&layout, random, pins, config));
+        }
+
+        for _ in 0..config.generations {
+            if progress.is_cancelled() {
+                break;
+            }
+
+            population.sort_by_key(|i| std::cmp::Reverse(i.score));
+
+            let mut next = population[..config.elite.min(population.len())].to_vec();
+
+            while next.len() < config.population && !progress.is_cancelled() {
+                let p1 = tournament(&population, &mut rng);
+                let p2 = tournament(&population, &mut rng);
+
+                let mut order = config.crossover.combine(&p1.order, &p2.order, &mut rng);
+                while f64::random(&mut rng) < config.mutation {
+                    let PosPair(a, b) = possible_swaps[rng.generate_range(0..possible_swaps.len())];
+                    order.swap(a as usize, b as usize);
+                }
+
+                next.push(self.individual(&layout, layout.with_order(&order), pins, config));
+            }
+
+            population = next;
+
+            let best = population.iter().map(|i| i.score).max().unwrap_or(i64::MIN);
+            progress.report(best);
+        }
+
+        let best = population
+            .into_iter()
+            .max_by_key(|i| i.score)
+            .expect("the population always contains the starting layout");
+
+        (best.layout, best.score)
+    }
+
+    /// Scores `layout`, improving it first if the run is memetic.
+    fn individual(
+        &self,
+        start: &Layout,
+        layout: Layout,
+        pins: &[usize],
+        config: &GeneticConfig,
+    ) -> Individual {
+        let (layout, score) = match config.memetic {
+            true => self.greedy_improve(layout, pins),
+            false => {
+                let score = self.score(&layout);
+                (layout, score)
+            }
+        };
+
+        Individual {
+            order: self.key_order(start, &layout),
+            layout,
+            score,
+        }
+    }
+
+    /// Order of the keys of `start` that gives `layout`, which has to be a permutation of `start`.
+    /// Keys are compared by what they are to the analyzer on every layer, so keys that are the same
+    /// to it are interchangeable.
+    fn key_order(&self, start: &Layout, layout: &Layout) -> Vec<usize> {
+        let column = |l: &Layout, p: usize| {
+            std::iter::once(self.data.mapping.get_u(l.keys[p]) as u32)
+                .chain(l.layers.iter().map(move |layer| layer.keys[p] as u32))
+                .collect::<Vec<_>>()
+        };
+
+        let mut used = vec![false; start.keys.len()];
+
+        (0..layout.keys.len())
+            .map(|p| {
+                let key = column(layout, p);
+                let i = (0..start.keys.len())
+                    .find(|&i| !used[i] && column(start, i) == key)
+                    .expect("layout is a permutation of the starting layout");
+
+                used[i] = true;
+                i
+            })
+            .collect()
+    }
+}
+
+fn tournament<'a>(population: &'a [Individual], rng: &mut WyRand) -> &'a Individual {
+    (0..TOURNAMENT_SIZE)
+        .map(|_| &population[rng.generate_range(0..population.len())])
+        .max_by_key(|i| i.score)
+        .expect("the tournament isn't empty")
+}
+
+/// Partially mapped crossover of two permutations.
+pub fn pmx(p1: &[usize], p2: &[usize], rng: &mut WyRand) -> Vec<usize> {
+    let len = p1.len();
+    let (mut start, mut end) = (rng.generate_range(0..=len), rng.generate_range(0..=len));
+    if start > end {
+        std::mem::swap(&mut start, &mut end);
+    }
+
+    let mut position_in_p2 = vec![0; len];
+    for (p, &v) in p2.iter().enumerate() {
+        position_in_p2[v] = p;
+    }
+
+    let mut child = vec![None; len];
+    for p in start..end {
+        child[p] = Some(p1[p]);
+    }
+
+    for (p, &v) in p2.iter().enumerate().take(end).skip(start) {
+        if p1[start..end].contains(&v) {
+            continue;
+        }
+
+        // follow the mapping of the copied slice until a position outside of it is found
+        let mut pos = p;
+        while (start..end).contains(&pos) {
+            pos = position_in_p2[p1[pos]];
+        }
+        child[pos] = Some(v);
+    }
+
+    child
+        .into_iter()
+        .zip(p2)
+        .map(|(c, &v)| c.unwrap_or(v))
+        .collect()
+}
+
+/// Cycle crossover of two permutations, taking cycles from both parents in turn.
+pub fn cycle_crossover(p1: &[usize], p2: &[usize]) -> Vec<usize> {
+    let len = p1.len();
+
+    let mut position_in_p1 = vec![0; len];
+    for (p, &v) in p1.iter().enumerate() {
+        position_in_p1[v] = p;
+    }
+
+    let mut child = vec![None; len];
+    let mut from_p1 = true;
+
+    for start in 0..len {
+        if child[start].is_some() {
+            continue;
+        }
+
+        let parent = if from_p1 { p1 } else { p2 };
+        let mut pos = start;
+        while child[pos].is_none() {
+            child[pos] = Some(parent[pos]);
+            pos = position_in_p1[p2[pos]];
+        }
+
+        from_p1 = !from_p1;
+    }
+
+    child.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_permutation(order: &[usize]) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        sorted.into_iter().eq(0..order.len())
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = "population=24, generations=40,crossover=cycle,memetic=false"
+            .parse::<GeneticConfig>()
+            .unwrap();
+
+        assert_eq!(config.population, 24);
+        assert_eq!(config.generations, 40);
+        assert_eq!(config.crossover, Crossover::Cycle);
+        assert!(!config.memetic);
+        assert_eq!(config.to_string().parse::<GeneticConfig>().unwrap(), config);
+
+        assert!("mutation=1.5".parse::<GeneticConfig>().is_err());
+        assert!("crossover=uniform".parse::<GeneticConfig>().is_err());
+        assert!("memetic=maybe".parse::<GeneticConfig>().is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn genetic_improve() {
+        let config = "population=4,generations=2"
+            .parse::<GeneticConfig>()
+            .unwrap();
+
+        crate::test_util::assert_optimizes(|analyzer, layout, pins| {
+            analyzer.genetic_improve(layout, pins, &config, 7)
+        });
+    }
+
+    #[test]
+    fn crossovers() {
+        let p1 = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let p2 = [0, 9, 3, 7, 8, 2, 6, 5, 1, 4];
+
+        assert_eq!(cycle_crossover(&p1, &p2), [0, 9, 2, 3, 8, 5, 6, 7, 1, 4]);
+
+        let mut rng = WyRand::new_seed(0);
+        for _ in 0..100 {
+            for child in [pmx(&p1, &p2, &mut rng), cycle_crossover(&p1, &p2)] {
+                assert!(is_permutation(&child), "{child:?}");
+                // keys that are at the same position in both parents stay there
+                assert_eq!((child[0], child[6]), (0, 6));
+            }
+        }
+    }
+}
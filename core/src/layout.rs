@@ -1,14 +1,18 @@
+use std::collections::BTreeMap;
+
+use fxhash::FxHashMap as HashMap;
 use libdof::prelude::{Dof, Finger, Keyboard, PhysicalKey, Shape};
-use nanorand::{tls_rng, Rng as _};
+use nanorand::{tls_rng, Rng as _, WyRand};
 
 use crate::{
-    cached_layout::CachedLayout, Result, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR, SPACE_CHAR,
+    cached_layout::CachedLayout, constraints::Constraints, OxeylyzerError, Result, MAGIC_KEY,
+    MAX_KEYS, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR, SPACE_CHAR,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub struct PosPair(pub u8, pub u8);
+pub struct PosPair(pub u16, pub u16);
 
-impl<U: Into<u8>> From<(U, U)> for PosPair {
+impl<U: Into<u16>> From<(U, U)> for PosPair {
     fn from((p1, p2): (U, U)) -> Self {
         Self(p1.into(), p2.into())
     }
@@ -52,14 +56,28 @@ pub struct Layout {
     pub fingers: Box<[Finger]>,
     pub keyboard: Box<[PhysicalKey]>,
     pub shape: Shape,
+    pub board: Option<String>,
+    pub layers: Box<[LayoutLayer]>,
+    /// Output of the magic key, by the char typed before it.
+    pub magic_rules: BTreeMap<char, char>,
+    /// Where chars can be placed when generating or optimizing the layout.
+    pub constraints: Constraints,
+}
+
+/// A layer other than the main layer. `key` is the char of the key on the main layer that
+/// switches to it, and `keys` has the same length as the main layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayoutLayer {
+    pub name: String,
+    pub key: char,
+    pub keys: Box<[char]>,
 }
 
 #[inline]
-fn shuffle_pins<T>(slice: &mut [T], pins: &[usize]) {
+fn shuffle_pins<T>(slice: &mut [T], pins: &[usize], rng: &mut WyRand) {
     let mapping = (0..slice.len())
         .filter(|x| !pins.contains(x))
         .collect::<Vec<_>>();
-    let mut rng = tls_rng();
 
     for (m, &swap1) in mapping.iter().enumerate() {
         let swap2 = rng.generate_range(m..mapping.len());
@@ -72,20 +90,42 @@ impl Layout {
     pub fn load<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
         let s = std::fs::read_to_string(path)?;
 
-        serde_json::from_str::<Dof>(&s)
-            .map(Into::into)
-            .map_err(Into::into)
+        Self::from_dof_str(&s)
     }
 
     #[cfg(target_arch = "wasm32")]
     pub async fn load(url: &str) -> Result<Self> {
-        let dof = gloo_net::http::Request::get(url)
+        let s = gloo_net::http::Request::get(url)
             .send()
             .await?
-            .json::<Dof>()
+            .text()
             .await?;
 
-        Ok(dof.into())
+        Self::from_dof_str(&s)
+    }
+
+    /// Parses the contents of a `.dof` file. Unlike converting from a `Dof`, this also keeps the
+    /// name of the board the layout is for, if it has one.
+    pub fn from_dof_str(s: &str) -> Result<Self> {
+        #[derive(serde::Deserialize)]
+        struct DofBoard {
+            #[serde(default)]
+            board: serde_json::Value,
+        }
+
+        let board = serde_json::from_str::<DofBoard>(s)?
+            .board
+            .as_str()
+            .map(str::to_lowercase);
+
+        let mut layout = Self::from(serde_json::from_str::<Dof>(s)?);
+        layout.board = board;
+
+        if layout.keys.len() > MAX_KEYS {
+            return Err(OxeylyzerError::TooManyKeys(layout.keys.len()));
+        }
+
+        Ok(layout)
     }
 
     pub fn random(&self) -> Self {
@@ -93,39 +133,128 @@ impl Layout {
     }
 
     pub fn random_with_pins(&self, pins: &[usize]) -> Self {
-        let shape = self.shape.clone();
-        let fingers = self.fingers.clone();
-        let keyboard = self.keyboard.clone();
+        self.random_with_seed(pins, tls_rng().generate())
+    }
+
+    /// Shuffles every key that isn't pinned or has to stay empty, placing chars according to the
+    /// constraints of the layout where possible. The same seed always gives the same layout.
+    pub fn random_with_seed(&self, pins: &[usize], seed: u64) -> Self {
+        let mut rng = WyRand::new_seed(seed);
+        let fixed = self.constraints.fixed_positions(pins);
+
+        let mut order = (0..self.keys.len()).collect::<Vec<_>>();
+        shuffle_pins(&mut order, &fixed, &mut rng);
+
+        if !self.constraints.is_empty() {
+            self.constraints.arrange(self, &mut order, &fixed, &mut rng);
+        }
+
+        let layout = self.with_order(&order);
 
-        let mut keys = self.keys.clone();
-        shuffle_pins(&mut keys, pins);
+        Self {
+            name: layout.keys.iter().collect(),
+            ..layout
+        }
+    }
+
+    /// Rearranges the keys of every layer, so that position `i` gets the key at `order[i]`.
+    pub(crate) fn with_order(&self, order: &[usize]) -> Self {
+        let keys = order.iter().map(|&i| self.keys[i]).collect::<Box<[_]>>();
+        let layers = self
+            .layers
+            .iter()
+            .map(|layer| LayoutLayer {
+                keys: order.iter().map(|&i| layer.keys[i]).collect(),
+                ..layer.clone()
+            })
+            .collect();
 
         Self {
-            name: keys.iter().collect(),
             keys,
-            fingers,
-            keyboard,
-            shape,
+            layers,
+            ..self.clone()
         }
     }
+
+    /// Maps every char that can only be typed on another layer to the keys it is typed with: the
+    /// key of its layer, followed by the key on the main layer at the same position.
+    pub fn key_sequences(&self) -> HashMap<char, Vec<char>> {
+        let mut res = HashMap::default();
+
+        for layer in self.layers.iter() {
+            for (&c, &main) in layer.keys.iter().zip(self.keys.iter()) {
+                if c == REPLACEMENT_CHAR || main == REPLACEMENT_CHAR || self.keys.contains(&c) {
+                    continue;
+                }
+
+                res.entry(c).or_insert_with(|| vec![layer.key, main]);
+            }
+        }
+
+        res
+    }
+
+    /// Hash of the keys, fingers, keyboard, layers and constraints, to check whether layouts were
+    /// generated from the same layout.
+    pub fn hash(&self) -> u64 {
+        let layout = format!(
+            "{:?}",
+            (
+                &self.keys,
+                &self.fingers,
+                &self.keyboard,
+                &self.layers,
+                &self.constraints
+            )
+        );
+
+        fxhash::hash64(&layout)
+    }
+}
+
+/// Layers are switched to by a key on the main layer, which is represented by a char. For shift
+/// this is `SHIFT_CHAR`, every other layer gets a circled number in order of appearance.
+fn layer_char(name: &str, layer_chars: &HashMap<&str, char>) -> char {
+    match name {
+        "shift" => SHIFT_CHAR,
+        name => layer_chars.get(name).copied().unwrap_or(REPLACEMENT_CHAR),
+    }
 }
 
 impl From<Dof> for Layout {
     fn from(dof: Dof) -> Self {
         use libdof::prelude::{Key, SpecialKey};
 
-        let keys = dof
-            .main_layer()
+        let layer_chars = dof
+            .layers()
             .keys()
-            .map(|k| match k {
-                Key::Char(c) => *c,
-                Key::Special(s) => match s {
-                    SpecialKey::Repeat => REPEAT_KEY,
-                    SpecialKey::Space => SPACE_CHAR,
-                    SpecialKey::Shift => SHIFT_CHAR,
-                    _ => REPLACEMENT_CHAR,
-                },
+            .map(String::as_str)
+            .filter(|&name| name != "main" && name != "shift")
+            .zip(('\u{2460}'..='\u{2473}').chain(std::iter::repeat(REPLACEMENT_CHAR)))
+            .collect::<HashMap<_, _>>();
+
+        let to_char = |k: &Key| match k {
+            Key::Char(c) => *c,
+            Key::Special(s) => match s {
+                SpecialKey::Repeat => REPEAT_KEY,
+                SpecialKey::Space => SPACE_CHAR,
+                SpecialKey::Shift => SHIFT_CHAR,
                 _ => REPLACEMENT_CHAR,
+            },
+            Key::Layer { name } => layer_char(name, &layer_chars),
+            _ => REPLACEMENT_CHAR,
+        };
+
+        let keys = dof.main_layer().keys().map(to_char).collect();
+
+        let layers = dof
+            .layers()
+            .iter()
+            .filter(|(name, _)| *name != "main")
+            .map(|(name, layer)| LayoutLayer {
+                name: name.clone(),
+                key: layer_char(name, &layer_chars),
+                keys: layer.keys().map(to_char).collect(),
             })
             .collect();
 
@@ -140,6 +269,10 @@ impl From<Dof> for Layout {
             fingers,
             keyboard,
             shape,
+            board: None,
+            layers,
+            magic_rules: BTreeMap::new(),
+            constraints: Constraints::default(),
         }
     }
 }
@@ -148,14 +281,14 @@ impl From<CachedLayout> for Layout {
     fn from(layout: CachedLayout) -> Self {
         Self {
             name: layout.name,
-            keys: layout
-                .keys
-                .iter()
-                .map(|&u| layout.char_mapping.get_c(u))
-                .collect(),
+            keys: layout.chars,
             fingers: layout.fingers,
             keyboard: layout.keyboard,
             shape: layout.shape,
+            board: layout.board,
+            layers: layout.layers,
+            magic_rules: layout.magic_rules,
+            constraints: layout.constraints,
         }
     }
 }
@@ -179,6 +312,16 @@ impl std::fmt::Display for Layout {
             writeln!(f)?;
         }
 
+        if !self.magic_rules.is_empty() {
+            let rules = self
+                .magic_rules
+                .iter()
+                .map(|(prev, out)| format!("{prev}{MAGIC_KEY} → {prev}{out}"))
+                .collect::<Vec<_>>();
+
+            writeln!(f, "magic: {}", rules.join(", "))?;
+        }
+
         Ok(())
     }
 }
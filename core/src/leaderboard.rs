@@ -0,0 +1,503 @@
+use std::{collections::BTreeMap, fmt, ops::Range, str::FromStr};
+
+use libdof::prelude::Finger;
+
+use crate::{layout::Layout, OxeylyzerError};
+
+/// A generated layout together with how it was generated.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderboardEntry {
+    pub layout: Layout,
+    pub score: i64,
+    /// Seed of the first run that found this layout.
+    pub seed: u64,
+    /// Amount of runs that found this layout, or a layout equivalent to it.
+    pub found: usize,
+}
+
+/// Keeps the best `size` layouts of many optimization runs, along with the score of every run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leaderboard {
+    size: usize,
+    entries: Vec<LeaderboardEntry>,
+    /// Canonical form of the layout of every entry.
+    canonical: Vec<Layout>,
+    equivalences: Equivalences,
+    scores: Vec<i64>,
+}
+
+impl Leaderboard {
+    pub fn new(size: usize) -> Self {
+        Self {
+            size,
+            entries: Vec::with_capacity(size + 1),
+            canonical: Vec::with_capacity(size + 1),
+            equivalences: Equivalences::default(),
+            scores: Vec::new(),
+        }
+    }
+
+    /// Counts layouts that are equivalent as the same layout, only keeping the best of them.
+    pub fn with_equivalences(mut self, equivalences: Equivalences) -> Self {
+        self.equivalences = equivalences;
+        self
+    }
+
+    pub fn equivalences(&self) -> Equivalences {
+        self.equivalences
+    }
+
+    /// Adds the result of a run. Layouts that are already on the leaderboard, or equivalent to a
+    /// layout on it, are counted as found again instead of being added twice.
+    pub fn insert(&mut self, layout: Layout, score: i64, seed: u64) {
+        self.scores.push(score);
+
+        let entry = LeaderboardEntry {
+            layout,
+            score,
+            seed,
+            found: 1,
+        };
+        self.insert_entry(entry);
+    }
+
+    /// Adds the entries and scores of earlier runs, like the ones saved in a checkpoint.
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = LeaderboardEntry>, scores: &[i64]) {
+        self.scores.extend_from_slice(scores);
+
+        for entry in entries {
+            self.insert_entry(entry);
+        }
+    }
+
+    fn insert_entry(&mut self, mut entry: LeaderboardEntry) {
+        let canonical = self.equivalences.canonical(&entry.layout);
+
+        if let Some(i) = self.entries.iter().zip(&self.canonical).position(|(e, c)| {
+            same_layout(c, &canonical) || self.equivalences.same_score(e.score, entry.score)
+        }) {
+            if self.entries[i].score >= entry.score {
+                self.entries[i].found += entry.found;
+                return;
+            }
+
+            // the better layout replaces the one that was found before
+            entry.found += self.entries.remove(i).found;
+            self.canonical.remove(i);
+        }
+
+        let i = self.entries.partition_point(|e| e.score >= entry.score);
+        if i < self.size {
+            self.entries.insert(i, entry);
+            self.entries.truncate(self.size);
+            self.canonical.insert(i, canonical);
+            self.canonical.truncate(self.size);
+        }
+    }
+
+    /// Entries from best to worst.
+    pub fn entries(&self) -> &[LeaderboardEntry] {
+        &self.entries
+    }
+
+    pub fn into_entries(self) -> Vec<LeaderboardEntry> {
+        self.entries
+    }
+
+    pub fn best(&self) -> Option<&LeaderboardEntry> {
+        self.entries.first()
+    }
+
+    /// Amount of runs that have been added.
+    pub fn runs(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Score of every run, in the order they were added.
+    pub fn scores(&self) -> &[i64] {
+        &self.scores
+    }
+
+    pub fn distribution(&self) -> Option<ScoreDistribution> {
+        ScoreDistribution::new(&self.scores)
+    }
+}
+
+pub(crate) fn same_layout(l1: &Layout, l2: &Layout) -> bool {
+    l1.keys == l2.keys && l1.layers == l2.layers && l1.magic_rules == l2.magic_rules
+}
+
+/// Which layouts are counted as the same layout, on top of layouts with the same keys. Can be
+/// parsed from a comma separated list like `mirror,columns,score=1000`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Equivalences {
+    /// Layouts that are mirror images of each other, if the fingering of the layout is symmetric.
+    pub mirror: bool,
+    /// Layouts that only differ in the order of columns typed by the same finger.
+    pub columns: bool,
+    /// Layouts with scores that differ by at most this much.
+    pub score: Option<u64>,
+}
+
+impl Equivalences {
+    /// Returns the same layout for every layout that is equivalent to `layout` by mirroring it or
+    /// reordering its columns.
+    pub fn canonical(&self, layout: &Layout) -> Layout {
+        let mut canonical = self.sorted_columns(layout);
+
+        if let Some(order) = self.mirror.then(|| mirrored_positions(layout)).flatten() {
+            let mirrored = self.sorted_columns(&permuted(layout, &order));
+
+            if contents(&mirrored) < contents(&canonical) {
+                canonical = mirrored;
+            }
+        }
+
+        canonical
+    }
+
+    fn same_score(&self, s1: i64, s2: i64) -> bool {
+        self.score.is_some_and(|epsilon| s1.abs_diff(s2) <= epsilon)
+    }
+
+    /// Sorts every group of columns that are typed by the same finger and span the same rows by
+    /// their keys.
+    fn sorted_columns(&self, layout: &Layout) -> Layout {
+        let groups = match self.columns.then(|| column_groups(layout)).flatten() {
+            Some(groups) => groups,
+            None => return layout.clone(),
+        };
+
+        let mut order = (0..layout.keys.len()).collect::<Vec<_>>();
+
+        for group in groups {
+            let mut sorted = group.clone();
+            sorted.sort_by_cached_key(|column| {
+                column
+                    .iter()
+                    .flat_map(|&p| {
+                        std::iter::once(layout.keys[p])
+                            .chain(layout.layers.iter().map(move |l| l.keys[p]))
+                    })
+                    .collect::<Vec<_>>()
+            });
+
+            for (slot, column) in group.iter().zip(&sorted) {
+                for (&to, &from) in slot.iter().zip(column) {
+                    order[to] = from;
+                }
+            }
+        }
+
+        permuted(layout, &order)
+    }
+}
+
+impl FromStr for Equivalences {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut equivalences = Self::default();
+
+        for e in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match e.split_once('=') {
+                None if e == "mirror" => equivalences.mirror = true,
+                None if e == "columns" => equivalences.columns = true,
+                Some(("score", epsilon)) => match epsilon.trim().parse::<f64>() {
+                    Ok(epsilon) if epsilon >= 0.0 && epsilon.is_finite() => {
+                        equivalences.score = Some(epsilon as u64)
+                    }
+                    _ => return Err(OxeylyzerError::InvalidEquivalence(e.into())),
+                },
+                _ => return Err(OxeylyzerError::InvalidEquivalence(e.into())),
+            }
+        }
+
+        Ok(equivalences)
+    }
+}
+
+impl fmt::Display for Equivalences {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names = Vec::new();
+
+        if self.mirror {
+            names.push("mirror".to_string());
+        }
+        if self.columns {
+            names.push("columns".to_string());
+        }
+        if let Some(epsilon) = self.score {
+            names.push(format!("score={epsilon}"));
+        }
+
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// Positions of every row of the layout, or `None` if its shape or fingering doesn't match its
+/// keys.
+fn rows(layout: &Layout) -> Option<Vec<Range<usize>>> {
+    let shape = layout.shape.inner();
+    let len = layout.keys.len();
+
+    if shape.iter().sum::<usize>() != len || layout.fingers.len() != len {
+        return None;
+    }
+
+    let mut start = 0;
+    let rows = shape
+        .iter()
+        .map(|&row_len| {
+            start += row_len;
+            start - row_len..start
+        })
+        .collect();
+
+    Some(rows)
+}
+
+/// For every position, the position on the other side of its row. `None` if those aren't typed by
+/// the mirrored finger, except for keys in the middle of a row which are their own mirror.
+fn mirrored_positions(layout: &Layout) -> Option<Vec<usize>> {
+    let mut order = Vec::with_capacity(layout.keys.len());
+
+    for row in rows(layout)? {
+        for p in row.clone() {
+            let q = row.start + row.end - 1 - p;
+            let mirrored = Finger::FINGERS[9 - layout.fingers[p] as usize];
+
+            if p != q && layout.fingers[q] != mirrored {
+                return None;
+            }
+            order.push(q);
+        }
+    }
+
+    Some(order)
+}
+
+/// Groups of columns that are typed by the same finger and span the same rows. Columns are the
+/// positions at the same index of their row, from top to bottom.
+fn column_groups(layout: &Layout) -> Option<Vec<Vec<Vec<usize>>>> {
+    let mut columns = BTreeMap::<(Finger, usize), (Vec<usize>, Vec<usize>)>::new();
+
+    for (r, row) in rows(layout)?.into_iter().enumerate() {
+        for p in row.clone() {
+            let (rows, positions) = columns
+                .entry((layout.fingers[p], p - row.start))
+                .or_default();
+            rows.push(r);
+            positions.push(p);
+        }
+    }
+
+    let mut groups = BTreeMap::<(Finger, Vec<usize>), Vec<Vec<usize>>>::new();
+    for ((finger, _), (rows, positions)) in columns {
+        groups.entry((finger, rows)).or_default().push(positions);
+    }
+
+    let groups = groups
+        .into_values()
+        .filter(|columns| columns.len() > 1)
+        .collect();
+
+    Some(groups)
+}
+
+/// `layout` with the key at `order[i]` at every position `i`, on every layer.
+fn permuted(layout: &Layout, order: &[usize]) -> Layout {
+    let permute = |keys: &[char]| order.iter().map(|&p| keys[p]).collect();
+
+    let mut permuted = layout.clone();
+    permuted.keys = permute(&layout.keys);
+    for (layer, original) in permuted.layers.iter_mut().zip(&layout.layers) {
+        layer.keys = permute(&original.keys);
+    }
+
+    permuted
+}
+
+fn contents(layout: &Layout) -> (&[char], Vec<&[char]>) {
+    let layers = layout.layers.iter().map(|l| &*l.keys).collect();
+
+    (&layout.keys, layers)
+}
+
+/// Distribution of the scores of many runs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScoreDistribution {
+    pub best: i64,
+    pub worst: i64,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+}
+
+impl ScoreDistribution {
+    pub fn new(scores: &[i64]) -> Option<Self> {
+        if scores.is_empty() {
+            return None;
+        }
+
+        let mut sorted = scores.to_vec();
+        sorted.sort_unstable();
+
+        let len = sorted.len();
+        let mean = sorted.iter().map(|&s| s as f64).sum::<f64>() / len as f64;
+        let median = match len % 2 {
+            0 => (sorted[len / 2 - 1] as f64 + sorted[len / 2] as f64) / 2.0,
+            _ => sorted[len / 2] as f64,
+        };
+        let variance = sorted
+            .iter()
+            .map(|&s| (s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / len as f64;
+
+        Some(Self {
+            best: sorted[len - 1],
+            worst: sorted[0],
+            mean,
+            median,
+            std_dev: variance.sqrt(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(keys: &str) -> Layout {
+        Layout {
+            name: keys.into(),
+            keys: keys.chars().collect(),
+            fingers: Default::default(),
+            keyboard: Default::default(),
+            shape: vec![keys.len()].into(),
+            board: None,
+            layers: Default::default(),
+            magic_rules: Default::default(),
+            constraints: Default::default(),
+        }
+    }
+
+    #[test]
+    fn insert() {
+        let mut leaderboard = Leaderboard::new(2);
+
+        leaderboard.insert(layout("abc"), 10, 0);
+        leaderboard.insert(layout("bca"), 30, 1);
+        leaderboard.insert(layout("cab"), 20, 2);
+        leaderboard.insert(layout("bca"), 30, 3);
+        leaderboard.insert(layout("acb"), 5, 4);
+
+        let entries = leaderboard
+            .entries()
+            .iter()
+            .map(|e| (e.score, e.seed, e.found))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, [(30, 1, 2), (20, 2, 1)]);
+        assert_eq!(leaderboard.runs(), 5);
+    }
+
+    fn split(keys: &str) -> Layout {
+        use Finger::*;
+
+        Layout {
+            fingers: [LI, LI, RI, RI, LI, LI, RI, RI].into(),
+            shape: vec![4, 4].into(),
+            ..layout(keys)
+        }
+    }
+
+    #[test]
+    fn parse_equivalences() {
+        let equivalences = "mirror, score=1e3".parse::<Equivalences>().unwrap();
+
+        assert!(equivalences.mirror && !equivalences.columns);
+        assert_eq!(equivalences.score, Some(1000));
+        assert_eq!(equivalences.to_string(), "mirror,score=1000");
+
+        assert!("columns,thumbs".parse::<Equivalences>().is_err());
+        assert!("score=-1".parse::<Equivalences>().is_err());
+    }
+
+    #[test]
+    fn canonical() {
+        let mirror = Equivalences {
+            mirror: true,
+            ..Default::default()
+        };
+        let columns = Equivalences {
+            columns: true,
+            ..Default::default()
+        };
+        let same = |e: &Equivalences, l1: &str, l2: &str| {
+            same_layout(&e.canonical(&split(l1)), &e.canonical(&split(l2)))
+        };
+
+        assert!(same(&mirror, "abcdefgh", "dcbahgfe"));
+        assert!(!same(&mirror, "abcdefgh", "bacdfegh"));
+        assert!(same(&columns, "abcdefgh", "bacdfegh"));
+        assert!(same(&columns, "abcdefgh", "badcfehg"));
+        // only swaps the top row
+        assert!(!same(&columns, "abcdefgh", "bacdefgh"));
+        assert!(!same(&columns, "abcdefgh", "dcbahgfe"));
+
+        let mut asymmetric = split("abcdefgh");
+        asymmetric.fingers[0] = Finger::LM;
+        assert_eq!(mirror.canonical(&asymmetric), asymmetric);
+    }
+
+    #[test]
+    fn insert_equivalent() {
+        let equivalences = "mirror,columns,score=1".parse().unwrap();
+        let mut leaderboard = Leaderboard::new(3).with_equivalences(equivalences);
+
+        leaderboard.insert(split("abcdefgh"), 10, 0);
+        leaderboard.insert(split("cdabghef"), 10, 1);
+        leaderboard.insert(split("abdcefgh"), 20, 2);
+        leaderboard.insert(split("acbdegfh"), 21, 3);
+        leaderboard.insert(split("adcbehgf"), 30, 4);
+
+        let entries = leaderboard
+            .entries()
+            .iter()
+            .map(|e| (e.score, e.seed, e.found))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, [(30, 4, 1), (21, 3, 2), (10, 0, 2)]);
+    }
+
+    #[test]
+    fn extend() {
+        let mut leaderboard = Leaderboard::new(2);
+        leaderboard.insert(layout("abc"), 10, 0);
+        leaderboard.insert(layout("bca"), 30, 1);
+        leaderboard.insert(layout("bca"), 30, 2);
+
+        let mut restored = Leaderboard::new(2);
+        restored.extend(leaderboard.entries().to_vec(), leaderboard.scores());
+        assert_eq!(restored, leaderboard);
+
+        restored.insert(layout("cab"), 20, 3);
+        leaderboard.insert(layout("cab"), 20, 3);
+        assert_eq!(restored, leaderboard);
+    }
+
+    #[test]
+    fn distribution() {
+        assert_eq!(ScoreDistribution::new(&[]), None);
+
+        let distribution = ScoreDistribution::new(&[4, -2, 8, 6]).unwrap();
+
+        assert_eq!(distribution.best, 8);
+        assert_eq!(distribution.worst, -2);
+        assert_eq!(distribution.mean, 4.0);
+        assert_eq!(distribution.median, 5.0);
+        assert_eq!(distribution.std_dev, 14f64.sqrt());
+    }
+}
@@ -5,13 +5,24 @@ pub mod analyze;
 pub mod analyzer_data;
 pub mod cached_layout;
 pub mod char_mapping;
+pub mod constraints;
 pub mod corpus_cleaner;
 pub mod data;
 pub mod depth_optimization;
+pub mod genetic_algorithm;
 pub mod layout;
+pub mod leaderboard;
+pub mod magic;
 pub mod optimization;
+pub mod parallel_tempering;
+pub mod pareto;
+pub mod progress;
 pub mod simulated_annealing;
 pub mod stats;
+pub mod tabu_search;
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(test)]
+mod test_util;
 pub mod trigrams;
 pub mod weights;
 
@@ -23,7 +34,7 @@ pub mod prelude {
         data::Data,
         layout::{Layout, PosPair},
         weights::{FingerWeights, Weights},
-        OxeylyzerError, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR,
+        OxeylyzerError, MAGIC_KEY, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR,
     };
 }
 
@@ -33,6 +44,13 @@ pub const REPLACEMENT_CHAR: char = char::REPLACEMENT_CHARACTER;
 pub const SPACE_CHAR: char = '␣';
 pub const SHIFT_CHAR: char = '⇑';
 pub const REPEAT_KEY: char = '@';
+pub const MAGIC_KEY: char = '★';
+
+/// Most chars a corpus can have. Bigrams are stored with an entry for every pair of chars, so
+/// this many chars already takes a few hundred megabytes.
+pub const MAX_CHARS: usize = 4096;
+/// Most keys a layout can have.
+pub const MAX_KEYS: usize = u16::MAX as usize;
 
 #[derive(Debug, Error)]
 pub enum OxeylyzerError {
@@ -40,6 +58,31 @@ pub enum OxeylyzerError {
     InvalidBigramLength(usize),
     #[error("Trigrams should contain 3 characters, trigram with length {0} encountered.")]
     InvalidTrigramLength(usize),
+    #[error("'{0}' is not a valid finger triple, expected something like 'LP LR LM'")]
+    InvalidFingerTriple(String),
+    #[error("'{0}' is not a valid optimization method")]
+    InvalidOptimizationMethod(String),
+    #[error("'{0}' is not a valid optimization parameter")]
+    InvalidOptimizationParameter(String),
+    #[error("'{0}' is not a valid objective, expected a stat like 'sfbs' or a trigram category")]
+    InvalidObjective(String),
+    #[error(
+        "'{0}' is not a valid equivalence, expected mirror, columns or something like 'score=1000'"
+    )]
+    InvalidEquivalence(String),
+    #[error("'{0}' is not a valid duration, expected something like '30s', '500ms' or '1m30s'")]
+    InvalidDuration(String),
+    #[error("Constraints can't be satisfied: {0}")]
+    UnsatisfiableConstraints(String),
+    #[error(
+        "Corpus has {0} chars, which is more than the {max} that are supported, as every pair of chars is stored",
+        max = MAX_CHARS
+    )]
+    TooManyChars(usize),
+    #[error("Layout has {0} keys, which is more than the {max} that are supported", max = MAX_KEYS)]
+    TooManyKeys(usize),
+    #[error("Effort map of board '{0}' has {1} keys, but the layout has {2}")]
+    EffortBoardLength(String, usize, usize),
     #[error("Failed to create a file chunker")]
     ChunkerInitError,
     #[error("Failed to create appropriate chunks")]
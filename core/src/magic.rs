@@ -0,0 +1,369 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use fxhash::FxHashMap as HashMap;
+use itertools::Itertools;
+
+use crate::{
+    analyze::Analyzer,
+    cached_layout::{BigramPair, CachedLayout},
+    data::Data,
+    layout::{Layout, PosPair},
+    optimization::OptimizationMethod,
+    Result, MAGIC_KEY, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR,
+};
+
+/// Amount of outputs that are tried for every char when searching for magic rules. These are the
+/// chars that most often follow it.
+const MAGIC_CANDIDATES: usize = 5;
+
+impl Analyzer {
+    /// Optimizes both the keys of `layout` and the rules of its magic key. Keys are optimized
+    /// with `method`, after which the rule of every char is set to the output that improves the
+    /// score the most. This repeats until changing rules doesn't improve the score anymore.
+    ///
+    /// Changing the rule of a char only changes the n-grams around it, so rules are compared by
+    /// scoring just those. Only the rules that are applied are scored on the entire corpus.
+    pub fn magic_improve(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        method: &OptimizationMethod,
+        seed: u64,
+    ) -> Result<(Layout, i64)> {
+        let analyzer = self.for_layout(&layout)?;
+        let (mut layout, mut score) = method.optimize(&analyzer, layout, pins, seed);
+
+        if !self.uses_key(&layout, MAGIC_KEY) {
+            return Ok((layout, score));
+        }
+
+        let candidates = self.magic_candidates(&layout);
+
+        let corpus = match self.uses_key(&layout, REPEAT_KEY) {
+            true => Cow::Owned(self.corpus.rewrite_previous(REPEAT_KEY, Some)),
+            false => Cow::Borrowed(&*self.corpus),
+        };
+
+        let mut analyzer = self.for_layout(&layout)?;
+
+        loop {
+            let mut improved = false;
+
+            for &(prev, ref outputs) in &candidates {
+                let current = layout.magic_rules.get(&prev).copied();
+
+                let best = {
+                    let part = self.magic_part(&corpus, &analyzer, &layout, prev, outputs);
+                    let current_part = part(&layout.magic_rules);
+
+                    std::iter::once(None)
+                        .chain(outputs.iter().copied().map(Some))
+                        .filter(|&output| output != current)
+                        .map(|output| {
+                            let mut rules = layout.magic_rules.clone();
+                            match output {
+                                Some(c) => rules.insert(prev, c),
+                                None => rules.remove(&prev),
+                            };
+
+                            let s = score + part(&rules) - current_part;
+
+                            (rules, s)
+                        })
+                        .max_by_key(|(_, s)| *s)
+                };
+
+                if let Some((rules, _)) = best.filter(|(_, s)| *s > score) {
+                    let l = Layout {
+                        magic_rules: rules,
+                        ..layout.clone()
+                    };
+                    let a = self.for_layout(&l)?;
+                    let s = a.score(&l);
+
+                    if s > score {
+                        layout = l;
+                        analyzer = a;
+                        score = s;
+                        improved = true;
+                    }
+                }
+            }
+
+            if !improved || self.control.is_cancelled() {
+                break;
+            }
+
+            (layout, score) = method.optimize(&analyzer, layout, pins, seed);
+            analyzer = self.for_layout(&layout)?;
+        }
+
+        Ok((layout, score))
+    }
+
+    /// Returns a function that scores `layout` with the given rules on just the n-grams that
+    /// change when the rule of `prev` is set to one of `outputs` or removed. `corpus` is the
+    /// corpus before its magic rules are applied, and `analyzer` scores the entire corpus.
+    fn magic_part<'a>(
+        &'a self,
+        corpus: &'a Data,
+        analyzer: &'a Analyzer,
+        layout: &Layout,
+        prev: char,
+        outputs: &[char],
+    ) -> impl Fn(&BTreeMap<char, char>) -> i64 + 'a {
+        let changed = outputs
+            .iter()
+            .copied()
+            .chain(layout.magic_rules.get(&prev).copied())
+            .collect::<Vec<_>>();
+
+        // n-grams only change if they start with a char of which the rule changes, or if they
+        // contain one of those chars after `prev`.
+        let keep = move |ngram: &[char]| match *ngram {
+            [c1, c2] => changed.contains(&c1) || changed.contains(&c2),
+            _ => {
+                changed.contains(&ngram[0])
+                    || ngram
+                        .windows(2)
+                        .any(|w| w[0] == prev && changed.contains(&w[1]))
+            }
+        };
+
+        let sequences = layout.key_sequences();
+        let expand = sequences.keys().any(|c| corpus.chars.contains_key(c));
+        let scorer = PartScorer::new(analyzer, layout);
+
+        move |rules| {
+            let part = corpus.rewrite_previous_where(MAGIC_KEY, |c| rules.get(&c).copied(), &keep);
+            let part = match expand {
+                true => part.expand_part_sequences(&sequences, &analyzer.corpus),
+                false => part,
+            };
+
+            scorer.score(&part)
+        }
+    }
+
+    /// Returns every magic rule of `layout` with the percentage of sfbs it removes, which is the
+    /// difference in sfbs between using every rule and using every rule except that one.
+    pub fn magic_rule_sfbs(&self, layout: &Layout) -> Result<Vec<(char, char, f64)>> {
+        let sfbs = self.for_layout(layout)?.stats(layout).sfbs;
+
+        let rules = layout
+            .magic_rules
+            .iter()
+            .map(|(&prev, &output)| {
+                let mut without = layout.clone();
+                without.magic_rules.remove(&prev);
+
+                let without_sfbs = self.for_layout(&without)?.stats(&without).sfbs;
+
+                Ok((prev, output, without_sfbs - sfbs))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(rules
+            .into_iter()
+            .sorted_by(|(_, _, f1), (_, _, f2)| f2.total_cmp(f1))
+            .collect())
+    }
+
+    /// For every char on `layout`, the chars that most often follow it.
+    fn magic_candidates(&self, layout: &Layout) -> Vec<(char, Vec<char>)> {
+        let is_candidate = |c: &char| {
+            ![MAGIC_KEY, REPEAT_KEY, REPLACEMENT_CHAR, SHIFT_CHAR].contains(c)
+                && self.corpus.chars.contains_key(c)
+        };
+
+        let chars = layout
+            .keys
+            .iter()
+            .copied()
+            .filter(is_candidate)
+            .collect::<Vec<_>>();
+
+        chars
+            .iter()
+            .map(|&prev| {
+                let outputs = chars
+                    .iter()
+                    .filter_map(|&c| {
+                        let freq = self.corpus.get_bigram([prev, c]).copied()?;
+                        (freq > 0.0).then_some((c, freq))
+                    })
+                    .sorted_by(|(_, f1), (_, f2)| f2.total_cmp(f1))
+                    .take(MAGIC_CANDIDATES)
+                    .map(|(c, _)| c)
+                    .collect();
+
+                (prev, outputs)
+            })
+            .collect()
+    }
+}
+
+/// Scores a part of a corpus on a layout the same way an analyzer scores its entire corpus, so
+/// parts with different magic rules can be compared without building an analyzer for each of
+/// them. The constraint penalty doesn't depend on the corpus, so it is left out.
+struct PartScorer<'a> {
+    analyzer: &'a Analyzer,
+    cache: CachedLayout,
+    positions: HashMap<char, Vec<usize>>,
+    /// Weight of a bigram typed on every pair of positions.
+    bigram_weights: Box<[i64]>,
+    /// Weight of a skipgram typed on every pair of positions.
+    skipgram_weights: Box<[i64]>,
+}
+
+impl<'a> PartScorer<'a> {
+    fn new(analyzer: &'a Analyzer, layout: &Layout) -> Self {
+        let cache = analyzer.cached_layout(layout.clone(), &[]);
+        let len = cache.keys.len();
+
+        let mut positions = HashMap::<char, Vec<usize>>::default();
+        for (p, &c) in cache.chars.iter().enumerate() {
+            positions.entry(c).or_default().push(p);
+        }
+
+        // every pair is counted in both directions, like the analyzer does
+        let pair_weights = |pairs: &[(&[BigramPair], i64)]| {
+            let mut res = vec![0; len * len];
+            for &(pairs, weight) in pairs {
+                for BigramPair {
+                    pair: PosPair(a, b),
+                    dist,
+                } in pairs
+                {
+                    let (a, b) = (*a as usize, *b as usize);
+                    res[a * len + b] += dist * weight;
+                    res[b * len + a] += dist * weight;
+                }
+            }
+            res.into_boxed_slice()
+        };
+
+        let weights = &analyzer.weights;
+        let sfbs = &cache.weighted_sfb_indices.all;
+        let bigram_weights = pair_weights(&[
+            (sfbs, weights.sfbs),
+            (&cache.stretch_indices.all, weights.lsbs),
+            (&cache.scissor_indices.full, weights.scissors),
+            (&cache.scissor_indices.half, weights.scissors),
+        ]);
+        let skipgram_weights = pair_weights(&[(sfbs, weights.sfs)]);
+
+        Self {
+            analyzer,
+            cache,
+            positions,
+            bigram_weights,
+            skipgram_weights,
+        }
+    }
+
+    fn positions(&self, c: &char) -> &[usize] {
+        self.positions.get(c).map_or(&[], Vec::as_slice)
+    }
+
+    fn pair_weight(&self, [c1, c2]: [char; 2], weights: &[i64]) -> i64 {
+        let len = self.cache.keys.len();
+
+        self.positions(&c1)
+            .iter()
+            .flat_map(|p1| {
+                self.positions(&c2)
+                    .iter()
+                    .map(move |p2| weights[p1 * len + p2])
+            })
+            .sum()
+    }
+
+    fn trigram_weight(&self, trigram: [char; 3]) -> i64 {
+        let fingers = |c| self.positions(c).iter().map(|&p| self.cache.fingers[p]);
+
+        fingers(&trigram[0])
+            .flat_map(|f1| fingers(&trigram[1]).map(move |f2| (f1, f2)))
+            .flat_map(|(f1, f2)| fingers(&trigram[2]).map(move |f3| [f1, f2, f3]))
+            .map(|fingers| self.analyzer.finger_trigram_weight(fingers))
+            .sum()
+    }
+
+    fn score(&self, part: &Data) -> i64 {
+        // frequencies are converted exactly like the analyzer converts them
+        let freq = |f: f64, total: i64| (f * (total as f64 / 100.0)) as i64;
+
+        let mut per_finger = [0; 10];
+        let mut effort = 0;
+
+        for (c, &f) in &part.chars {
+            let freq = freq(f, part.char_total);
+
+            for &p in self.positions(c) {
+                per_finger[self.cache.fingers[p] as usize] += freq;
+                effort += freq * self.cache.effort_map[p];
+            }
+        }
+
+        let bigrams = part
+            .bigrams
+            .iter()
+            .map(|(&b, &f)| freq(f, part.bigram_total) * self.pair_weight(b, &self.bigram_weights))
+            .sum::<i64>();
+
+        let skipgrams = part
+            .skipgrams
+            .iter()
+            .map(|(&s, &f)| {
+                freq(f, part.skipgram_total) * self.pair_weight(s, &self.skipgram_weights)
+            })
+            .sum::<i64>();
+
+        let trigrams = match self.analyzer.analyze_trigrams {
+            true => part
+                .trigrams
+                .iter()
+                .map(|(&t, &f)| freq(f, part.trigram_total) * self.trigram_weight(t))
+                .sum(),
+            false => 0,
+        };
+
+        bigrams
+            + skipgrams
+            + trigrams
+            + self.analyzer.weighted_finger_usage(&per_finger)
+            + effort * self.analyzer.weights.effort.weight
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn magic_part() {
+        let (analyzer, mut layout) = crate::test_util::analyzer_layout();
+        let q = layout.keys.iter().position(|&c| c == 'q').unwrap();
+        layout.keys[q] = MAGIC_KEY;
+
+        let with_rules = |rules: &[(char, char)]| Layout {
+            magic_rules: rules.iter().copied().collect(),
+            ..layout.clone()
+        };
+        let score = |l: &Layout| analyzer.for_layout(l).unwrap().score(l);
+
+        let base = with_rules(&[('e', 'e'), ('t', 'h')]);
+        let base_analyzer = analyzer.for_layout(&base).unwrap();
+        let part = analyzer.magic_part(&analyzer.corpus, &base_analyzer, &base, 't', &['e']);
+
+        // only the rule of `t` changes
+        for rules in [&[('e', 'e')][..], &[('e', 'e'), ('t', 'e')]] {
+            let l = with_rules(rules);
+            let diff = score(&l) - score(&base);
+            let part_diff = part(&l.magic_rules) - part(&base.magic_rules);
+
+            assert_eq!(diff, part_diff, "{rules:?}");
+        }
+    }
+}
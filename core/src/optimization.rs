@@ -1,20 +1,30 @@
-use crate::{analyze::Analyzer, layout::Layout};
+use std::{fmt, str::FromStr, time::Duration};
 
+use nanorand::{tls_rng, Rng as _};
+
+use crate::{
+    analyze::Analyzer, depth_optimization::DepthConfig, genetic_algorithm::GeneticConfig,
+    layout::Layout, parallel_tempering::TemperingConfig, simulated_annealing::AnnealingConfig,
+    tabu_search::TabuConfig, OxeylyzerError,
+};
+
+/// A way of optimizing a layout. Optimizers that make random choices make them using `seed`, so
+/// optimizing the same layout with the same seed always gives the same result.
 pub trait OptimizeLayout {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64);
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], seed: u64) -> (Layout, i64);
 }
 
 #[derive(Debug, Clone)]
 pub struct Greedy;
 
 #[derive(Debug, Clone)]
-pub struct GreedyDepth2;
+pub struct GreedyDepth2(pub DepthConfig);
 
 #[derive(Debug, Clone)]
-pub struct GreedyDepth3;
+pub struct GreedyDepth3(pub DepthConfig);
 
 #[derive(Debug, Clone)]
-pub struct GreedyDepth4;
+pub struct GreedyDepth4(pub DepthConfig);
 
 #[derive(Debug, Clone)]
 pub struct GreedyAlternative;
@@ -23,80 +33,436 @@ pub struct GreedyAlternative;
 pub struct GreedyAlternativeD3;
 
 #[derive(Debug, Clone)]
-pub struct SimulatedAnnealing;
+pub struct SimulatedAnnealing(pub AnnealingConfig);
+
+#[derive(Debug, Clone)]
+pub struct SimulatedAnnealingDepth2(pub AnnealingConfig);
+
+#[derive(Debug, Clone)]
+pub struct Genetic(pub GeneticConfig);
 
 #[derive(Debug, Clone)]
-pub struct SimulatedAnnealingDepth2;
+pub struct Tabu(pub TabuConfig);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Debug, Clone)]
+pub struct ParallelTempering(pub TemperingConfig);
+
+/// Every way a layout can be optimized. Can be parsed from its name, where greedy depth,
+/// annealing, genetic, tabu and tempering methods can be followed by parameters after a colon,
+/// like `annealing:iterations=500000,cooling=linear`. See [`DepthConfig`], [`AnnealingConfig`],
+/// [`GeneticConfig`], [`TabuConfig`] and [`TemperingConfig`] for all parameters.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub enum OptimizationMethod {
     Greedy,
-    GreedyDepth2,
-    GreedyDepth3,
-    GreedyDepth4,
+    GreedyDepth2(DepthConfig),
+    GreedyDepth3(DepthConfig),
+    GreedyDepth4(DepthConfig),
     GreedyAlternative,
+    #[default]
     GreedyAlternativeD3,
+    SimulatedAnnealing(AnnealingConfig),
+    SimulatedAnnealingDepth2(AnnealingConfig),
+    Genetic(GeneticConfig),
+    Tabu(TabuConfig),
+    ParallelTempering(TemperingConfig),
 }
 
 impl OptimizationMethod {
-    pub fn optimize(&self, a: &Analyzer, layout: Layout) -> (Layout, i64) {
+    /// Names of every method, which is what they are parsed from.
+    pub const NAMES: [&'static str; 11] = [
+        "greedy",
+        "greedy-d2",
+        "greedy-d3",
+        "greedy-d4",
+        "alternative",
+        "alternative-d3",
+        "annealing",
+        "annealing-d2",
+        "genetic",
+        "tabu",
+        "tempering",
+    ];
+
+    pub fn optimize(
+        &self,
+        a: &Analyzer,
+        layout: Layout,
+        pins: &[usize],
+        seed: u64,
+    ) -> (Layout, i64) {
         use OptimizationMethod as OM;
 
         match self {
-            OM::Greedy => Greedy::optimize(a, layout),
-            OM::GreedyDepth2 => GreedyDepth2::optimize(a, layout),
-            OM::GreedyDepth3 => GreedyDepth3::optimize(a, layout),
-            OM::GreedyDepth4 => GreedyDepth4::optimize(a, layout),
-            OM::GreedyAlternative => GreedyAlternative::optimize(a, layout),
-            OM::GreedyAlternativeD3 => GreedyAlternativeD3::optimize(a, layout),
+            OM::Greedy => Greedy.optimize(a, layout, pins, seed),
+            OM::GreedyDepth2(config) => GreedyDepth2(*config).optimize(a, layout, pins, seed),
+            OM::GreedyDepth3(config) => GreedyDepth3(*config).optimize(a, layout, pins, seed),
+            OM::GreedyDepth4(config) => GreedyDepth4(*config).optimize(a, layout, pins, seed),
+            OM::GreedyAlternative => GreedyAlternative.optimize(a, layout, pins, seed),
+            OM::GreedyAlternativeD3 => GreedyAlternativeD3.optimize(a, layout, pins, seed),
+            OM::SimulatedAnnealing(config) => {
+                SimulatedAnnealing(*config).optimize(a, layout, pins, seed)
+            }
+            OM::SimulatedAnnealingDepth2(config) => {
+                SimulatedAnnealingDepth2(*config).optimize(a, layout, pins, seed)
+            }
+            OM::Genetic(config) => Genetic(*config).optimize(a, layout, pins, seed),
+            OM::Tabu(config) => Tabu(*config).optimize(a, layout, pins, seed),
+            OM::ParallelTempering(config) => {
+                ParallelTempering(*config).optimize(a, layout, pins, seed)
+            }
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        use OptimizationMethod as OM;
+
+        let i = match self {
+            OM::Greedy => 0,
+            OM::GreedyDepth2(_) => 1,
+            OM::GreedyDepth3(_) => 2,
+            OM::GreedyDepth4(_) => 3,
+            OM::GreedyAlternative => 4,
+            OM::GreedyAlternativeD3 => 5,
+            OM::SimulatedAnnealing(_) => 6,
+            OM::SimulatedAnnealingDepth2(_) => 7,
+            OM::Genetic(_) => 8,
+            OM::Tabu(_) => 9,
+            OM::ParallelTempering(_) => 10,
+        };
+
+        Self::NAMES[i]
+    }
+}
+
+impl FromStr for OptimizationMethod {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use OptimizationMethod as OM;
+
+        let (name, params) = match s.split_once(':') {
+            Some((name, params)) => (name.trim(), Some(params)),
+            None => (s.trim(), None),
+        };
+
+        let method = match name {
+            "greedy" => OM::Greedy,
+            "greedy-d2" => OM::GreedyDepth2(params.unwrap_or("").parse()?),
+            "greedy-d3" => OM::GreedyDepth3(params.unwrap_or("").parse()?),
+            "greedy-d4" => OM::GreedyDepth4(params.unwrap_or("").parse()?),
+            "alternative" => OM::GreedyAlternative,
+            "alternative-d3" => OM::GreedyAlternativeD3,
+            "annealing" | "sa" => OM::SimulatedAnnealing(params.unwrap_or("").parse()?),
+            "annealing-d2" | "sa-d2" => OM::SimulatedAnnealingDepth2(params.unwrap_or("").parse()?),
+            "genetic" | "ga" => OM::Genetic(params.unwrap_or("").parse()?),
+            "tabu" => OM::Tabu(params.unwrap_or("").parse()?),
+            "tempering" | "pt" => OM::ParallelTempering(params.unwrap_or("").parse()?),
+            _ => return Err(OxeylyzerError::InvalidOptimizationMethod(name.into())),
+        };
+
+        let takes_params = matches!(
+            method,
+            OM::GreedyDepth2(_)
+                | OM::GreedyDepth3(_)
+                | OM::GreedyDepth4(_)
+                | OM::SimulatedAnnealing(_)
+                | OM::SimulatedAnnealingDepth2(_)
+                | OM::Genetic(_)
+                | OM::Tabu(_)
+                | OM::ParallelTempering(_)
+        );
+
+        match params {
+            Some(params) if !takes_params => {
+                Err(OxeylyzerError::InvalidOptimizationParameter(params.into()))
+            }
+            _ => Ok(method),
         }
     }
 }
 
+impl fmt::Display for OptimizationMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::GreedyDepth2(config)
+            | Self::GreedyDepth3(config)
+            | Self::GreedyDepth4(config) => {
+                write!(f, "{}:{config}", self.name())
+            }
+            Self::SimulatedAnnealing(config) | Self::SimulatedAnnealingDepth2(config) => {
+                write!(f, "{}:{config}", self.name())
+            }
+            Self::Genetic(config) => write!(f, "{}:{config}", self.name()),
+            Self::Tabu(config) => write!(f, "{}:{config}", self.name()),
+            Self::ParallelTempering(config) => write!(f, "{}:{config}", self.name()),
+            _ => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Returns a random seed to start a reproducible run with.
+pub fn random_seed() -> u64 {
+    tls_rng().generate()
+}
+
+/// Seed of the `run`th run of a batch started with `seed`. The first run uses `seed` itself, so
+/// any single run can be repeated by starting a batch of one with its seed.
+pub fn run_seed(seed: u64, run: usize) -> u64 {
+    seed.wrapping_add((run as u64).wrapping_mul(0x9e3779b97f4a7c15))
+}
+
+/// Parses a duration like `90s`, `500ms`, `2m`, `1h` or a combination like `1m30s`. A number
+/// without a unit is in seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, OxeylyzerError> {
+    let invalid = || OxeylyzerError::InvalidDuration(s.into());
+
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = s.parse::<f64>() {
+        return Duration::try_from_secs_f64(secs).map_err(|_| invalid());
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = s;
+
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let (number, tail) = rest.split_at(number_len);
+        let unit_len = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_len);
+
+        let number = number.parse::<f64>().map_err(|_| invalid())?;
+        let secs = match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return Err(invalid()),
+        };
+
+        total += Duration::try_from_secs_f64(secs).map_err(|_| invalid())?;
+        rest = tail;
+    }
+
+    Ok(total)
+}
+
+/// A `key=value` parameter of an optimization method.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Param<'a> {
+    pub key: &'a str,
+    pub value: &'a str,
+    param: &'a str,
+}
+
+impl Param<'_> {
+    pub fn invalid(&self) -> OxeylyzerError {
+        OxeylyzerError::InvalidOptimizationParameter(self.param.into())
+    }
+
+    pub fn parse<T: FromStr>(&self) -> Result<T, OxeylyzerError> {
+        self.value.parse().map_err(|_| self.invalid())
+    }
+
+    /// A count, which can have underscores like `100_000`.
+    pub fn count(&self) -> Result<usize, OxeylyzerError> {
+        self.value
+            .replace('_', "")
+            .parse()
+            .map_err(|_| self.invalid())
+    }
+
+    /// A number above zero.
+    pub fn positive(&self) -> Result<f64, OxeylyzerError> {
+        self.parse::<f64>()
+            .ok()
+            .filter(|t| *t > 0.0)
+            .ok_or_else(|| self.invalid())
+    }
+}
+
+/// Splits a comma separated list of parameters like `iterations=5000,tenure=7` into its keys and
+/// values.
+pub(crate) fn parse_params(s: &str) -> impl Iterator<Item = Result<Param<'_>, OxeylyzerError>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|param| match param.split_once('=') {
+            Some((key, value)) => Ok(Param {
+                key: key.trim(),
+                value: value.trim(),
+                param,
+            }),
+            None => Err(OxeylyzerError::InvalidOptimizationParameter(param.into())),
+        })
+}
+
+/// Formats a duration in a way [`parse_duration`] can parse.
+pub fn format_duration(d: Duration) -> String {
+    match d.subsec_millis() {
+        0 => format!("{}s", d.as_secs()),
+        _ => format!("{}ms", d.as_millis()),
+    }
+}
+
 impl OptimizeLayout for Greedy {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.greedy_improve(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.greedy_improve(layout, pins)
     }
 }
 
 impl OptimizeLayout for GreedyDepth2 {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.greedy_depth2_improve(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.greedy_depth_improve(layout, pins, 2, &self.0)
     }
 }
 
 impl OptimizeLayout for GreedyDepth3 {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.greedy_depth3_improve(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.greedy_depth_improve(layout, pins, 3, &self.0)
     }
 }
 
 impl OptimizeLayout for GreedyDepth4 {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.optimize_depth4(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.optimize_depth4(layout, pins, &self.0)
     }
 }
 
 impl OptimizeLayout for GreedyAlternative {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.always_better_swap(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.always_better_swap(layout, pins)
     }
 }
 
 impl OptimizeLayout for GreedyAlternativeD3 {
-    fn optimize(a: &Analyzer, layout: Layout) -> (Layout, i64) {
-        a.alternative_d3(layout, &[])
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.alternative_d3(layout, pins)
+    }
+}
+
+impl OptimizeLayout for SimulatedAnnealing {
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], seed: u64) -> (Layout, i64) {
+        a.annealing_improve(layout, pins, &self.0, seed)
+    }
+}
+
+impl OptimizeLayout for SimulatedAnnealingDepth2 {
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], seed: u64) -> (Layout, i64) {
+        a.annealing_depth2_improve(layout, pins, &self.0, seed)
+    }
+}
+
+impl OptimizeLayout for Genetic {
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], seed: u64) -> (Layout, i64) {
+        a.genetic_improve(layout, pins, &self.0, seed)
     }
 }
 
-// impl OptimizeLayout<3> for SimulatedAnnealing {
-//     fn optimize(a: &Analyzer, layout: Layout, [initial_temperature, cooling_rate, max_iterations]: [f64; 3]) -> (Layout, i64) {
-//         a.annealing_improve(layout, initial_temperature, cooling_rate, max_iterations as usize)
-//     }
-// }
+// tabu search is deterministic, so it has no use for the seed
+impl OptimizeLayout for Tabu {
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], _: u64) -> (Layout, i64) {
+        a.tabu_improve(layout, pins, &self.0)
+    }
+}
 
-// impl OptimizeLayout<3> for SimulatedAnnealingDepth2 {
-//     fn optimize(a: &Analyzer, layout: Layout, [initial_temperature, cooling_rate, max_iterations]: [f64; 3]) -> (Layout, i64) {
-//         a.annealing_depth2_improve(layout, initial_temperature, cooling_rate, max_iterations as usize)
-//     }
-// }
+impl OptimizeLayout for ParallelTempering {
+    fn optimize(&self, a: &Analyzer, layout: Layout, pins: &[usize], seed: u64) -> (Layout, i64) {
+        a.tempering_improve(layout, pins, &self.0, seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulated_annealing::AnnealingLimit;
+
+    #[test]
+    fn parse_methods() {
+        for name in OptimizationMethod::NAMES {
+            let method = name.parse::<OptimizationMethod>().unwrap();
+            assert_eq!(method.name(), name);
+            assert_eq!(
+                method.to_string().parse::<OptimizationMethod>().unwrap(),
+                method
+            );
+        }
+
+        let method = "annealing:iterations=5000"
+            .parse::<OptimizationMethod>()
+            .unwrap();
+        let OptimizationMethod::SimulatedAnnealing(config) = method else {
+            panic!("expected annealing, got {method:?}")
+        };
+        assert_eq!(config.limit, AnnealingLimit::Iterations(5000));
+
+        assert!("greedy:iterations=5".parse::<OptimizationMethod>().is_err());
+        assert!("simplex".parse::<OptimizationMethod>().is_err());
+
+        let method = "ga:population=8".parse::<OptimizationMethod>().unwrap();
+        let OptimizationMethod::Genetic(config) = method else {
+            panic!("expected genetic, got {method:?}")
+        };
+        assert_eq!(config.population, 8);
+
+        let method = "greedy-d3:prune=true"
+            .parse::<OptimizationMethod>()
+            .unwrap();
+        let OptimizationMethod::GreedyDepth3(config) = method else {
+            panic!("expected greedy-d3, got {method:?}")
+        };
+        assert!(config.prune && !config.parallel);
+    }
+
+    #[test]
+    fn params() {
+        let params = parse_params(" iterations=100_000, temp = 2.5,,")
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(
+            (params[0].key, params[0].count().unwrap()),
+            ("iterations", 100_000)
+        );
+        assert_eq!(
+            (params[1].key, params[1].positive().unwrap()),
+            ("temp", 2.5)
+        );
+        assert!(params[1].count().is_err());
+
+        assert!(parse_params("iterations").any(|p| p.is_err()));
+        let negative = parse_params("temp=-1").next().unwrap().unwrap();
+        assert!(negative.positive().is_err());
+    }
+
+    #[test]
+    fn run_seeds() {
+        assert_eq!(run_seed(42, 0), 42);
+        assert_ne!(run_seed(42, 1), run_seed(43, 1));
+        assert_ne!(run_seed(42, 1), run_seed(42, 2));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("60s").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("60").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("1m30s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("1.5h").unwrap(), Duration::from_secs(5400));
+
+        for d in [Duration::from_secs(90), Duration::from_millis(1500)] {
+            assert_eq!(parse_duration(&format_duration(d)).unwrap(), d);
+        }
+
+        for invalid in ["", "s", "10y", "-5s", "1m30"] {
+            assert!(parse_duration(invalid).is_err(), "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::{fmt, str::FromStr, time::Instant};
+
+use nanorand::{RandomGen, Rng, WyRand};
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+
+use crate::{
+    analyze::Analyzer,
+    cached_layout::CachedLayout,
+    layout::Layout,
+    optimization::{format_duration, parse_duration, parse_params},
+    progress::RunProgress,
+    simulated_annealing::{
+        acceptance_probability, random_swap, AnnealingConfig, AnnealingLimit, BestOrder,
+    },
+    OxeylyzerError,
+};
+
+/// Parameters of a parallel tempering run: `chains` annealing chains at fixed temperatures
+/// between the hottest and the coldest, which run in parallel and try to exchange layouts with
+/// the chain at the next temperature every `interval` iterations. The limit is per chain.
+/// Temperatures that aren't set are calibrated the same way as for simulated annealing.
+///
+/// Can be parsed from a comma separated list of parameters, like
+/// `chains=8,iterations=50000,interval=500,max-temp=5e9,min-temp=1e6` or `time=30s`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TemperingConfig {
+    pub chains: usize,
+    pub interval: usize,
+    pub max_temperature: Option<f64>,
+    pub min_temperature: Option<f64>,
+    pub limit: AnnealingLimit,
+}
+
+impl Default for TemperingConfig {
+    fn default() -> Self {
+        Self {
+            chains: 8,
+            interval: 500,
+            max_temperature: None,
+            min_temperature: None,
+            limit: AnnealingLimit::Iterations(50_000),
+        }
+    }
+}
+
+impl FromStr for TemperingConfig {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for param in parse_params(s) {
+            let param = param?;
+
+            match param.key {
+                "chains" | "c" => config.chains = param.count()?.max(2),
+                "interval" => config.interval = param.count()?.max(1),
+                "max-temp" | "max-temperature" => config.max_temperature = Some(param.positive()?),
+                "min-temp" | "min-temperature" => config.min_temperature = Some(param.positive()?),
+                "iterations" | "i" => config.limit = AnnealingLimit::Iterations(param.count()?),
+                "time" | "t" => config.limit = AnnealingLimit::Time(parse_duration(param.value)?),
+                _ => return Err(param.invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for TemperingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "chains={},interval={}", self.chains, self.interval)?;
+        match self.limit {
+            AnnealingLimit::Iterations(i) => write!(f, ",iterations={i}")?,
+            AnnealingLimit::Time(d) => write!(f, ",time={}", format_duration(d))?,
+        }
+        if let Some(t) = self.max_temperature {
+            write!(f, ",max-temp={t}")?;
+        }
+        if let Some(t) = self.min_temperature {
+            write!(f, ",min-temp={t}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An annealing chain at a fixed temperature.
+struct Chain {
+    cache: CachedLayout,
+    score: i64,
+    best: BestOrder,
+    rng: WyRand,
+    temperature: f64,
+}
+
+impl Chain {
+    fn run(&mut self, a: &Analyzer, iterations: usize) {
+        for _ in 0..iterations {
+            if a.control.is_cancelled() {
+                break;
+            }
+
+            let swap = random_swap(&self.cache, &mut self.rng);
+
+            self.cache.swap(swap);
+            let new_score = a.score_cached_swap(&self.cache, swap);
+
+            let ap = acceptance_probability(self.score, new_score, self.temperature);
+
+            if ap > f64::random(&mut self.rng) {
+                a.update_cache(&mut self.cache, swap);
+                self.score = new_score;
+                self.best.swap(swap);
+                self.best.update(new_score);
+            } else {
+                self.cache.swap(swap);
+            }
+        }
+    }
+}
+
+/// Tries to exchange the layouts of the pairs of neighboring chains starting at `first`, where
+/// every chain keeps its temperature.
+fn exchange(chains: &mut [Chain], first: usize, rng: &mut WyRand) {
+    for i in (first..chains.len() - 1).step_by(2) {
+        let (cold, hot) = (&chains[i], &chains[i + 1]);
+        let diff = (hot.score - cold.score) as f64;
+        let p = (diff * (1.0 / cold.temperature - 1.0 / hot.temperature)).exp();
+
+        if p > f64::random(rng) {
+            chains.swap(i, i + 1);
+
+            let temperature = chains[i].temperature;
+            chains[i].temperature = chains[i + 1].temperature;
+            chains[i + 1].temperature = temperature;
+        }
+    }
+}
+
+impl Analyzer {
+    pub fn tempering_improve(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        config: &TemperingConfig,
+        seed: u64,
+    ) -> (Layout, i64) {
+        let start = layout.clone();
+        let mut cache = self.cached_layout(layout, pins);
+        let mut rng = WyRand::new_seed(seed);
+
+        if cache.possible_swaps.is_empty() {
+            let score = self.score_cache(&cache);
+            return (cache.into(), score);
+        }
+
+        let annealing = AnnealingConfig {
+            initial_temperature: config.max_temperature,
+            final_temperature: config.min_temperature,
+            ..Default::default()
+        };
+        let (hottest, coldest) = self.annealing_temperatures(&mut cache, &mut rng, &annealing);
+        let score = self.score_cache(&cache);
+
+        // chains are ordered from cold to hot, with temperatures spaced geometrically
+        let mut chains = (0..config.chains)
+            .map(|i| {
+                let progress = i as f64 / (config.chains - 1).max(1) as f64;
+
+                Chain {
+                    cache: cache.clone(),
+                    score,
+                    best: BestOrder::new(start.keys.len(), score),
+                    rng: WyRand::new_seed(rng.generate()),
+                    temperature: coldest * (hottest / coldest).powf(progress),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let run_start = Instant::now();
+        let mut progress = RunProgress::new(&self.control);
+        let mut done = 0;
+
+        for round in 0.. {
+            let iterations = match config.limit {
+                AnnealingLimit::Iterations(max) => config.interval.min(max - done),
+                AnnealingLimit::Time(budget) if run_start.elapsed() < budget => config.interval,
+                AnnealingLimit::Time(_) => 0,
+            };
+            if iterations == 0 || progress.is_cancelled() {
+                break;
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            chains.par_iter_mut().for_each(|c| c.run(self, iterations));
+            #[cfg(target_arch = "wasm32")]
+            chains.iter_mut().for_each(|c| c.run(self, iterations));
+
+            done += iterations;
+
+            // alternate between exchanging even and odd pairs of neighboring chains
+            exchange(&mut chains, round % 2, &mut rng);
+
+            let best = chains.iter().map(|c| c.best.score).max().unwrap_or(score);
+            progress.report(best);
+        }
+
+        let best = chains
+            .into_iter()
+            .max_by_key(|c| c.best.score)
+            .expect("there are always at least two chains");
+
+        best.best.into_layout(&start, best.cache, best.score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_config() {
+        let config = "chains=4, iterations=20_000,interval=100,max-temp=1e9"
+            .parse::<TemperingConfig>()
+            .unwrap();
+
+        assert_eq!(config.chains, 4);
+        assert_eq!(config.interval, 100);
+        assert_eq!(config.limit, AnnealingLimit::Iterations(20_000));
+        assert_eq!(config.max_temperature, Some(1e9));
+        assert_eq!(config.min_temperature, None);
+
+        let config = "time=2m".parse::<TemperingConfig>().unwrap();
+        assert_eq!(config.limit, AnnealingLimit::Time(Duration::from_secs(120)));
+        assert_eq!(
+            config.to_string().parse::<TemperingConfig>().unwrap(),
+            config
+        );
+
+        assert!("min-temp=0".parse::<TemperingConfig>().is_err());
+        assert!("chains=many".parse::<TemperingConfig>().is_err());
+        assert!("cooling=linear".parse::<TemperingConfig>().is_err());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn exchange_chains() {
+        let (analyzer, layout) = crate::test_util::analyzer_layout();
+        let mut rng = WyRand::new_seed(0);
+
+        let mut chains = [(1, 1e6), (2, 1e7), (3, 1e8)].map(|(seed, temperature)| {
+            let cache = analyzer.cached_layout(layout.random_with_seed(&[], seed), &[]);
+            let score = analyzer.score_cache(&cache);
+
+            Chain {
+                cache,
+                score,
+                best: BestOrder::new(layout.keys.len(), score),
+                rng: WyRand::new_seed(seed),
+                temperature,
+            }
+        });
+
+        // a hotter chain with a better layout always gives it to the colder chain
+        chains[0].score = -1000;
+        chains[1].score = 1000;
+        chains[2].score = -1_000_000_000_000;
+        let keys = chains
+            .iter()
+            .map(|c| c.cache.keys.clone())
+            .collect::<Vec<_>>();
+
+        exchange(&mut chains, 0, &mut rng);
+
+        assert_eq!(chains[0].cache.keys, keys[1]);
+        assert_eq!(chains[1].cache.keys, keys[0]);
+        assert_eq!(chains[2].cache.keys, keys[2]);
+        assert_eq!((chains[0].score, chains[1].score), (1000, -1000));
+        let temperatures = chains.iter().map(|c| c.temperature).collect::<Vec<_>>();
+        assert_eq!(temperatures, [1e6, 1e7, 1e8]);
+
+        // a much worse layout is practically never taken by a colder chain
+        exchange(&mut chains, 1, &mut rng);
+
+        assert_eq!(chains[1].cache.keys, keys[0]);
+        assert_eq!(chains[2].cache.keys, keys[2]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn tempering_improve() {
+        let config = "chains=3,iterations=3000,interval=200"
+            .parse::<TemperingConfig>()
+            .unwrap();
+
+        crate::test_util::assert_optimizes(|analyzer, layout, pins| {
+            analyzer.tempering_improve(layout, pins, &config, 7)
+        });
+    }
+}
@@ -0,0 +1,356 @@
+use std::{fmt, str::FromStr};
+
+use nanorand::{RandomGen, WyRand};
+
+use crate::{
+    analyze::Analyzer, layout::Layout, leaderboard::same_layout, stats::Stats, weights::Weights,
+    OxeylyzerError,
+};
+
+/// Weights of objectives are multiplied by a random factor between `1 / MAX_WEIGHT_FACTOR` and
+/// `MAX_WEIGHT_FACTOR` for every run.
+const MAX_WEIGHT_FACTOR: f64 = 4.0;
+
+/// A stat a Pareto front is built over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Metric {
+    Sfbs,
+    Sfs,
+    Lsbs,
+    Scissors,
+    HalfScissors,
+    FingerUseDeviation,
+    Effort,
+    /// Inrolls and outrolls together.
+    Rolls,
+    /// A trigram category by name.
+    Trigram(String),
+}
+
+impl Metric {
+    pub fn value(&self, stats: &Stats) -> f64 {
+        match self {
+            Self::Sfbs => stats.sfbs,
+            Self::Sfs => stats.sfs,
+            Self::Lsbs => stats.lsbs,
+            Self::Scissors => stats.scissors,
+            Self::HalfScissors => stats.half_scissors,
+            Self::FingerUseDeviation => stats.finger_use_deviation,
+            Self::Effort => stats.effort,
+            Self::Rolls => stats.trigrams.get("inroll") + stats.trigrams.get("outroll"),
+            Self::Trigram(category) => stats.trigrams.get(category),
+        }
+    }
+
+    /// Whether a higher value of this metric is better when it isn't specified.
+    pub fn higher_is_better(&self) -> bool {
+        match self {
+            Self::Rolls => true,
+            Self::Trigram(category) => matches!(
+                category.as_str(),
+                "inroll" | "outroll" | "alternate" | "onehandin" | "onehandout"
+            ),
+            _ => false,
+        }
+    }
+
+    /// Multiplies the weights this metric is scored with by `factor`.
+    fn scale_weights(&self, weights: &mut Weights, factor: f64) {
+        let scale = |w: &mut i64| *w = (*w as f64 * factor).round() as i64;
+
+        match self {
+            Self::Sfbs => scale(&mut weights.sfbs),
+            Self::Sfs => scale(&mut weights.sfs),
+            Self::Lsbs => scale(&mut weights.lsbs),
+            Self::Scissors | Self::HalfScissors => scale(&mut weights.scissors),
+            Self::FingerUseDeviation => scale(&mut weights.finger_usage.weight),
+            Self::Effort => scale(&mut weights.effort.weight),
+            Self::Rolls => {
+                Self::Trigram("inroll".into()).scale_weights(weights, factor);
+                Self::Trigram("outroll".into()).scale_weights(weights, factor);
+            }
+            Self::Trigram(category) => {
+                let mut weight = weights.trigram_weight(category);
+                scale(&mut weight);
+                weights.trigram_categories.insert(category.clone(), weight);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sfbs => write!(f, "sfbs"),
+            Self::Sfs => write!(f, "sfs"),
+            Self::Lsbs => write!(f, "lsbs"),
+            Self::Scissors => write!(f, "scissors"),
+            Self::HalfScissors => write!(f, "half-scissors"),
+            Self::FingerUseDeviation => write!(f, "finger-use-deviation"),
+            Self::Effort => write!(f, "effort"),
+            Self::Rolls => write!(f, "rolls"),
+            Self::Trigram(category) => write!(f, "{category}"),
+        }
+    }
+}
+
+/// A metric, and whether it should be as high or as low as possible. Can be parsed from the name
+/// of a metric, optionally prefixed by `+` to maximize it or `-` to minimize it. Any name that
+/// isn't a bigram stat is a trigram category, which is maximized by default for rolls, alternates
+/// and onehands, and minimized otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Objective {
+    pub metric: Metric,
+    pub maximize: bool,
+}
+
+impl FromStr for Objective {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, maximize) = match s.split_at_checked(1) {
+            Some(("+", name)) => (name, Some(true)),
+            Some(("-", name)) => (name, Some(false)),
+            _ => (s, None),
+        };
+
+        let metric = match name {
+            "sfbs" | "sfb" => Metric::Sfbs,
+            "sfs" => Metric::Sfs,
+            "lsbs" | "lsb" => Metric::Lsbs,
+            "scissors" => Metric::Scissors,
+            "half-scissors" => Metric::HalfScissors,
+            "finger-use-deviation" | "deviation" => Metric::FingerUseDeviation,
+            "effort" => Metric::Effort,
+            "rolls" => Metric::Rolls,
+            "" => return Err(OxeylyzerError::InvalidObjective(s.into())),
+            category => Metric::Trigram(category.into()),
+        };
+
+        Ok(Self {
+            maximize: maximize.unwrap_or_else(|| metric.higher_is_better()),
+            metric,
+        })
+    }
+}
+
+impl fmt::Display for Objective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.maximize { '+' } else { '-' };
+        write!(f, "{sign}{}", self.metric)
+    }
+}
+
+/// Parses a comma separated list of objectives, like `sfbs,sfs,rolls,-redirect`.
+pub fn parse_objectives(s: &str) -> Result<Vec<Objective>, OxeylyzerError> {
+    s.split(',')
+        .filter(|o| !o.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// A layout on a Pareto front, along with the value of every objective for it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoEntry {
+    pub layout: Layout,
+    pub score: i64,
+    pub values: Vec<f64>,
+    /// Seed of the first run that found this layout.
+    pub seed: u64,
+    /// Amount of runs that found this layout.
+    pub found: usize,
+}
+
+/// Keeps every layout of many optimization runs that no other layout is at least as good as on
+/// every objective, and better on one of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParetoFront {
+    objectives: Vec<Objective>,
+    entries: Vec<ParetoEntry>,
+    runs: usize,
+}
+
+impl ParetoFront {
+    pub fn new(objectives: Vec<Objective>) -> Self {
+        Self {
+            objectives,
+            entries: Vec::new(),
+            runs: 0,
+        }
+    }
+
+    pub fn objectives(&self) -> &[Objective] {
+        &self.objectives
+    }
+
+    /// Adds the result of a run, returning whether it is on the front. Layouts that are already on
+    /// the front are counted as found again instead of being added twice.
+    pub fn insert(&mut self, layout: Layout, score: i64, values: Vec<f64>, seed: u64) -> bool {
+        self.runs += 1;
+
+        let entry = ParetoEntry {
+            layout,
+            score,
+            values,
+            seed,
+            found: 1,
+        };
+        self.insert_entry(entry)
+    }
+
+    /// Adds the entries of an earlier front over the same objectives, and the amount of runs it
+    /// was made from, like the ones saved in a checkpoint.
+    pub fn extend(&mut self, entries: impl IntoIterator<Item = ParetoEntry>, runs: usize) {
+        self.runs += runs;
+
+        for entry in entries {
+            self.insert_entry(entry);
+        }
+    }
+
+    fn insert_entry(&mut self, entry: ParetoEntry) -> bool {
+        if let Some(e) = self
+            .entries
+            .iter_mut()
+            .find(|e| same_layout(&e.layout, &entry.layout))
+        {
+            e.found += entry.found;
+            return true;
+        }
+
+        let objectives = &self.objectives;
+
+        if self
+            .entries
+            .iter()
+            .any(|e| dominates(objectives, &e.values, &entry.values))
+        {
+            return false;
+        }
+
+        self.entries
+            .retain(|e| !dominates(objectives, &entry.values, &e.values));
+
+        let i = self.entries.partition_point(|e| e.score >= entry.score);
+        self.entries.insert(i, entry);
+
+        true
+    }
+
+    /// Entries from best to worst score.
+    pub fn entries(&self) -> &[ParetoEntry] {
+        &self.entries
+    }
+
+    /// Amount of runs that have been added.
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+}
+
+/// Whether `v1` is at least as good as `v2` on every objective, and better on one of them.
+pub fn dominates(objectives: &[Objective], v1: &[f64], v2: &[f64]) -> bool {
+    let mut better = false;
+
+    for ((o, &a), &b) in objectives.iter().zip(v1).zip(v2) {
+        let (a, b) = if o.maximize { (a, b) } else { (-a, -b) };
+
+        if a < b {
+            return false;
+        }
+        better |= a > b;
+    }
+
+    better
+}
+
+impl Analyzer {
+    /// Values of every objective for `layout`.
+    pub fn objective_values(&self, layout: &Layout, objectives: &[Objective]) -> Vec<f64> {
+        let stats = self.stats(layout);
+
+        objectives.iter().map(|o| o.metric.value(&stats)).collect()
+    }
+
+    /// Returns an error if an objective is a trigram category that isn't in the trigram table.
+    pub fn validate_objectives(&self, objectives: &[Objective]) -> Result<(), OxeylyzerError> {
+        let categories = self.trigram_table.categories();
+
+        match objectives.iter().find(|o| match &o.metric {
+            Metric::Trigram(c) => !categories.contains(c),
+            _ => false,
+        }) {
+            Some(o) => Err(OxeylyzerError::InvalidObjective(o.metric.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns an analyzer for this corpus in which the weights of every objective are multiplied
+    /// by a random factor, so runs with different seeds explore different tradeoffs. Weights that
+    /// are 0 stay 0.
+    pub fn with_scaled_weights(&self, objectives: &[Objective], seed: u64) -> Self {
+        let mut rng = WyRand::new_seed(seed);
+        let mut weights = self.weights.clone();
+
+        for o in objectives {
+            let factor = MAX_WEIGHT_FACTOR.powf(f64::random(&mut rng) * 2.0 - 1.0);
+            o.metric.scale_weights(&mut weights, factor);
+        }
+
+        self.with_weights(weights)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(keys: &str) -> Layout {
+        Layout {
+            name: keys.into(),
+            keys: keys.chars().collect(),
+            fingers: Default::default(),
+            keyboard: Default::default(),
+            shape: vec![keys.len()].into(),
+            board: None,
+            layers: Default::default(),
+            magic_rules: Default::default(),
+            constraints: Default::default(),
+        }
+    }
+
+    #[test]
+    fn parse_objectives() {
+        let objectives = super::parse_objectives("sfbs, rolls,-alternate,+sfs").unwrap();
+
+        let parsed = objectives.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert_eq!(parsed, ["-sfbs", "+rolls", "-alternate", "+sfs"]);
+        assert_eq!(objectives[2].metric, Metric::Trigram("alternate".into()));
+
+        assert!("+".parse::<Objective>().is_err());
+    }
+
+    #[test]
+    fn front() {
+        let objectives = super::parse_objectives("sfbs,rolls").unwrap();
+        let mut front = ParetoFront::new(objectives);
+
+        assert!(front.insert(layout("abc"), 10, vec![1.0, 40.0], 0));
+        assert!(front.insert(layout("bca"), 20, vec![2.0, 50.0], 1));
+        // worse sfbs and rolls than the first layout
+        assert!(!front.insert(layout("cab"), 30, vec![1.5, 30.0], 2));
+        assert!(front.insert(layout("bca"), 20, vec![2.0, 50.0], 3));
+        // better than the first layout on both
+        assert!(front.insert(layout("acb"), 5, vec![0.5, 45.0], 4));
+
+        let entries = front
+            .entries()
+            .iter()
+            .map(|e| (e.score, e.seed, e.found))
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries, [(20, 1, 2), (5, 4, 1)]);
+        assert_eq!(front.runs(), 5);
+    }
+}
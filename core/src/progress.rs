@@ -0,0 +1,168 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// Amount of iterations between progress reports of optimizers with many cheap iterations, like
+/// simulated annealing.
+pub(crate) const REPORT_INTERVAL: usize = 1000;
+
+/// Flag that stops every optimization using it as soon as possible. Cloned tokens share the same
+/// flag, so a token can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Makes the token usable again after it has been cancelled.
+    pub fn reset(&self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress of a single optimization run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OptimizationProgress {
+    pub iteration: usize,
+    pub best_score: i64,
+    pub elapsed: Duration,
+}
+
+/// Receives progress of optimizations. Runs can happen on multiple threads at once, in which case
+/// each of them reports its own progress.
+pub trait OptimizationObserver: Send + Sync {
+    fn progress(&self, progress: &OptimizationProgress);
+}
+
+impl<F: Fn(&OptimizationProgress) + Send + Sync> OptimizationObserver for F {
+    fn progress(&self, progress: &OptimizationProgress) {
+        self(progress)
+    }
+}
+
+/// Cancellation and progress reporting of the optimizations of an analyzer. Doesn't influence any
+/// results, so it is ignored when comparing analyzers.
+#[derive(Clone, Default)]
+pub struct OptimizationControl {
+    pub cancellation: CancellationToken,
+    pub observer: Option<Arc<dyn OptimizationObserver>>,
+}
+
+impl OptimizationControl {
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation.is_cancelled()
+    }
+}
+
+impl fmt::Debug for OptimizationControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OptimizationControl")
+            .field("cancellation", &self.cancellation)
+            .field("observer", &self.observer.is_some())
+            .finish()
+    }
+}
+
+impl PartialEq for OptimizationControl {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// Keeps track of the iterations and elapsed time of a single run to report to the observer.
+pub(crate) struct RunProgress<'a> {
+    control: &'a OptimizationControl,
+    start: Instant,
+    iteration: usize,
+}
+
+impl<'a> RunProgress<'a> {
+    pub fn new(control: &'a OptimizationControl) -> Self {
+        Self {
+            control,
+            start: Instant::now(),
+            iteration: 0,
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.control.is_cancelled()
+    }
+
+    /// Finishes an iteration, reporting `best_score` to the observer.
+    pub fn report(&mut self, best_score: i64) {
+        self.iteration += 1;
+
+        if let Some(observer) = &self.control.observer {
+            observer.progress(&OptimizationProgress {
+                iteration: self.iteration,
+                best_score,
+                elapsed: self.start.elapsed(),
+            });
+        }
+    }
+
+    /// Finishes an iteration, only reporting `best_score` to the observer every `interval`
+    /// iterations.
+    pub fn report_every(&mut self, best_score: i64, interval: usize) {
+        match (self.iteration + 1) % interval {
+            0 => self.report(best_score),
+            _ => self.iteration += 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn reports() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let observed = reports.clone();
+
+        let control = OptimizationControl {
+            observer: Some(Arc::new(move |p: &OptimizationProgress| {
+                observed.lock().unwrap().push((p.iteration, p.best_score))
+            })),
+            ..Default::default()
+        };
+
+        let mut progress = RunProgress::new(&control);
+        for score in 0..10 {
+            progress.report_every(score, 4);
+        }
+        progress.report(20);
+
+        assert_eq!(*reports.lock().unwrap(), [(4, 3), (8, 7), (11, 20)]);
+    }
+
+    #[test]
+    fn cancellation() {
+        let control = OptimizationControl::default();
+        let token = control.cancellation.clone();
+        let progress = RunProgress::new(&control);
+
+        assert!(!progress.is_cancelled());
+        token.cancel();
+        assert!(progress.is_cancelled());
+        token.reset();
+        assert!(!control.is_cancelled());
+    }
+}
@@ -1,27 +1,241 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
 use nanorand::{RandomGen, Rng, WyRand};
 
 use crate::{
     analyze::Analyzer,
     cached_layout::CachedLayout,
     layout::{Layout, PosPair},
+    optimization::{format_duration, parse_duration, parse_params},
+    progress::{RunProgress, REPORT_INTERVAL},
+    OxeylyzerError,
 };
 
+/// Amount of random swaps that are scored to calibrate the temperature.
+const CALIBRATION_SAMPLES: usize = 1000;
+/// Probability of accepting an average worsening swap at the initial temperature.
+const INITIAL_ACCEPTANCE: f64 = 0.8;
+/// Probability of accepting the smallest worsening swap at the final temperature.
+const FINAL_ACCEPTANCE: f64 = 0.001;
+/// Amount of iterations between checks of the time a run has left.
+const TIME_CHECK_INTERVAL: usize = 64;
+
+/// How the temperature decreases from the initial to the final temperature.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CoolingSchedule {
+    #[default]
+    Exponential,
+    Linear,
+}
+
+impl CoolingSchedule {
+    /// Temperature after `progress`, going from 0.0 at the start to 1.0 at the end of a run.
+    pub fn temperature(&self, initial: f64, last: f64, progress: f64) -> f64 {
+        match self {
+            Self::Exponential => initial * (last / initial).powf(progress),
+            Self::Linear => initial + (last - initial) * progress,
+        }
+    }
+}
+
+impl FromStr for CoolingSchedule {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exponential" | "exp" => Ok(Self::Exponential),
+            "linear" => Ok(Self::Linear),
+            _ => Err(OxeylyzerError::InvalidOptimizationParameter(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for CoolingSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exponential => write!(f, "exponential"),
+            Self::Linear => write!(f, "linear"),
+        }
+    }
+}
+
+/// When an annealing run stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnealingLimit {
+    Iterations(usize),
+    Time(Duration),
+}
+
+impl Default for AnnealingLimit {
+    fn default() -> Self {
+        Self::Iterations(100_000)
+    }
+}
+
+/// Parameters of a simulated annealing run. Temperatures that aren't set are calibrated from the
+/// score differences of random swaps on the starting layout.
+///
+/// Can be parsed from a comma separated list of parameters, like
+/// `iterations=200000,cooling=linear,temp=5e9,final-temp=1e6` or `time=30s`.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct AnnealingConfig {
+    pub initial_temperature: Option<f64>,
+    pub final_temperature: Option<f64>,
+    pub cooling: CoolingSchedule,
+    pub limit: AnnealingLimit,
+}
+
+impl FromStr for AnnealingConfig {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for param in parse_params(s) {
+            let param = param?;
+
+            match param.key {
+                "temp" | "temperature" => config.initial_temperature = Some(param.positive()?),
+                "final-temp" | "final-temperature" => {
+                    config.final_temperature = Some(param.positive()?)
+                }
+                "cooling" => config.cooling = param.value.parse()?,
+                "iterations" | "i" => config.limit = AnnealingLimit::Iterations(param.count()?),
+                "time" | "t" => config.limit = AnnealingLimit::Time(parse_duration(param.value)?),
+                _ => return Err(param.invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for AnnealingConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.limit {
+            AnnealingLimit::Iterations(i) => write!(f, "iterations={i}")?,
+            AnnealingLimit::Time(d) => write!(f, "time={}", format_duration(d))?,
+        }
+        write!(f, ",cooling={}", self.cooling)?;
+        if let Some(t) = self.initial_temperature {
+            write!(f, ",temp={t}")?;
+        }
+        if let Some(t) = self.final_temperature {
+            write!(f, ",final-temp={t}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps track of how far along a run is, by iterations or by time depending on its limit. The
+/// time is only checked every [`TIME_CHECK_INTERVAL`] iterations.
+struct Timeline {
+    limit: AnnealingLimit,
+    start: Instant,
+    iteration: usize,
+    progress: f64,
+}
+
+impl Timeline {
+    fn new(limit: AnnealingLimit) -> Self {
+        Self {
+            limit,
+            start: Instant::now(),
+            iteration: 0,
+            progress: 0.0,
+        }
+    }
+
+    /// Advances by one iteration, returning the progress between 0.0 and 1.0 or `None` when the
+    /// run is done.
+    fn next(&mut self) -> Option<f64> {
+        match self.limit {
+            AnnealingLimit::Iterations(max) => {
+                self.progress = self.iteration as f64 / max as f64;
+            }
+            AnnealingLimit::Time(budget) if self.iteration.is_multiple_of(TIME_CHECK_INTERVAL) => {
+                self.progress = self.start.elapsed().as_secs_f64() / budget.as_secs_f64();
+            }
+            AnnealingLimit::Time(_) => {}
+        }
+        self.iteration += 1;
+
+        (self.progress < 1.0).then_some(self.progress)
+    }
+}
+
+/// Best layout a run has found, stored as the order of the keys of the layout it started with.
+pub(crate) struct BestOrder {
+    current: Vec<usize>,
+    best: Vec<usize>,
+    pub score: i64,
+}
+
+impl BestOrder {
+    pub fn new(len: usize, score: i64) -> Self {
+        let current = (0..len).collect::<Vec<_>>();
+
+        Self {
+            best: current.clone(),
+            current,
+            score,
+        }
+    }
+
+    pub fn swap(&mut self, PosPair(a, b): PosPair) {
+        self.current.swap(a as usize, b as usize);
+    }
+
+    pub fn update(&mut self, score: i64) {
+        if score > self.score {
+            self.score = score;
+            self.best.clone_from(&self.current);
+        }
+    }
+
+    /// Returns the best layout, which is `cache` itself if its score is the best.
+    pub fn into_layout(self, start: &Layout, cache: CachedLayout, score: i64) -> (Layout, i64) {
+        match self.score > score {
+            true => (start.with_order(&self.best), self.score),
+            false => (cache.into(), score),
+        }
+    }
+}
+
 impl Analyzer {
     pub fn annealing_improve(
         &self,
         layout: Layout,
         pins: &[usize],
-        initial_temperature: f64,
-        cooling_rate: f64,
-        max_iterations: usize,
+        config: &AnnealingConfig,
+        seed: u64,
     ) -> (Layout, i64) {
+        let start = layout.clone();
         let mut cache = self.cached_layout(layout, pins);
-        let mut rng = WyRand::new();
+        let mut rng = WyRand::new_seed(seed);
+
+        if cache.possible_swaps.is_empty() {
+            let score = self.score_cache(&cache);
+            return (cache.into(), score);
+        }
 
+        let (initial, last) = self.annealing_temperatures(&mut cache, &mut rng, config);
         let mut current_score = self.score_cache(&cache);
-        let mut temperature = initial_temperature;
+        let mut timeline = Timeline::new(config.limit);
+        let mut progress = RunProgress::new(&self.control);
+        let mut best = BestOrder::new(start.keys.len(), current_score);
 
-        for _ in 0..max_iterations {
+        while let Some(p) = timeline.next() {
+            if progress.is_cancelled() {
+                break;
+            }
+
+            let temperature = config.cooling.temperature(initial, last, p);
             let swap = random_swap(&cache, &mut rng);
 
             cache.swap(swap);
@@ -32,31 +246,46 @@ impl Analyzer {
             if ap > f64::random(&mut rng) {
                 self.update_cache(&mut cache, swap);
                 current_score = new_score;
+                best.swap(swap);
+                best.update(current_score);
             } else {
                 cache.swap(swap);
             }
 
-            temperature *= cooling_rate;
+            progress.report_every(best.score, REPORT_INTERVAL);
         }
 
-        (cache.into(), current_score)
+        best.into_layout(&start, cache, current_score)
     }
 
     pub fn annealing_depth2_improve(
         &self,
         layout: Layout,
         pins: &[usize],
-        initial_temperature: f64,
-        cooling_rate: f64,
-        max_iterations: usize,
+        config: &AnnealingConfig,
+        seed: u64,
     ) -> (Layout, i64) {
+        let start = layout.clone();
         let mut cache = self.cached_layout(layout, pins);
-        let mut rng = WyRand::new();
+        let mut rng = WyRand::new_seed(seed);
+
+        if cache.possible_swaps.is_empty() {
+            let score = self.score_cache(&cache);
+            return (cache.into(), score);
+        }
 
+        let (initial, last) = self.annealing_temperatures(&mut cache, &mut rng, config);
         let mut current_score = self.score_cache(&cache);
-        let mut temperature = initial_temperature;
+        let mut timeline = Timeline::new(config.limit);
+        let mut progress = RunProgress::new(&self.control);
+        let mut best = BestOrder::new(start.keys.len(), current_score);
 
-        for _ in 0..max_iterations {
+        while let Some(p) = timeline.next() {
+            if progress.is_cancelled() {
+                break;
+            }
+
+            let temperature = config.cooling.temperature(initial, last, p);
             let [swap1, swap2] = random_swap2(&cache, &mut rng);
 
             cache.swap(swap1);
@@ -69,21 +298,79 @@ impl Analyzer {
             if ap > f64::random(&mut rng) {
                 self.update_cache(&mut cache, swap2);
                 current_score = new_score;
+                best.swap(swap1);
+                best.swap(swap2);
+                best.update(current_score);
             } else {
                 cache.swap(swap2);
                 cache.swap(swap1);
                 self.update_cache(&mut cache, swap1);
             }
 
-            temperature *= cooling_rate;
+            progress.report_every(best.score, REPORT_INTERVAL);
         }
 
-        (cache.into(), current_score)
+        best.into_layout(&start, cache, current_score)
+    }
+
+    /// Returns the initial and final temperature of a run, calibrating the ones `config` doesn't
+    /// set from the score losses of random swaps. The initial temperature accepts an average
+    /// worsening swap 80% of the time, the final temperature accepts the smallest one 0.1% of the
+    /// time.
+    pub fn annealing_temperatures(
+        &self,
+        cache: &mut CachedLayout,
+        rng: &mut WyRand,
+        config: &AnnealingConfig,
+    ) -> (f64, f64) {
+        let (average, smallest) = match (config.initial_temperature, config.final_temperature) {
+            (Some(_), Some(_)) => (0.0, 0.0),
+            _ => self.swap_losses(cache, rng),
+        };
+
+        let initial = config
+            .initial_temperature
+            .unwrap_or_else(|| -average / INITIAL_ACCEPTANCE.ln());
+        let last = config
+            .final_temperature
+            .unwrap_or_else(|| -smallest / FINAL_ACCEPTANCE.ln());
+
+        (initial, last.min(initial))
+    }
+
+    /// Average and smallest amount a random swap that makes `cache` worse lowers its score by.
+    /// Both are 1.0 when no sampled swap makes it worse.
+    fn swap_losses(&self, cache: &mut CachedLayout, rng: &mut WyRand) -> (f64, f64) {
+        let score = self.score_cache(cache);
+
+        let losses = (0..CALIBRATION_SAMPLES)
+            .filter_map(|_| {
+                let swap = random_swap(cache, rng);
+
+                cache.swap(swap);
+                let diff = score - self.score_cached_swap(cache, swap);
+                // swaps that violate constraints are never accepted, so they shouldn't heat it up
+                let violates = self.violations_swap_diff(cache, swap) > 0;
+                cache.swap(swap);
+
+                (diff > 0 && !violates).then_some(diff as f64)
+            })
+            .collect::<Vec<_>>();
+
+        match losses.len() {
+            0 => (1.0, 1.0),
+            len => {
+                let average = losses.iter().sum::<f64>() / len as f64;
+                let smallest = losses.iter().copied().fold(f64::INFINITY, f64::min);
+
+                (average, smallest)
+            }
+        }
     }
 }
 
 #[inline]
-fn random_swap(cache: &CachedLayout, rng: &mut WyRand) -> PosPair {
+pub(crate) fn random_swap(cache: &CachedLayout, rng: &mut WyRand) -> PosPair {
     cache.possible_swaps[rng.generate_range(0..(cache.possible_swaps.len()))]
 }
 
@@ -93,7 +380,7 @@ fn random_swap2(cache: &CachedLayout, rng: &mut WyRand) -> [PosPair; 2] {
 }
 
 #[inline]
-fn acceptance_probability(current_score: i64, new_score: i64, temperature: f64) -> f64 {
+pub(crate) fn acceptance_probability(current_score: i64, new_score: i64, temperature: f64) -> f64 {
     if new_score > current_score {
         1.0
     } else {
@@ -104,3 +391,75 @@ fn acceptance_probability(current_score: i64, new_score: i64, temperature: f64)
     //     new_score - current_score
     // );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = "iterations=20_000, cooling=linear,temp=1e9"
+            .parse::<AnnealingConfig>()
+            .unwrap();
+
+        assert_eq!(config.limit, AnnealingLimit::Iterations(20_000));
+        assert_eq!(config.cooling, CoolingSchedule::Linear);
+        assert_eq!(config.initial_temperature, Some(1e9));
+        assert_eq!(config.final_temperature, None);
+
+        let config = "time=1m30s".parse::<AnnealingConfig>().unwrap();
+        assert_eq!(config.limit, AnnealingLimit::Time(Duration::from_secs(90)));
+        assert_eq!(
+            config.to_string().parse::<AnnealingConfig>().unwrap(),
+            config
+        );
+
+        assert!("temp=-1".parse::<AnnealingConfig>().is_err());
+        assert!("cooling=fast".parse::<AnnealingConfig>().is_err());
+        assert!("iterations".parse::<AnnealingConfig>().is_err());
+        assert!("speed=3".parse::<AnnealingConfig>().is_err());
+    }
+
+    #[test]
+    fn cooling_schedules() {
+        use CoolingSchedule::*;
+
+        for schedule in [Exponential, Linear] {
+            assert_eq!(schedule.temperature(100.0, 1.0, 0.0), 100.0);
+            assert!((schedule.temperature(100.0, 1.0, 1.0) - 1.0).abs() < 1e-9);
+        }
+
+        assert!((Exponential.temperature(100.0, 1.0, 0.5) - 10.0).abs() < 1e-9);
+        assert_eq!(Linear.temperature(100.0, 0.0, 0.5), 50.0);
+    }
+
+    #[test]
+    fn timeline() {
+        let mut timeline = Timeline::new(AnnealingLimit::Iterations(4));
+        let progress = std::iter::from_fn(|| timeline.next()).collect::<Vec<_>>();
+        assert_eq!(progress, [0.0, 0.25, 0.5, 0.75]);
+
+        // the time is only checked every interval, so a run ends at the next check after its
+        // budget has passed
+        let mut timeline = Timeline::new(AnnealingLimit::Time(Duration::from_millis(1)));
+        assert!(timeline.next().is_some());
+        std::thread::sleep(Duration::from_millis(2));
+        for _ in 1..TIME_CHECK_INTERVAL {
+            assert!(timeline.next().is_some());
+        }
+        assert_eq!(timeline.next(), None);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn annealing_improve() {
+        let config = "iterations=3000".parse::<AnnealingConfig>().unwrap();
+
+        crate::test_util::assert_optimizes(|analyzer, layout, pins| {
+            analyzer.annealing_improve(layout, pins, &config, 7)
+        });
+        crate::test_util::assert_optimizes(|analyzer, layout, pins| {
+            analyzer.annealing_depth2_improve(layout, pins, &config, 7)
+        });
+    }
+}
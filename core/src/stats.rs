@@ -1,3 +1,5 @@
+use indexmap::IndexMap;
+
 use crate::{
     analyze::TrigramData,
     prelude::{Analyzer, Layout},
@@ -9,32 +11,46 @@ pub struct Stats {
     pub finger_sfbs: [f64; 10],
     pub weighted_finger_distance: [f64; 10],
     pub unweighted_finger_distance: [f64; 10],
+    pub finger_lsbs: [f64; 10],
+    pub finger_use_deviation: f64,
+    pub row_use: RowUsage,
+    pub effort: f64,
     pub sfbs: f64,
     pub sfs: f64,
+    pub lsbs: f64,
+    pub scissors: f64,
+    pub half_scissors: f64,
     pub trigrams: TrigramStats,
 }
 
 #[derive(Debug, Clone, Default, PartialEq)]
-pub struct TrigramStats {
-    pub sft: f64,
-    pub sfb: f64,
-    pub inroll: f64,
-    pub outroll: f64,
-    pub alternate: f64,
-    pub redirect: f64,
-    pub onehandin: f64,
-    pub onehandout: f64,
+pub struct RowUsage {
+    pub top: f64,
+    pub home: f64,
+    pub bottom: f64,
     pub thumb: f64,
-    pub invalid: f64,
+}
+
+/// Trigram frequency of every category of the trigram table in percentages.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrigramStats(pub IndexMap<String, f64>);
+
+impl TrigramStats {
+    pub fn get(&self, category: &str) -> f64 {
+        self.0.get(category).copied().unwrap_or_default()
+    }
 }
 
 impl Analyzer {
     pub fn stats(&self, layout: &Layout) -> Stats {
         let cache = self.cached_layout(layout.clone(), &[]);
 
-        let finger_use = self
-            .finger_use(&cache)
-            .map(|u| u as f64 / self.data.char_total);
+        let raw_finger_use = self.finger_use(&cache);
+
+        let finger_use = raw_finger_use.map(|u| u as f64 / self.data.char_total);
+
+        let finger_use_deviation =
+            self.finger_usage_deviation(&raw_finger_use) as f64 / self.data.char_total;
 
         let finger_sfbs = self
             .finger_sfbs(&cache)
@@ -48,8 +64,28 @@ impl Analyzer {
             .unweighted_finger_distance(&cache)
             .map(|s| s as f64 / ((self.data.bigram_total + self.data.skipgram_total) * 100.0));
 
+        let [top, home, bottom, thumb] = self
+            .row_use(&cache)
+            .map(|u| u as f64 / self.data.char_total);
+
+        let row_use = RowUsage {
+            top,
+            home,
+            bottom,
+            thumb,
+        };
+
+        let effort = self.effort(&cache) as f64 / (self.data.char_total * 100.0 * 100.0);
+
+        let finger_lsbs = self
+            .finger_lsbs(&cache)
+            .map(|s| s as f64 / self.data.bigram_total);
+
         let sfbs = self.sfbs(&cache) as f64 / self.data.char_total;
         let sfs = self.sfs(&cache) as f64 / self.data.bigram_total;
+        let lsbs = self.lsbs(&cache) as f64 / self.data.bigram_total;
+        let scissors = self.scissors(&cache) as f64 / self.data.bigram_total;
+        let half_scissors = self.half_scissors(&cache) as f64 / self.data.bigram_total;
 
         let trigrams = self.trigram_stats(self.trigrams(&cache));
 
@@ -58,24 +94,26 @@ impl Analyzer {
             finger_sfbs,
             weighted_finger_distance,
             unweighted_finger_distance,
+            finger_lsbs,
+            finger_use_deviation,
+            row_use,
+            effort,
             sfbs,
             sfs,
+            lsbs,
+            scissors,
+            half_scissors,
             trigrams,
         }
     }
 
     pub fn trigram_stats(&self, trigrams: TrigramData) -> TrigramStats {
-        TrigramStats {
-            sft: trigrams.sft as f64 / self.data.trigram_total,
-            sfb: trigrams.sfb as f64 / self.data.trigram_total,
-            inroll: trigrams.inroll as f64 / self.data.trigram_total,
-            outroll: trigrams.outroll as f64 / self.data.trigram_total,
-            alternate: trigrams.alternate as f64 / self.data.trigram_total,
-            redirect: trigrams.redirect as f64 / self.data.trigram_total,
-            onehandin: trigrams.onehandin as f64 / self.data.trigram_total,
-            onehandout: trigrams.onehandout as f64 / self.data.trigram_total,
-            thumb: trigrams.thumb as f64 / self.data.trigram_total,
-            invalid: trigrams.invalid as f64 / self.data.trigram_total,
-        }
+        let stats = trigrams
+            .0
+            .into_iter()
+            .map(|(category, freq)| (category, freq as f64 / self.data.trigram_total))
+            .collect();
+
+        TrigramStats(stats)
     }
 }
@@ -0,0 +1,179 @@
+use std::{fmt, str::FromStr};
+
+use crate::{
+    analyze::Analyzer,
+    layout::{Layout, PosPair},
+    optimization::parse_params,
+    progress::RunProgress,
+    simulated_annealing::BestOrder,
+    OxeylyzerError,
+};
+
+/// Parameters of a tabu search run. Every iteration applies the best swap, even if it makes the
+/// layout worse, except for swaps of positions that were swapped in the last `tenure` iterations.
+/// Those are only applied when they lead to a better layout than the best one found so far. Tabu
+/// search makes no random choices, so the same layout always gives the same result.
+///
+/// Can be parsed from a comma separated list of parameters, like `iterations=1000,tenure=10`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TabuConfig {
+    pub iterations: usize,
+    pub tenure: usize,
+}
+
+impl Default for TabuConfig {
+    fn default() -> Self {
+        Self {
+            iterations: 500,
+            tenure: 7,
+        }
+    }
+}
+
+impl FromStr for TabuConfig {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+
+        for param in parse_params(s) {
+            let param = param?;
+
+            match param.key {
+                "iterations" | "i" => config.iterations = param.count()?,
+                "tenure" => config.tenure = param.count()?,
+                _ => return Err(param.invalid()),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl fmt::Display for TabuConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "iterations={},tenure={}", self.iterations, self.tenure)
+    }
+}
+
+/// Positions that were swapped recently enough to be tabu.
+#[derive(Debug, Clone)]
+struct TabuList {
+    /// Iteration in which every position was last swapped.
+    last_swapped: Vec<Option<usize>>,
+    tenure: usize,
+}
+
+impl TabuList {
+    fn new(len: usize, tenure: usize) -> Self {
+        Self {
+            last_swapped: vec![None; len],
+            tenure,
+        }
+    }
+
+    fn is_tabu(&self, p: u16, iteration: usize) -> bool {
+        self.last_swapped[p as usize].is_some_and(|i| iteration - i <= self.tenure)
+    }
+
+    /// Whether `swap` can be applied in `iteration`, where tabu swaps are only allowed if their
+    /// `score` beats the `best` score found so far.
+    fn allows(&self, PosPair(a, b): PosPair, iteration: usize, score: i64, best: i64) -> bool {
+        !(self.is_tabu(a, iteration) || self.is_tabu(b, iteration)) || score > best
+    }
+
+    fn record(&mut self, PosPair(a, b): PosPair, iteration: usize) {
+        self.last_swapped[a as usize] = Some(iteration);
+        self.last_swapped[b as usize] = Some(iteration);
+    }
+}
+
+impl Analyzer {
+    pub fn tabu_improve(
+        &self,
+        layout: Layout,
+        pins: &[usize],
+        config: &TabuConfig,
+    ) -> (Layout, i64) {
+        let start = layout.clone();
+        let mut cache = self.cached_layout(layout, pins);
+        let mut current_score = self.score_cache(&cache);
+        let mut best = BestOrder::new(start.keys.len(), current_score);
+        let mut progress = RunProgress::new(&self.control);
+
+        let mut tabu = TabuList::new(cache.keys.len(), config.tenure);
+
+        for iteration in 1..=config.iterations {
+            if progress.is_cancelled() {
+                break;
+            }
+
+            let allowed = |swap, score| tabu.allows(swap, iteration, score, best.score);
+
+            let Some((swap, score)) = self.best_swap_where(&mut cache, allowed) else {
+                break;
+            };
+
+            cache.swap(swap);
+            self.update_cache(&mut cache, swap);
+            current_score = score;
+
+            tabu.record(swap, iteration);
+
+            best.swap(swap);
+            best.update(current_score);
+            progress.report(best.score);
+        }
+
+        best.into_layout(&start, cache, current_score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_config() {
+        let config = "iterations=2_000, tenure=12".parse::<TabuConfig>().unwrap();
+
+        assert_eq!(config.iterations, 2000);
+        assert_eq!(config.tenure, 12);
+        assert_eq!(config.to_string().parse::<TabuConfig>().unwrap(), config);
+        assert_eq!("".parse::<TabuConfig>().unwrap(), TabuConfig::default());
+
+        assert!("tenure=-1".parse::<TabuConfig>().is_err());
+        assert!("aspiration=false".parse::<TabuConfig>().is_err());
+    }
+
+    #[test]
+    fn tabu_list() {
+        let mut tabu = TabuList::new(4, 2);
+        let (swapped, other) = (PosPair(0, 1), PosPair(2, 3));
+
+        assert!(tabu.allows(swapped, 1, 0, 10));
+        tabu.record(swapped, 1);
+
+        // swaps sharing a position with the last swap are tabu for `tenure` iterations
+        for iteration in 2..=3 {
+            assert!(!tabu.allows(swapped, iteration, 0, 10));
+            assert!(!tabu.allows(PosPair(1, 2), iteration, 0, 10));
+            assert!(tabu.allows(other, iteration, 0, 10));
+        }
+        assert!(tabu.allows(swapped, 4, 0, 10));
+
+        // aspiration: a tabu swap that beats the best score is allowed anyway
+        assert!(!tabu.allows(swapped, 2, 10, 10));
+        assert!(tabu.allows(swapped, 2, 11, 10));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn tabu_improve() {
+        let config = "iterations=20".parse::<TabuConfig>().unwrap();
+
+        crate::test_util::assert_optimizes(|analyzer, layout, pins| {
+            analyzer.tabu_improve(layout, pins, &config)
+        });
+    }
+}
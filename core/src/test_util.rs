@@ -0,0 +1,34 @@
+use crate::{analyze::Analyzer, data::Data, layout::Layout, weights::dummy_weights};
+
+/// Positions that are pinned when testing optimizers.
+pub const PINS: [usize; 3] = [0, 5, 12];
+
+pub fn analyzer_layout() -> (Analyzer, Layout) {
+    let data = Data::load("../data/shai.json").expect("this should exist");
+
+    let weights = dummy_weights();
+
+    let analyzer = Analyzer::new(data, weights).expect("shai has few enough chars");
+
+    let layout =
+        Layout::load("../layouts/rstn-oxey.dof").expect("this layout is valid and exists, soooo");
+
+    (analyzer, layout)
+}
+
+/// Optimizes a random layout with [`PINS`] pinned, checking that the result scores at least as
+/// well as the start with the score that is returned, that pinned keys stay in place, and that
+/// optimizing again gives the same result.
+pub fn assert_optimizes(optimize: impl Fn(&Analyzer, Layout, &[usize]) -> (Layout, i64)) {
+    let (analyzer, layout) = analyzer_layout();
+    let start = layout.random_with_seed(&PINS, 1);
+
+    let (improved, score) = optimize(&analyzer, start.clone(), &PINS);
+
+    assert!(score >= analyzer.score(&start));
+    assert_eq!(score, analyzer.score(&improved));
+    for p in PINS {
+        assert_eq!(improved.keys[p], start.keys[p]);
+    }
+    assert_eq!(optimize(&analyzer, start, &PINS), (improved, score));
+}
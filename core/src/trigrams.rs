@@ -1,7 +1,43 @@
+//! Classification of finger triples into trigram categories.
+//!
+//! A motion between two different fingers of the same hand is *inward* when it moves towards the
+//! thumb, and *outward* otherwise. Every pattern below is defined on its own, so patterns can be
+//! combined in rules in any order:
+//!
+//! | pattern      | definition                                                        | example    |
+//! |--------------|-------------------------------------------------------------------|------------|
+//! | `thumb`      | any of the fingers is a thumb                                     | `LI LT RI` |
+//! | `sft`        | all three fingers are the same                                    | `LI LI LI` |
+//! | `sfb`        | the first two or last two fingers are the same, but not all three | `LI LI RM` |
+//! | `inroll`     | one hand switch, with an inward motion on the other hand          | `LR LM RI` |
+//! | `outroll`    | one hand switch, with an outward motion on the other hand         | `RI LM LR` |
+//! | `alternate`  | two hand switches                                                 | `LM RI LR` |
+//! | `redirect`   | one hand, changing direction halfway                              | `LR LP LM` |
+//! | `onehandin`  | one hand, two inward motions                                      | `LP LR LM` |
+//! | `onehandout` | one hand, two outward motions                                     | `LM LR LP` |
+//! | `index`      | any of the fingers is an index finger                             | `LR LI LM` |
+//! | `no-index`   | none of the fingers is an index finger or a thumb                 | `LR LP LM` |
+//!
+//! The default table applies the rules `thumb`, `sft`, `sfb`, `inroll`, `outroll`, `alternate`,
+//! `redirect`, `onehandin` and `onehandout` in that order, and puts every triple in exactly one of
+//! them: 488 thumb, 8 sft, 112 sfb, 96 inroll, 96 outroll, 128 alternate, 56 redirect, 8 onehandin
+//! and 8 onehandout triples. The full table can be printed with the `trigram-table` command.
+
+use std::collections::BTreeMap;
+
 use libdof::prelude::{Finger as DofFinger, Finger::*};
+use serde::{Deserialize, Serialize};
+use serde_with::{DeserializeFromStr, SerializeDisplay};
+
+use crate::OxeylyzerError;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum TrigramType {
+/// Category every finger triple that doesn't match any rule falls in.
+pub const INVALID_CATEGORY: &str = "invalid";
+
+/// Property of a finger triple that classification rules can match on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrigramPattern {
     Sft,
     Sfb,
     // Repeat,
@@ -12,7 +48,11 @@ pub enum TrigramType {
     OnehandIn,
     OnehandOut,
     Thumb,
-    Invalid,
+    /// At least one of the fingers is an index finger.
+    Index,
+    /// None of the fingers are an index finger or a thumb.
+    #[serde(rename = "no-index")]
+    NoIndex,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -46,26 +86,27 @@ impl Finger {
         matches!(self.0, RT | LT)
     }
 
-    const fn _is_index(self) -> bool {
+    const fn is_index(self) -> bool {
         matches!(self.0, LI | RI)
     }
 
-    const fn _is_non_index(self) -> bool {
-        !(self.is_thumb() || self._is_index())
+    const fn is_non_index(self) -> bool {
+        !(self.is_thumb() || self.is_index())
     }
 
+    /// Whether moving from `self` to `rhs` moves towards the thumb of the same hand. Fingers are
+    /// ordered `LP..=LT` on the left hand and `RT..=RP` on the right, so on the left hand inward
+    /// means a higher finger and on the right hand a lower one.
     const fn is_inward(self, rhs: Self) -> bool {
-        matches!(
-            (self.0, rhs.0),
-            (LP, LR | LM | LI | LT)
-                | (RP, RP | RM | RI | RT)
-                | (LR, LM | LI | LT)
-                | (RR, RM | RI | RT)
-                | (LM, LI | LT)
-                | (RM, RI | RT)
-                | (LI, LT)
-                | (RI, RT)
-        )
+        match (self.hand(), rhs.hand()) {
+            (Hand::Left, Hand::Left) => (self.0 as u8) < (rhs.0 as u8),
+            (Hand::Right, Hand::Right) => (self.0 as u8) > (rhs.0 as u8),
+            _ => false,
+        }
+    }
+
+    const fn is_outward(self, rhs: Self) -> bool {
+        rhs.is_inward(self)
     }
 }
 
@@ -77,6 +118,12 @@ impl Trigram {
         Self([Finger(f1), Finger(f2), Finger(f3)])
     }
 
+    const fn is_same_hand(&self) -> bool {
+        let [f1, f2, f3] = self.0;
+
+        f1.hand().eq(f2.hand()) && f2.hand().eq(f3.hand())
+    }
+
     const fn is_sft(&self) -> bool {
         let [f1, f2, f3] = self.0;
 
@@ -98,9 +145,9 @@ impl Trigram {
 
     const fn is_outroll(&self) -> bool {
         let [f1, f2, f3] = self.0;
+        let [h1, h2, h3] = [f1.hand(), f2.hand(), f3.hand()];
 
-        f1.hand().eq(f2.hand()) && !f2.hand().eq(f3.hand()) && !f1.is_inward(f2)
-            || f2.hand().eq(f3.hand()) && !f1.hand().eq(f2.hand()) && !f2.is_inward(f3)
+        h1.eq(h2) && !h2.eq(h3) && f1.is_outward(f2) || h2.eq(h3) && !h1.eq(h2) && f2.is_outward(f3)
     }
 
     const fn is_alternate(&self) -> bool {
@@ -112,19 +159,20 @@ impl Trigram {
     const fn is_redirect(&self) -> bool {
         let [f1, f2, f3] = self.0;
 
-        (f1.is_inward(f2) && !f2.is_inward(f3)) || (!f1.is_inward(f2) && f2.is_inward(f3))
+        self.is_same_hand()
+            && (f1.is_inward(f2) && f2.is_outward(f3) || f1.is_outward(f2) && f2.is_inward(f3))
     }
 
     const fn is_onehandin(&self) -> bool {
         let [f1, f2, f3] = self.0;
 
-        f1.is_inward(f2) && f2.is_inward(f3)
+        self.is_same_hand() && f1.is_inward(f2) && f2.is_inward(f3)
     }
 
     const fn is_onehandout(&self) -> bool {
         let [f1, f2, f3] = self.0;
 
-        !(f1.is_inward(f2) || f2.is_inward(f3))
+        self.is_same_hand() && f1.is_outward(f2) && f2.is_outward(f3)
     }
 
     const fn is_thumb(&self) -> bool {
@@ -132,69 +180,407 @@ impl Trigram {
 
         f1.is_thumb() || f2.is_thumb() || f3.is_thumb()
     }
+
+    const fn has_index(&self) -> bool {
+        let [f1, f2, f3] = self.0;
+
+        f1.is_index() || f2.is_index() || f3.is_index()
+    }
+
+    const fn is_non_index(&self) -> bool {
+        let [f1, f2, f3] = self.0;
+
+        f1.is_non_index() && f2.is_non_index() && f3.is_non_index()
+    }
+
+    const fn matches(&self, pattern: TrigramPattern) -> bool {
+        use TrigramPattern::*;
+
+        match pattern {
+            Sft => self.is_sft(),
+            Sfb => self.is_sfb(),
+            Inroll => self.is_inroll(),
+            Outroll => self.is_outroll(),
+            Alternate => self.is_alternate(),
+            Redirect => self.is_redirect(),
+            OnehandIn => self.is_onehandin(),
+            OnehandOut => self.is_onehandout(),
+            Thumb => self.is_thumb(),
+            Index => self.has_index(),
+            NoIndex => self.is_non_index(),
+        }
+    }
+}
+
+/// Finger triple, written as for example `"LP LR LM"` in config files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, SerializeDisplay, DeserializeFromStr)]
+pub struct FingerTriple(pub [DofFinger; 3]);
+
+impl FingerTriple {
+    pub const fn index(&self) -> usize {
+        let [f1, f2, f3] = self.0;
+
+        f1 as usize * 100 + f2 as usize * 10 + f3 as usize
+    }
+}
+
+impl PartialOrd for FingerTriple {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for FingerTriple {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.index().cmp(&other.index())
+    }
+}
+
+impl std::str::FromStr for FingerTriple {
+    type Err = OxeylyzerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |f: &str| {
+            DofFinger::FINGERS
+                .into_iter()
+                .find(|finger| format!("{finger:?}").eq_ignore_ascii_case(f))
+                .ok_or_else(|| OxeylyzerError::InvalidFingerTriple(s.to_owned()))
+        };
+
+        let fingers = s
+            .split_whitespace()
+            .map(parse)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        fingers
+            .try_into()
+            .map(Self)
+            .map_err(|_| OxeylyzerError::InvalidFingerTriple(s.to_owned()))
+    }
+}
+
+impl std::fmt::Display for FingerTriple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let [f1, f2, f3] = self.0;
+
+        write!(f, "{f1:?} {f2:?} {f3:?}")
+    }
 }
 
-pub const fn trigrams() -> [TrigramType; 1000] {
-    use TrigramType::*;
-
-    let mut res = [Invalid; 1000];
-
-    let mut i = 0;
-    while i < 10 {
-        let mut j = 0;
-        while j < 10 {
-            let mut k = 0;
-            while k < 10 {
-                let fs = Trigram::new([
-                    DofFinger::FINGERS[i],
-                    DofFinger::FINGERS[j],
-                    DofFinger::FINGERS[k],
-                ]);
-
-                res[i * 100 + j * 10 + k] = if fs.is_thumb() {
-                    Thumb
-                } else if fs.is_sft() {
-                    Sft
-                } else if fs.is_sfb() {
-                    Sfb
-                } else if fs.is_inroll() {
-                    Inroll
-                } else if fs.is_outroll() {
-                    Outroll
-                } else if fs.is_alternate() {
-                    Alternate
-                } else if fs.is_redirect() {
-                    Redirect
-                } else if fs.is_onehandin() {
-                    OnehandIn
-                } else if fs.is_onehandout() {
-                    OnehandOut
-                } else {
-                    Invalid
-                };
-
-                k += 1;
+/// A finger triple is in `category` if it matches every one of `patterns`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrigramRule {
+    pub category: String,
+    pub patterns: Vec<TrigramPattern>,
+}
+
+impl TrigramRule {
+    fn new(category: &str, patterns: &[TrigramPattern]) -> Self {
+        Self {
+            category: category.to_owned(),
+            patterns: patterns.to_vec(),
+        }
+    }
+}
+
+/// Describes how finger triples are classified. Every triple is put in the category of the first
+/// rule it matches, unless it's listed in `triples`, which takes precedence over any rule.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrigramTableConfig {
+    pub rules: Vec<TrigramRule>,
+    pub triples: BTreeMap<FingerTriple, String>,
+}
+
+impl Default for TrigramTableConfig {
+    fn default() -> Self {
+        use TrigramPattern::*;
+
+        let rules = vec![
+            TrigramRule::new("thumb", &[Thumb]),
+            TrigramRule::new("sft", &[Sft]),
+            TrigramRule::new("sfb", &[Sfb]),
+            TrigramRule::new("inroll", &[Inroll]),
+            TrigramRule::new("outroll", &[Outroll]),
+            TrigramRule::new("alternate", &[Alternate]),
+            TrigramRule::new("redirect", &[Redirect]),
+            TrigramRule::new("onehandin", &[OnehandIn]),
+            TrigramRule::new("onehandout", &[OnehandOut]),
+        ];
+
+        Self {
+            rules,
+            triples: BTreeMap::new(),
+        }
+    }
+}
+
+/// Category of every finger triple, indexed by `f1 * 100 + f2 * 10 + f3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrigramTable {
+    categories: Box<[String]>,
+    table: Box<[u16; 1000]>,
+}
+
+impl TrigramTable {
+    pub fn new(config: &TrigramTableConfig) -> Self {
+        let mut categories = Vec::<String>::new();
+
+        let mut category_index =
+            |category: &str| match categories.iter().position(|c| c == category) {
+                Some(i) => i as u16,
+                None => {
+                    categories.push(category.to_owned());
+                    (categories.len() - 1) as u16
+                }
+            };
+
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| (category_index(&rule.category), &rule.patterns))
+            .collect::<Vec<_>>();
+
+        let triples = config
+            .triples
+            .iter()
+            .map(|(triple, category)| (triple.index(), category_index(category)))
+            .collect::<Vec<_>>();
+
+        let invalid = category_index(INVALID_CATEGORY);
+
+        let mut table = Box::new([invalid; 1000]);
+
+        for f1 in DofFinger::FINGERS {
+            for f2 in DofFinger::FINGERS {
+                for f3 in DofFinger::FINGERS {
+                    let trigram = Trigram::new([f1, f2, f3]);
+                    let i = FingerTriple([f1, f2, f3]).index();
+
+                    if let Some(&(category, _)) = rules
+                        .iter()
+                        .find(|(_, patterns)| patterns.iter().all(|&p| trigram.matches(p)))
+                    {
+                        table[i] = category;
+                    }
+                }
             }
+        }
 
-            j += 1;
+        for (i, category) in triples {
+            table[i] = category;
         }
 
-        i += 1;
+        Self {
+            categories: categories.into(),
+            table,
+        }
+    }
+
+    pub fn categories(&self) -> &[String] {
+        &self.categories
+    }
+
+    /// Index of the category of the finger triple at index `i`.
+    #[inline]
+    pub fn get(&self, i: usize) -> usize {
+        self.table[i] as usize
+    }
+
+    pub fn category(&self, fingers: [DofFinger; 3]) -> &str {
+        &self.categories[self.get(FingerTriple(fingers).index())]
     }
 
-    res
+    pub fn map<T: Copy>(&self, f: impl Fn(&str) -> T) -> Box<[T; 1000]> {
+        let categories = self.categories.iter().map(|c| f(c)).collect::<Vec<_>>();
+
+        Box::new(std::array::from_fn(|i| categories[self.get(i)]))
+    }
 }
 
-pub const TRIGRAMS: [TrigramType; 1000] = trigrams();
+impl Default for TrigramTable {
+    fn default() -> Self {
+        Self::new(&TrigramTableConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_triples() -> impl Iterator<Item = [DofFinger; 3]> {
+        DofFinger::FINGERS.into_iter().flat_map(|f1| {
+            DofFinger::FINGERS
+                .into_iter()
+                .flat_map(move |f2| DofFinger::FINGERS.into_iter().map(move |f3| [f1, f2, f3]))
+        })
+    }
 
-#[test]
-fn print() {
-    for f1 in DofFinger::FINGERS {
-        for f2 in DofFinger::FINGERS {
-            for f3 in DofFinger::FINGERS {
-                let t = TRIGRAMS[f1 as usize * 100 + f2 as usize * 10 + f3 as usize];
-                println!("{f1} {f2} {f3}: {t:?}");
+    fn mirror(fingers: [DofFinger; 3]) -> [DofFinger; 3] {
+        fingers.map(|f| DofFinger::FINGERS[9 - f as usize])
+    }
+
+    fn reverse([f1, f2, f3]: [DofFinger; 3]) -> [DofFinger; 3] {
+        [f3, f2, f1]
+    }
+
+    fn hand_switches(fingers: [DofFinger; 3]) -> usize {
+        let [h1, h2, h3] = fingers.map(|f| Finger(f).hand() as u8);
+
+        (h1 != h2) as usize + (h2 != h3) as usize
+    }
+
+    #[test]
+    fn inward() {
+        let inward = |f1, f2| Finger(f1).is_inward(Finger(f2));
+
+        assert!(inward(LP, LR));
+        assert!(inward(LR, LI));
+        assert!(inward(RP, RR));
+        assert!(inward(RM, RI));
+        assert!(!inward(RP, RP));
+        assert!(!inward(RR, RP));
+        assert!(!inward(LI, LM));
+        assert!(!inward(LI, RI));
+        assert!(!inward(LP, RT));
+    }
+
+    #[test]
+    fn default_counts() {
+        let table = TrigramTable::default();
+
+        let count = |category: &str| {
+            all_triples()
+                .filter(|&fingers| table.category(fingers) == category)
+                .count()
+        };
+
+        assert_eq!(count("thumb"), 488);
+        assert_eq!(count("sft"), 8);
+        assert_eq!(count("sfb"), 112);
+        assert_eq!(count("inroll"), 96);
+        assert_eq!(count("outroll"), 96);
+        assert_eq!(count("alternate"), 128);
+        assert_eq!(count("redirect"), 56);
+        assert_eq!(count("onehandin"), 8);
+        assert_eq!(count("onehandout"), 8);
+        assert_eq!(count(INVALID_CATEGORY), 0);
+    }
+
+    #[test]
+    fn default_is_mirror_symmetric() {
+        let table = TrigramTable::default();
+
+        for fingers in all_triples() {
+            assert_eq!(
+                table.category(fingers),
+                table.category(mirror(fingers)),
+                "{fingers:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn default_reversed() {
+        let table = TrigramTable::default();
+
+        for fingers in all_triples() {
+            let expected = match table.category(fingers) {
+                "inroll" => "outroll",
+                "outroll" => "inroll",
+                "onehandin" => "onehandout",
+                "onehandout" => "onehandin",
+                category => category,
+            };
+
+            assert_eq!(table.category(reverse(fingers)), expected, "{fingers:?}");
+        }
+    }
+
+    #[test]
+    fn default_hands() {
+        let table = TrigramTable::default();
+
+        for fingers in all_triples().filter(|f| !f.iter().any(|f| f.is_thumb())) {
+            let switches = hand_switches(fingers);
+
+            match table.category(fingers) {
+                "inroll" | "outroll" => assert_eq!(switches, 1, "{fingers:?}"),
+                "alternate" => assert_eq!(switches, 2, "{fingers:?}"),
+                "redirect" | "onehandin" | "onehandout" | "sft" => {
+                    assert_eq!(switches, 0, "{fingers:?}")
+                }
+                "sfb" => assert!(switches <= 1, "{fingers:?}"),
+                category => panic!("{fingers:?} is {category}"),
             }
         }
     }
+
+    #[test]
+    fn patterns() {
+        let t = |fingers| Trigram::new(fingers);
+
+        assert!(t([LP, LR, LM]).is_onehandin());
+        assert!(t([RP, RR, RM]).is_onehandin());
+        assert!(t([LM, LR, LP]).is_onehandout());
+        assert!(!t([LP, LR, RM]).is_onehandin());
+        assert!(!t([LP, LR, RP]).is_redirect());
+        assert!(t([LR, LP, LM]).is_redirect());
+        assert!(t([RR, RP, RM]).is_redirect());
+        assert!(t([LP, LR, RM]).is_inroll());
+        assert!(t([RM, LR, LP]).is_outroll());
+        assert!(!t([LP, LP, RM]).is_outroll());
+        assert!(t([LR, LP, LM]).is_non_index());
+        assert!(!t([LR, LP, LI]).is_non_index());
+        assert!(t([LR, LP, LI]).has_index());
+    }
+
+    #[test]
+    fn custom_table() {
+        let config = toml::from_str::<TrigramTableConfig>(
+            r#"
+            rules = [
+                { category = "bad-redirect", patterns = ["redirect", "no-index"] },
+                { category = "redirect", patterns = ["redirect"] },
+            ]
+
+            [triples]
+            "LI LM RI" = "alternate"
+            "#,
+        )
+        .unwrap();
+
+        let table = TrigramTable::new(&config);
+
+        assert_eq!(
+            table.categories(),
+            ["bad-redirect", "redirect", "alternate", INVALID_CATEGORY]
+        );
+        assert_eq!(table.category([LR, LP, LM]), "bad-redirect");
+        assert_eq!(table.category([LI, LP, LM]), "redirect");
+        assert_eq!(table.category([LI, LM, RI]), "alternate");
+        assert_eq!(table.category([LI, RI, LI]), INVALID_CATEGORY);
+
+        assert!("LI LM".parse::<FingerTriple>().is_err());
+        assert!("LI LM XX".parse::<FingerTriple>().is_err());
+    }
+
+    #[test]
+    fn many_categories() {
+        let config = TrigramTableConfig {
+            rules: vec![],
+            triples: all_triples()
+                .take(400)
+                .enumerate()
+                .map(|(i, fingers)| (FingerTriple(fingers), format!("category-{i}")))
+                .collect(),
+        };
+
+        let table = TrigramTable::new(&config);
+
+        assert_eq!(table.categories().len(), 401);
+        for (i, fingers) in all_triples().take(400).enumerate() {
+            assert_eq!(table.category(fingers), format!("category-{i}"));
+        }
+    }
 }
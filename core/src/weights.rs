@@ -1,10 +1,18 @@
+use std::collections::BTreeMap;
+
 use libdof::dofinitions::Finger;
 use serde::{Deserialize, Serialize};
 
+use crate::{trigrams::TrigramTableConfig, OxeylyzerError, Result};
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Weights {
     pub sfbs: i64,
     pub sfs: i64,
+    #[serde(default)]
+    pub lsbs: i64,
+    #[serde(default)]
+    pub scissors: i64,
     pub sft: i64,
     pub inroll: i64,
     pub outroll: i64,
@@ -13,7 +21,17 @@ pub struct Weights {
     pub onehandin: i64,
     pub onehandout: i64,
     pub thumb: i64,
+    /// Weights of trigram categories by name. These take precedence over the fields above, and
+    /// are the only way to weigh categories added through `trigram_table`.
+    #[serde(default)]
+    pub trigram_categories: BTreeMap<String, i64>,
+    #[serde(default)]
+    pub trigram_table: TrigramTableConfig,
     pub fingers: FingerWeights,
+    #[serde(default)]
+    pub finger_usage: FingerUsageTargets,
+    #[serde(default)]
+    pub effort: EffortWeights,
 }
 
 impl Weights {
@@ -21,8 +39,9 @@ impl Weights {
         self.sfbs != 0 || self.sfs != 0
     }
 
-    pub const fn has_trigram_weights(&self) -> bool {
-        self.sft != 0
+    pub fn has_trigram_weights(&self) -> bool {
+        self.trigram_categories.values().any(|&w| w != 0)
+            || self.sft != 0
             || self.inroll != 0
             || self.outroll != 0
             || self.alternate != 0
@@ -31,6 +50,31 @@ impl Weights {
             || self.onehandout != 0
             || self.thumb != 0
     }
+
+    /// Hash of every weight, to check whether layouts were scored with the same weights.
+    pub fn hash(&self) -> u64 {
+        let weights = serde_json::to_string(self).expect("weights can always be serialized");
+
+        fxhash::hash64(&weights)
+    }
+
+    pub fn trigram_weight(&self, category: &str) -> i64 {
+        if let Some(&weight) = self.trigram_categories.get(category) {
+            return weight;
+        }
+
+        match category {
+            "sft" => self.sft,
+            "inroll" => self.inroll,
+            "outroll" => self.outroll,
+            "alternate" => self.alternate,
+            "redirect" => self.redirect,
+            "onehandin" => self.onehandin,
+            "onehandout" => self.onehandout,
+            "thumb" => self.thumb,
+            _ => 0,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -84,10 +128,76 @@ impl Default for FingerWeights {
     }
 }
 
+/// Target usage per finger and for the left hand, in percent of all characters. Deviating from a
+/// target is penalized by `weight` per unit of frequency. Fingers without a target are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FingerUsageTargets {
+    pub weight: i64,
+    pub lp: Option<f64>,
+    pub lr: Option<f64>,
+    pub lm: Option<f64>,
+    pub li: Option<f64>,
+    pub lt: Option<f64>,
+    pub rt: Option<f64>,
+    pub ri: Option<f64>,
+    pub rm: Option<f64>,
+    pub rr: Option<f64>,
+    pub rp: Option<f64>,
+    pub left_hand: Option<f64>,
+}
+
+impl FingerUsageTargets {
+    #[inline]
+    pub const fn get(&self, f: Finger) -> Option<f64> {
+        use Finger::*;
+
+        match f {
+            LP => self.lp,
+            LR => self.lr,
+            LM => self.lm,
+            LI => self.li,
+            LT => self.lt,
+            RT => self.rt,
+            RI => self.ri,
+            RM => self.rm,
+            RR => self.rr,
+            RP => self.rp,
+        }
+    }
+}
+
+/// Weight of the per-key effort map. Effort is generated from the physical position and finger of
+/// every key, unless `boards` contains a map for the board of the layout. Such a map lists the
+/// effort of every key in key units, in the same order as the keys of the layout.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffortWeights {
+    pub weight: i64,
+    pub boards: BTreeMap<String, Vec<f64>>,
+}
+
+impl EffortWeights {
+    /// The effort map of `board` if there is one, or an error if it doesn't have an entry for
+    /// every one of the `keys` keys of the layout.
+    pub fn board(&self, board: &str, keys: usize) -> Result<Option<&[f64]>> {
+        match self.boards.get(board) {
+            Some(effort) if effort.len() != keys => Err(OxeylyzerError::EffortBoardLength(
+                board.into(),
+                effort.len(),
+                keys,
+            )),
+            effort => Ok(effort.map(Vec::as_slice)),
+        }
+    }
+}
+
 pub fn dummy_weights() -> Weights {
     Weights {
         sfbs: -7,
         sfs: -1,
+        lsbs: -5,
+        scissors: -5,
         sft: -12,
         inroll: 5,
         outroll: 4,
@@ -96,6 +206,8 @@ pub fn dummy_weights() -> Weights {
         onehandin: 1,
         onehandout: 0,
         thumb: 0,
+        trigram_categories: Default::default(),
+        trigram_table: Default::default(),
         fingers: FingerWeights {
             lp: 77,
             lr: 32,
@@ -108,5 +220,21 @@ pub fn dummy_weights() -> Weights {
             rr: 32,
             rp: 77,
         },
+        finger_usage: FingerUsageTargets {
+            weight: -1000,
+            lp: Some(7.0),
+            lr: Some(9.0),
+            lm: Some(13.0),
+            li: Some(14.0),
+            ri: Some(14.0),
+            rm: Some(13.0),
+            rr: Some(9.0),
+            rp: Some(7.0),
+            ..Default::default()
+        },
+        effort: EffortWeights {
+            weight: -2,
+            ..Default::default()
+        },
     }
 }
@@ -0,0 +1,267 @@
+use oxeylyzer_core::{
+    leaderboard::{Leaderboard, LeaderboardEntry},
+    pareto::{ParetoEntry, ParetoFront},
+    prelude::Layout,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use crate::{flags, Result};
+
+/// Minimum time between two checkpoints of a generation.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// State of a generation that can be saved to a file and resumed later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Name of the corpus the layouts were generated for.
+    pub corpus: String,
+    /// Hash of the weights the layouts were scored with.
+    pub weights: u64,
+    /// Hash of the layout the layouts were generated from, with the constraints of the config.
+    #[serde(default)]
+    pub layout: u64,
+    pub settings: GenSettings,
+    pub finished: FinishedRuns,
+    /// Score of every finished run.
+    pub scores: Vec<i64>,
+    pub leaderboard: Vec<SavedLayout>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub front: Vec<SavedLayout>,
+    /// Amount of runs the Pareto front was made from, which stays the same when the scores of
+    /// earlier runs are left out.
+    #[serde(default)]
+    pub front_runs: usize,
+}
+
+impl Checkpoint {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let s = fs::read_to_string(path)?;
+
+        serde_json::from_str(&s).map_err(Into::into)
+    }
+
+    /// Saves the checkpoint to a temporary file first, so a checkpoint that was saved before
+    /// stays intact if saving is interrupted.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+
+        fs::write(&tmp, serde_json::to_string(self)?)?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+}
+
+/// Results of a generation so far.
+#[derive(Debug, Clone)]
+pub struct GenState {
+    pub leaderboard: Leaderboard,
+    pub front: Option<ParetoFront>,
+    pub finished: FinishedRuns,
+}
+
+impl GenState {
+    pub fn checkpoint(
+        &self,
+        corpus: &str,
+        weights: u64,
+        layout: u64,
+        settings: GenSettings,
+    ) -> Checkpoint {
+        Checkpoint {
+            corpus: corpus.into(),
+            weights,
+            layout,
+            settings,
+            finished: self.finished.clone(),
+            scores: self.leaderboard.scores().to_vec(),
+            leaderboard: self.leaderboard.entries().iter().map(Into::into).collect(),
+            front: self
+                .front
+                .iter()
+                .flat_map(|f| f.entries())
+                .map(Into::into)
+                .collect(),
+            front_runs: self.front.as_ref().map_or(0, ParetoFront::runs),
+        }
+    }
+}
+
+/// Settings of `gen` with every default filled in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GenSettings {
+    /// Name of the layout to use as a basis.
+    pub name: String,
+    pub count: Option<usize>,
+    /// Time that is left to generate for.
+    pub time: Option<Duration>,
+    pub pins: Option<String>,
+    pub method: Option<String>,
+    pub seed: u64,
+    pub top: usize,
+    pub pareto: Option<String>,
+    pub equivalent: Option<String>,
+}
+
+impl GenSettings {
+    pub fn new(gen: flags::Gen, time: Option<Duration>, seed: u64) -> Self {
+        let count = match (gen.count, time) {
+            (None, Some(_)) => None,
+            (count, _) => Some(count.unwrap_or(10)),
+        };
+
+        Self {
+            name: gen.name,
+            count,
+            time,
+            pins: gen.pins,
+            method: gen.method,
+            seed,
+            top: gen.top.unwrap_or(10),
+            pareto: gen.pareto,
+            equivalent: gen.equivalent,
+        }
+    }
+}
+
+/// Indices of the runs that finished, stored as every run before `before` and the runs after it
+/// that finished out of order.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FinishedRuns {
+    pub before: usize,
+    pub after: BTreeSet<usize>,
+}
+
+impl FinishedRuns {
+    pub fn insert(&mut self, run: usize) {
+        self.after.insert(run);
+
+        while self.after.remove(&self.before) {
+            self.before += 1;
+        }
+    }
+
+    pub fn contains(&self, run: usize) -> bool {
+        run < self.before || self.after.contains(&run)
+    }
+
+    /// Amount of finished runs with an index below `end`.
+    pub fn count_below(&self, end: usize) -> usize {
+        self.before.min(end) + self.after.range(..end).count()
+    }
+}
+
+/// A layout on the leaderboard or Pareto front, without the parts it shares with the layout it was
+/// generated from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedLayout {
+    pub keys: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub magic_rules: BTreeMap<char, char>,
+    pub score: i64,
+    pub seed: u64,
+    pub found: usize,
+    /// Values of the objectives of the Pareto front.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<f64>,
+}
+
+impl SavedLayout {
+    fn new(layout: &Layout, score: i64, seed: u64, found: usize, values: Vec<f64>) -> Self {
+        Self {
+            keys: layout.keys.iter().collect(),
+            layers: layout
+                .layers
+                .iter()
+                .map(|l| l.keys.iter().collect())
+                .collect(),
+            magic_rules: layout.magic_rules.clone(),
+            score,
+            seed,
+            found,
+            values,
+        }
+    }
+
+    /// The saved layout, or `None` if it doesn't fit on `base`.
+    pub fn layout(&self, base: &Layout) -> Option<Layout> {
+        let keys = self.keys.chars().collect::<Box<[char]>>();
+        if keys.len() != base.keys.len() || self.layers.len() != base.layers.len() {
+            return None;
+        }
+
+        let mut layout = base.clone();
+        layout.keys = keys;
+        layout.magic_rules = self.magic_rules.clone();
+
+        for (layer, keys) in layout.layers.iter_mut().zip(&self.layers) {
+            layer.keys = keys.chars().collect();
+            if layer.keys.len() != layout.keys.len() {
+                return None;
+            }
+        }
+
+        Some(layout)
+    }
+
+    pub fn leaderboard_entry(&self, base: &Layout) -> Option<LeaderboardEntry> {
+        Some(LeaderboardEntry {
+            layout: self.layout(base)?,
+            score: self.score,
+            seed: self.seed,
+            found: self.found,
+        })
+    }
+
+    pub fn pareto_entry(&self, base: &Layout) -> Option<ParetoEntry> {
+        Some(ParetoEntry {
+            layout: self.layout(base)?,
+            score: self.score,
+            values: self.values.clone(),
+            seed: self.seed,
+            found: self.found,
+        })
+    }
+}
+
+impl From<&LeaderboardEntry> for SavedLayout {
+    fn from(e: &LeaderboardEntry) -> Self {
+        Self::new(&e.layout, e.score, e.seed, e.found, vec![])
+    }
+}
+
+impl From<&ParetoEntry> for SavedLayout {
+    fn from(e: &ParetoEntry) -> Self {
+        Self::new(&e.layout, e.score, e.seed, e.found, e.values.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finished_runs() {
+        let mut finished = FinishedRuns::default();
+
+        for run in [1, 0, 4, 2, 6] {
+            finished.insert(run);
+        }
+
+        assert_eq!(finished.before, 3);
+        assert_eq!(finished.after, BTreeSet::from([4, 6]));
+        assert!(finished.contains(2) && finished.contains(4));
+        assert!(!finished.contains(3) && !finished.contains(5));
+        assert_eq!(finished.count_below(5), 4);
+        assert_eq!(finished.count_below(2), 2);
+    }
+}
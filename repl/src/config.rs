@@ -1,7 +1,10 @@
-use oxeylyzer_core::prelude::Weights;
+use oxeylyzer_core::{constraints::Constraints, prelude::Weights};
 use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, OneOrMany};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 use crate::Result;
 
@@ -12,6 +15,12 @@ pub struct Config {
     pub corpus: String,
     #[serde_as(as = "OneOrMany<_>")]
     pub layouts: Vec<PathBuf>,
+    /// Magic rules for layouts with a magic key, by the char typed before the magic key.
+    #[serde(default)]
+    pub magic_rules: BTreeMap<char, char>,
+    /// Constraints on where chars can be placed when generating layouts.
+    #[serde(default)]
+    pub constraints: Constraints,
 }
 
 impl Config {
@@ -51,4 +60,44 @@ mod tests {
         assert!(p2.is_ok());
         assert!(p3.is_ok());
     }
+
+    #[test]
+    fn magic_rules() {
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        struct MagicRules {
+            magic_rules: BTreeMap<char, char>,
+        }
+
+        let s = "[magic_rules]\ne = \"o\"\n\"'\" = \"s\"";
+        let rules = toml::from_str::<MagicRules>(s).unwrap().magic_rules;
+
+        assert_eq!(rules, BTreeMap::from_iter([('e', 'o'), ('\'', 's')]));
+        assert!(toml::from_str::<MagicRules>("[magic_rules]\nab = \"c\"").is_err());
+    }
+
+    #[test]
+    fn constraints() {
+        #[derive(Debug, Clone, Serialize, Deserialize)]
+        struct WithConstraints {
+            #[serde(default)]
+            constraints: Constraints,
+        }
+
+        let s = r#"
+            [constraints]
+            same_hand = ["th"]
+
+            [[constraints.regions]]
+            chars = "aeiou"
+            hand = "left"
+        "#;
+        let constraints = toml::from_str::<WithConstraints>(s).unwrap().constraints;
+
+        assert_eq!(constraints.same_hand, [['t', 'h']]);
+        assert_eq!(constraints.regions[0].chars, "aeiou");
+        assert!(toml::from_str::<WithConstraints>("")
+            .unwrap()
+            .constraints
+            .is_empty());
+    }
 }
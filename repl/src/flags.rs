@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 xflags::xflags! {
     cmd oxeylyzer {
         /// Analyze a layout.
@@ -11,10 +13,59 @@ xflags::xflags! {
         cmd gen g generate {
             /// Name of the layout to use as a basis.
             required name: String
-            /// Name of iterations to do. 10 by default.
+            /// Amount of layouts to generate. 10 by default, or unlimited when generating for a
+            /// set amount of time.
             optional count: usize
             /// Characters to pin. Setting pins speeds up generation.
             optional -p, --pins pins: String
+            /// Optimization method, alternative-d3 by default. One of greedy, greedy-d2,
+            /// greedy-d3, greedy-d4, alternative, alternative-d3, annealing, annealing-d2, genetic,
+            /// tabu or tempering. Annealing takes parameters after a colon, for example
+            /// annealing:iterations=200000,cooling=linear,temp=1e9,final-temp=1e5 or
+            /// annealing:time=10s. Temperatures that aren't set are calibrated automatically.
+            /// Genetic, tabu and tempering take parameters the same way, for example
+            /// genetic:population=24,generations=40,crossover=cycle,mutation=0.3,memetic=false,
+            /// tabu:iterations=1000,tenure=10 or tempering:chains=8,iterations=50000,interval=500.
+            /// Greedy depth methods can prune and search in parallel, like
+            /// greedy-d3:prune=true,parallel=true.
+            optional -m, --method method: String
+            /// Seed to generate with. Generating with the same seed, pins and method gives the
+            /// same layouts. Random by default.
+            optional -s, --seed seed: u64
+            /// Keep generating layouts for this long, like 60s or 5m.
+            optional -t, --time time: String
+            /// Amount of best layouts to show. 10 by default.
+            optional -n, --top top: usize
+            /// Keep the Pareto front over these stats instead of the best scores, like
+            /// sfbs,sfs,rolls,-redirect. Every run scales the weights of these stats by a random
+            /// factor to explore different tradeoffs. Stats can be prefixed by + to maximize them
+            /// or - to minimize them.
+            optional --pareto objectives: String
+            /// Count layouts that are equivalent as the same layout, only showing the best of
+            /// them. One or more of mirror, for mirrored hands, columns, for swapped columns typed
+            /// by the same finger, and score=<difference>, for scores at most that far apart.
+            /// For example mirror,columns,score=1000.
+            optional -e, --equivalent equivalences: String
+            /// Regularly save the progress of generating to this file, so it can be continued
+            /// with resume if it is stopped.
+            optional --checkpoint path: PathBuf
+        }
+        /// Continues generating from a checkpoint saved by gen.
+        cmd resume {
+            /// Path of the checkpoint.
+            required path: PathBuf
+            /// Resume even if the weights or the layout have changed since the checkpoint was
+            /// saved, scoring its layouts again.
+            optional -f, --force
+        }
+        /// Shows the Pareto front of the last generation that kept one.
+        cmd front {}
+        /// Loads a layout from the Pareto front, so it can be used like any other layout.
+        cmd pick {
+            /// Number of the layout on the front.
+            required index: usize
+            /// Name to load the layout as. pareto-<index> by default.
+            optional name: String
         }
         /// Shows the top n sfbs on a layout.
         cmd sfbs {
@@ -23,10 +74,32 @@ xflags::xflags! {
             /// Amount of sfbs to show. 10 by default.
             optional -c, --count count: usize
         }
+        /// Shows the top n full and half scissors on a layout.
+        cmd scissors {
+            /// Name of the layout to show scissors of.
+            required name: String
+            /// Amount of scissors to show. 10 by default.
+            optional -c, --count count: usize
+        }
         /// Shows every trigram stat of a layout.
         cmd trigrams t {
             required name: String
         }
+        /// Compares the stats of a layout with and without using its repeat key.
+        cmd repeat {
+            /// Name of the layout to compare. It must have a repeat key.
+            required name: String
+        }
+        /// Shows the magic rules of a layout and how many sfbs each of them removes.
+        cmd magic {
+            /// Name of the layout to show magic rules of. It must have a magic key.
+            required name: String
+        }
+        /// Shows the trigram category of every finger triple.
+        cmd trigram-table {
+            /// Only show finger triples in this category.
+            optional category: String
+        }
         /// Reload the analyzer config file
         cmd r reload refresh {}
         /// Quit the analyzer
@@ -1,15 +1,33 @@
+mod checkpoint;
 mod config;
 mod flags;
+mod progress;
 
+use checkpoint::{Checkpoint, FinishedRuns, GenSettings, GenState, CHECKPOINT_INTERVAL};
 use config::Config;
 use itertools::Itertools;
-use oxeylyzer_core::{cached_layout::BigramPair, prelude::*};
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use libdof::prelude::Finger;
+use oxeylyzer_core::{
+    cached_layout::BigramPair,
+    constraints::Constraints,
+    leaderboard::Leaderboard,
+    optimization::{parse_duration, random_seed, run_seed, OptimizationMethod},
+    pareto::{parse_objectives, ParetoFront},
+    prelude::*,
+};
+use progress::GenerationProgress;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     fs,
     io::Write as _,
     path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 use thiserror::Error;
 
@@ -17,6 +35,22 @@ use thiserror::Error;
 pub enum ReplError {
     #[error("Layout '{0}' not found. It might exist, but it's not currently loaded.")]
     UnknownLayout(String),
+    #[error("Layout '{0}' doesn't have a repeat key.")]
+    NoRepeatKey(String),
+    #[error("Layout '{0}' doesn't have a magic key.")]
+    NoMagicKey(String),
+    #[error("No Pareto front has been generated yet, use gen with --pareto first.")]
+    NoParetoFront,
+    #[error("The Pareto front doesn't have a layout #{0}.")]
+    UnknownFrontEntry(usize),
+    #[error("Checkpoint was made for corpus '{0}', but '{1}' is loaded.")]
+    CheckpointCorpus(String, String),
+    #[error("Weights have changed since the checkpoint was made, use --force to resume anyway.")]
+    CheckpointWeights,
+    #[error(
+        "Layout '{0}' has changed since the checkpoint was made, use --force to resume anyway."
+    )]
+    CheckpointLayout(String),
     #[error("Path '{0}' either doesn't exist or is not a directory")]
     NotADirectory(PathBuf),
     #[error("Invalid quotation marks")]
@@ -33,6 +67,8 @@ pub enum ReplError {
     TomlSerializeError(#[from] toml::ser::Error),
     #[error("{0}")]
     TomlDeserializeError(#[from] toml::de::Error),
+    #[error("{0}")]
+    JsonError(#[from] serde_json::Error),
 }
 
 pub type Result<T> = std::result::Result<T, ReplError>;
@@ -44,9 +80,14 @@ pub enum ReplStatus {
 }
 
 pub struct Repl {
-    a: Analyzer,
+    a: Arc<Analyzer>,
+    /// Analyzers for layouts with layers, a repeat key or a magic key, see [`Self::analyzer`].
+    analyzers: Mutex<HashMap<String, Arc<Analyzer>>>,
     layouts: HashMap<String, Layout>,
     config_path: PathBuf,
+    constraints: Constraints,
+    progress: Arc<GenerationProgress>,
+    front: Option<ParetoFront>,
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -57,7 +98,9 @@ impl Repl {
 
         let data = Data::load(&config.corpus)?;
 
-        let a = Analyzer::new(data, config.weights);
+        let progress = Arc::new(GenerationProgress::new());
+        let mut a = Analyzer::new(data, config.weights)?;
+        a.control.observer = Some(progress.clone());
 
         let layouts = config
             .layouts
@@ -67,12 +110,28 @@ impl Repl {
                     .inspect_err(|e| println!("Error loading layout at '{}': {e}", p.display()))
             })
             .flat_map(|h| h.into_iter())
+            .map(|(name, mut layout): (String, Layout)| {
+                if layout.keys.contains(&MAGIC_KEY) && layout.magic_rules.is_empty() {
+                    layout.magic_rules = config.magic_rules.clone();
+                }
+                (name, layout)
+            })
+            .inspect(|(name, layout)| {
+                let effort = |board| a.weights.effort.board(board, layout.keys.len());
+                if let Some(Err(e)) = layout.board.as_deref().map(effort) {
+                    println!("{e}, using generated effort for layout '{name}' instead.");
+                }
+            })
             .collect();
 
         Ok(Self {
-            a,
+            a: Arc::new(a),
+            analyzers: Default::default(),
             layouts,
             config_path,
+            constraints: config.constraints,
+            progress,
+            front: None,
         })
     }
 
@@ -82,13 +141,55 @@ impl Repl {
             .ok_or(ReplError::UnknownLayout(name.into()))
     }
 
+    /// Same as [`Analyzer::for_layout`], but keeps the analyzers it builds by what they change
+    /// about the corpus, so every command on the same layout doesn't build them again.
+    fn analyzer(&self, layout: &Layout) -> Result<Arc<Analyzer>> {
+        let sequences = layout
+            .key_sequences()
+            .into_iter()
+            .sorted()
+            .collect::<Vec<_>>();
+        let signature = format!(
+            "{:?}",
+            (
+                sequences,
+                layout.keys.contains(&REPEAT_KEY),
+                layout.keys.contains(&MAGIC_KEY),
+                &layout.magic_rules
+            )
+        );
+
+        let mut analyzers = self.analyzers.lock().unwrap();
+        if let Some(a) = analyzers.get(&signature) {
+            return Ok(a.clone());
+        }
+
+        let a = match self.a.for_layout(layout)? {
+            Cow::Borrowed(_) => self.a.clone(),
+            Cow::Owned(a) => Arc::new(a),
+        };
+        analyzers.insert(signature, a.clone());
+
+        Ok(a)
+    }
+
+    /// The layout to generate layouts from, with the constraints of the config.
+    fn base_layout(&self, name: &str) -> Result<Layout> {
+        Ok(Layout {
+            constraints: self.constraints.clone(),
+            ..self.layout(name)?.clone()
+        })
+    }
+
     fn analyze(&self, name: &str) -> Result<()> {
         let layout = self.layout(name)?;
-        let stats = self.a.stats(layout);
+        let a = self.analyzer(layout)?;
+        let stats = a.stats(layout);
 
         let finger_use = stats.finger_use.map(|f| format!("{f:.2}")).join(", ");
         let finger_sfbs = stats.finger_sfbs.map(|f| format!("{f:.2}")).join(", ");
-        let score = self.a.score(layout);
+        let row_use = &stats.row_use;
+        let score = a.score(layout);
 
         print!("{}", layout);
 
@@ -97,66 +198,445 @@ impl Repl {
                 "score:   {}\n\n",
                 "sfbs:    {:.3}%\n",
                 "sfs:     {:.3}%\n",
+                "lsbs:    {:.3}%\n",
+                "scissors: {:.3}%\n",
+                "half scissors: {:.3}%\n",
                 "finger usage:\n{}\n",
+                "finger usage deviation: {:.3}%\n",
+                "row usage: top {:.2}%, home {:.2}%, bottom {:.2}%, thumb {:.2}%\n",
+                "effort:  {:.3}\n",
                 "finger sfbs:\n{}\n"
             ),
-            score, stats.sfbs, stats.sfs, finger_use, finger_sfbs,
+            score,
+            stats.sfbs,
+            stats.sfs,
+            stats.lsbs,
+            stats.scissors,
+            stats.half_scissors,
+            finger_use,
+            stats.finger_use_deviation,
+            row_use.top,
+            row_use.home,
+            row_use.bottom,
+            row_use.thumb,
+            stats.effort,
+            finger_sfbs,
         );
 
         self.trigrams(name)
     }
 
-    fn rank(&self) {
+    fn rank(&self) -> Result<()> {
         self.layouts
             .iter()
             .map(|(n, l)| {
-                let s = self.a.score(l);
-                (n, s)
+                let s = self.analyzer(l)?.score(l);
+                Ok((n, s))
             })
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
             .sorted_by(|(_, a), (_, b)| a.cmp(b))
             .for_each(|(n, s)| println!("{n:<15} {s}"));
+
+        Ok(())
     }
 
-    fn generate(&self, name: &str, count: Option<usize>, pin_chars: Option<String>) -> Result<()> {
-        let layout = self.layout(name)?;
-        let count = count.unwrap_or(10);
-        let pins = match pin_chars {
-            Some(chars) => pin_positions(layout, chars),
+    /// Generates layouts, returning the Pareto front if one was kept.
+    fn generate(&self, gen: flags::Gen) -> Result<Option<ParetoFront>> {
+        let time = gen.time.as_deref().map(parse_duration).transpose()?;
+        let seed = gen.seed.unwrap_or_else(random_seed);
+        let path = gen.checkpoint.clone();
+
+        let checkpoint = Checkpoint {
+            corpus: self.a.data.name().into(),
+            weights: self.a.weights.hash(),
+            layout: self.base_layout(&gen.name)?.hash(),
+            settings: GenSettings::new(gen, time, seed),
+            finished: FinishedRuns::default(),
+            scores: vec![],
+            leaderboard: vec![],
+            front: vec![],
+            front_runs: 0,
+        };
+
+        self.run_generation(checkpoint, path.as_deref(), false)
+    }
+
+    /// Continues a generation from a checkpoint, refusing if it was made for a different corpus.
+    /// Checkpoints made with different weights or from a different layout are only resumed when
+    /// forced, rescoring the layouts in them and leaving out the scores of the earlier runs.
+    fn resume(&self, path: &Path, force: bool) -> Result<Option<ParetoFront>> {
+        let mut checkpoint = Checkpoint::load(path)?;
+        let corpus = self.a.data.name();
+
+        if checkpoint.corpus != corpus {
+            return Err(ReplError::CheckpointCorpus(
+                checkpoint.corpus,
+                corpus.into(),
+            ));
+        }
+
+        let base = self.base_layout(&checkpoint.settings.name)?;
+        let weights_changed = checkpoint.weights != self.a.weights.hash();
+        let layout_changed = checkpoint.layout != base.hash();
+
+        if !force {
+            if weights_changed {
+                return Err(ReplError::CheckpointWeights);
+            }
+            if layout_changed {
+                return Err(ReplError::CheckpointLayout(checkpoint.settings.name));
+            }
+        }
+
+        if weights_changed {
+            println!("weights have changed since the checkpoint, rescoring it.");
+        }
+        if layout_changed {
+            println!(
+                "the layout has changed since the checkpoint, rescoring the layouts that fit."
+            );
+            checkpoint
+                .leaderboard
+                .retain(|saved| saved.layout(&base).is_some());
+            checkpoint
+                .front
+                .retain(|saved| saved.layout(&base).is_some());
+        }
+
+        let rescore = weights_changed || layout_changed;
+        if rescore {
+            // scores of layouts that were scored differently can't be compared
+            checkpoint.scores.clear();
+        }
+
+        println!(
+            "resuming generating layouts from '{}' after {} runs.",
+            checkpoint.settings.name,
+            checkpoint.finished.count_below(usize::MAX)
+        );
+
+        self.run_generation(checkpoint, Some(path), rescore)
+    }
+
+    /// Generates layouts, continuing from the runs in `checkpoint` and saving to `path` while
+    /// generating. `rescore` scores the layouts in the checkpoint again.
+    fn run_generation(
+        &self,
+        checkpoint: Checkpoint,
+        path: Option<&Path>,
+        rescore: bool,
+    ) -> Result<Option<ParetoFront>> {
+        let Checkpoint {
+            corpus,
+            settings,
+            finished,
+            scores,
+            ..
+        } = &checkpoint;
+
+        let layout = &self.base_layout(&settings.name)?;
+        let method = match &settings.method {
+            Some(method) => method.parse::<OptimizationMethod>()?,
+            None => OptimizationMethod::default(),
+        };
+        let a = self.analyzer(layout)?;
+        let (count, time, seed) = (settings.count, settings.time, settings.seed);
+        let pins = match &settings.pins {
+            Some(chars) => pin_positions(layout, chars.clone()),
             None => vec![],
         };
+        layout.constraints.validate(layout, &pins)?;
+
+        let objectives = settings
+            .pareto
+            .as_deref()
+            .map(parse_objectives)
+            .transpose()?;
+        if let Some(objectives) = &objectives {
+            a.validate_objectives(objectives)?;
+        }
+        let equivalences = settings.equivalent.as_deref().unwrap_or_default().parse()?;
 
-        let start = std::time::Instant::now();
+        let changed = || ReplError::CheckpointLayout(settings.name.clone());
 
-        let mut layouts = Vec::with_capacity(count);
-        (0..count)
-            .into_par_iter()
-            .map(|_| {
-                let l = layout.random_with_pins(&pins);
-                self.a.alternative_d3(l, &pins)
-                // self.a.greedy_depth2_improve(l)
-                // .annealing_improve(starting_layout, 20_500_000_000_000.0, 0.987, 5000)
+        // every magic layout has its own rules, so it is scored by its own analyzer
+        let analyzer_for = |l: &Layout| -> Result<Cow<Analyzer>> {
+            match l.keys.contains(&MAGIC_KEY) {
+                true => Ok(self.a.for_layout(l)?),
+                false => Ok(Cow::Borrowed(&*a)),
+            }
+        };
+
+        let mut leaderboard = Leaderboard::new(settings.top).with_equivalences(equivalences);
+        let entries = checkpoint
+            .leaderboard
+            .iter()
+            .map(|saved| {
+                let mut entry = saved.leaderboard_entry(layout).ok_or_else(changed)?;
+                if rescore {
+                    entry.score = analyzer_for(&entry.layout)?.score(&entry.layout);
+                }
+                Ok(entry)
             })
-            .collect_into_vec(&mut layouts);
+            .collect::<Result<Vec<_>>>()?;
+        leaderboard.extend(entries, scores);
+
+        let mut front = objectives.clone().map(ParetoFront::new);
+        if let (Some(front), Some(objectives)) = (&mut front, &objectives) {
+            let entries = checkpoint
+                .front
+                .iter()
+                .map(|saved| {
+                    let mut entry = saved.pareto_entry(layout).ok_or_else(changed)?;
+                    if rescore {
+                        let a = analyzer_for(&entry.layout)?;
+                        entry.score = a.score(&entry.layout);
+                        entry.values = a.objective_values(&entry.layout, objectives);
+                    }
+                    Ok(entry)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            front.extend(entries, checkpoint.front_runs);
+        }
 
-        layouts.sort_by(|(_, s1), (_, s2)| s2.cmp(s1));
+        let weights = self.a.weights.hash();
+        let layout_hash = layout.hash();
+        let state = Mutex::new(GenState {
+            leaderboard,
+            front,
+            finished: finished.clone(),
+        });
+        let last_save = Mutex::new(Instant::now());
+        let save = |state: &GenState, start: Instant| {
+            let Some(path) = path else {
+                return;
+            };
+
+            let settings = GenSettings {
+                time: time.map(|t| t.saturating_sub(start.elapsed())),
+                ..settings.clone()
+            };
+            let checkpoint = state.checkpoint(corpus, weights, layout_hash, settings);
+
+            if let Err(e) = checkpoint.save(path) {
+                println!("failed to save checkpoint to '{}': {e}", path.display());
+            }
+            *last_save.lock().unwrap() = Instant::now();
+        };
+
+        let next_run = AtomicUsize::new(0);
+        let done = AtomicBool::new(false);
+        let timed_out = AtomicBool::new(false);
+
+        let start = Instant::now();
+
+        self.a.control.cancellation.reset();
+        self.progress
+            .start(count.map(|c| c - finished.count_below(c)), time);
+
+        let result = std::thread::scope(|s| {
+            if let Some(time) = time {
+                let (done, timed_out) = (&done, &timed_out);
+
+                s.spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        match time.checked_sub(start.elapsed()) {
+                            Some(left) => std::thread::sleep(left.min(Duration::from_millis(50))),
+                            None => {
+                                timed_out.store(true, Ordering::Relaxed);
+                                self.a.control.cancellation.cancel();
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+
+            let result: Result<()> = (0..rayon::current_num_threads())
+                .into_par_iter()
+                .try_for_each(|_| loop {
+                    let i = next_run.fetch_add(1, Ordering::Relaxed);
+                    if a.control.is_cancelled() || count.is_some_and(|count| i >= count) {
+                        break Ok(());
+                    }
+                    if finished.contains(i) {
+                        continue;
+                    }
+
+                    let seed = run_seed(seed, i);
+                    let l = layout.random_with_seed(&pins, seed);
+
+                    let (l, score) = match (l.keys.contains(&MAGIC_KEY), &objectives) {
+                        (true, None) => self.a.magic_improve(l, &pins, &method, seed)?,
+                        (true, Some(objectives)) => {
+                            let scaled = self.a.with_scaled_weights(objectives, seed);
+                            let (l, _) = scaled.magic_improve(l, &pins, &method, seed)?;
+                            let score = analyzer_for(&l)?.score(&l);
+                            (l, score)
+                        }
+                        (false, None) => method.optimize(&a, l, &pins, seed),
+                        (false, Some(objectives)) => {
+                            let scaled = a.with_scaled_weights(objectives, seed);
+                            let (l, _) = method.optimize(&scaled, l, &pins, seed);
+                            let score = a.score(&l);
+                            (l, score)
+                        }
+                    };
+
+                    // runs that are cut short by the time budget are left out
+                    if timed_out.load(Ordering::Relaxed) {
+                        break Ok(());
+                    }
+
+                    let values = match &objectives {
+                        Some(objectives) => {
+                            Some(analyzer_for(&l)?.objective_values(&l, objectives))
+                        }
+                        None => None,
+                    };
+
+                    {
+                        let mut state = state.lock().unwrap();
+                        if let (Some(front), Some(values)) = (&mut state.front, values) {
+                            front.insert(l.clone(), score, values, seed);
+                        }
+
+                        state.leaderboard.insert(l, score, seed);
+                        state.finished.insert(i);
+
+                        if last_save.lock().unwrap().elapsed() >= CHECKPOINT_INTERVAL {
+                            save(&state, start);
+                        }
+                    }
+
+                    self.progress.run_finished(score);
+                });
+
+            done.store(true, Ordering::Relaxed);
+            result
+        });
+
+        self.progress.finish();
+
+        let state = state.into_inner().unwrap();
+        save(&state, start);
+        result?;
+
+        let GenState {
+            leaderboard, front, ..
+        } = state;
+        let runs = leaderboard.runs();
+
+        let entries = match &front {
+            Some(front) => {
+                print_front(front);
+                &[]
+            }
+            None => leaderboard.entries(),
+        };
 
-        for (i, (mut layout, score)) in layouts.into_iter().enumerate().take(10) {
+        for (i, entry) in entries.iter().enumerate() {
+            let mut layout = entry.layout.clone();
             layout.name = "".into();
-            println!("#{}, score: {}{}", i, score, layout);
+            let violations = match layout.constraints.violations(&layout) {
+                0 => String::new(),
+                n => format!(", violates {n} constraints"),
+            };
+            println!(
+                "#{}, score: {}, seed: {}, found {} times{}{}",
+                i, entry.score, entry.seed, entry.found, violations, layout
+            );
         }
 
-        println!(
-            "generating {} variants took {:.2} seconds.",
-            count,
-            start.elapsed().as_secs_f64()
-        );
+        if runs == 0 && timed_out.load(Ordering::Relaxed) {
+            println!("no variant was finished in time, try generating for longer or using a faster method.");
+            return Ok(None);
+        }
+
+        if self.a.control.is_cancelled() && !timed_out.load(Ordering::Relaxed) {
+            println!(
+                "generation was stopped after {:.2} seconds, showing the best of {} variants, \
+                some of which may not be fully optimized.",
+                start.elapsed().as_secs_f64(),
+                runs,
+            );
+        } else {
+            println!(
+                "generating {} variants using {} with seed {} took {:.2} seconds.",
+                runs,
+                method,
+                seed,
+                start.elapsed().as_secs_f64()
+            );
+        }
+
+        if let (Some(dist), Some(best)) = (leaderboard.distribution(), leaderboard.best()) {
+            println!(
+                "scores: best {}, mean {:.0}, median {:.0}, worst {}, standard deviation {:.0}",
+                dist.best, dist.mean, dist.median, dist.worst, dist.std_dev
+            );
+            println!(
+                "the best layout was found by {} out of {} runs ({:.1}%).",
+                best.found,
+                runs,
+                best.found as f64 / runs as f64 * 100.0
+            );
+        }
+        if leaderboard.equivalences() != Default::default() {
+            println!(
+                "layouts that are equivalent by {} were counted as the same layout.",
+                leaderboard.equivalences()
+            );
+        }
+        println!("a single variant can be generated again by using its seed with a count of 1.");
+
+        if let Some(front) = &front {
+            println!(
+                "{} layouts are on the Pareto front, use pick to load one of them.",
+                front.entries().len()
+            );
+        }
+        if let Some(path) = path {
+            println!(
+                "saved a checkpoint to '{}', use resume to continue generating from it.",
+                path.display()
+            );
+        }
+
+        Ok(front)
+    }
+
+    fn front(&self) -> Result<()> {
+        let front = self.front.as_ref().ok_or(ReplError::NoParetoFront)?;
+        print_front(front);
+
+        Ok(())
+    }
+
+    fn pick(&mut self, index: usize, name: Option<String>) -> Result<()> {
+        let front = self.front.as_ref().ok_or(ReplError::NoParetoFront)?;
+        let entry = front
+            .entries()
+            .get(index)
+            .ok_or(ReplError::UnknownFrontEntry(index))?;
+
+        let name = name.unwrap_or_else(|| format!("pareto-{index}"));
+        let layout = Layout {
+            name: name.clone(),
+            ..entry.layout.clone()
+        };
+
+        self.layouts.insert(name.to_lowercase(), layout);
+        println!("loaded layout #{index} of the Pareto front as '{name}'.");
 
         Ok(())
     }
 
     fn sfbs(&self, name: &str, count: Option<usize>) -> Result<()> {
         let layout = self.layout(name)?;
-        let cache = self.a.cached_layout(layout.clone(), &[]);
+        let analyzer = self.analyzer(layout)?;
+        let cache = analyzer.cached_layout(layout.clone(), &[]);
         let count = count.unwrap_or(10);
 
         cache
@@ -171,12 +651,13 @@ impl Repl {
                     let u1 = cache.keys[*a as usize];
                     let u2 = cache.keys[*b as usize];
 
-                    let c1 = self.a.mapping().get_c(u1);
-                    let c2 = self.a.mapping().get_c(u2);
+                    let c1 = analyzer.mapping().get_c(u1);
+                    let c2 = analyzer.mapping().get_c(u2);
 
-                    let freq = self.a.data.get_bigram_u([u1, u2]) as f64 / self.a.data.bigram_total;
+                    let freq =
+                        analyzer.data.get_bigram_u([u1, u2]) as f64 / analyzer.data.bigram_total;
                     let freq2 =
-                        self.a.data.get_bigram_u([u2, u1]) as f64 / self.a.data.bigram_total;
+                        analyzer.data.get_bigram_u([u2, u1]) as f64 / analyzer.data.bigram_total;
 
                     [([c1, c2], freq), ([c2, c1], freq2)]
                 },
@@ -188,49 +669,141 @@ impl Repl {
         Ok(())
     }
 
+    fn scissors(&self, name: &str, count: Option<usize>) -> Result<()> {
+        let layout = self.layout(name)?;
+        let analyzer = self.analyzer(layout)?;
+        let cache = analyzer.cached_layout(layout.clone(), &[]);
+        let count = count.unwrap_or(10);
+
+        let full = cache.scissor_indices.full.iter().map(|p| (p, ""));
+        let half = cache.scissor_indices.half.iter().map(|p| (p, " (half)"));
+
+        full.chain(half)
+            .flat_map(
+                |(
+                    BigramPair {
+                        pair: PosPair(a, b),
+                        ..
+                    },
+                    kind,
+                )| {
+                    let u1 = cache.keys[*a as usize];
+                    let u2 = cache.keys[*b as usize];
+
+                    let c1 = analyzer.mapping().get_c(u1);
+                    let c2 = analyzer.mapping().get_c(u2);
+
+                    let freq =
+                        analyzer.data.get_bigram_u([u1, u2]) as f64 / analyzer.data.bigram_total;
+                    let freq2 =
+                        analyzer.data.get_bigram_u([u2, u1]) as f64 / analyzer.data.bigram_total;
+
+                    [([c1, c2], freq, kind), ([c2, c1], freq2, kind)]
+                },
+            )
+            .sorted_by(|(_, f1, _), (_, f2, _)| f2.total_cmp(f1))
+            .take(count)
+            .for_each(|([c1, c2], f, kind)| println!("{c1}{c2}: {f:.3}%{kind}"));
+
+        Ok(())
+    }
+
     pub fn trigrams(&self, name: &str) -> Result<()> {
         let layout = self.layout(name)?;
-        let trigram_stats = self.a.stats(layout).trigrams;
+        let trigram_stats = self.analyzer(layout)?.stats(layout).trigrams;
 
-        if trigram_stats.sft != 0.0 {
-            println!("Sft:          {:.3}%", trigram_stats.sft);
-        }
-        if trigram_stats.sfb != 0.0 {
-            println!("Sfb:          {:.3}%", trigram_stats.sfb);
-        }
-        if trigram_stats.inroll != 0.0 {
-            println!("Inroll:       {:.3}%", trigram_stats.inroll);
-        }
-        if trigram_stats.outroll != 0.0 {
-            println!("Outroll:      {:.3}%", trigram_stats.outroll);
-        }
-        if trigram_stats.alternate != 0.0 {
-            println!("Alternate:    {:.3}%", trigram_stats.alternate);
-        }
-        if trigram_stats.redirect != 0.0 {
-            println!("Redirect:     {:.3}%", trigram_stats.redirect);
+        for (category, freq) in trigram_stats.0 {
+            if freq != 0.0 {
+                println!("{:<13} {freq:.3}%", format!("{category}:"));
+            }
         }
-        if trigram_stats.onehandin != 0.0 {
-            println!("Onehand In:   {:.3}%", trigram_stats.onehandin);
+
+        Ok(())
+    }
+
+    fn repeat(&self, name: &str) -> Result<()> {
+        let layout = self.layout(name)?;
+
+        if !layout.keys.contains(&REPEAT_KEY) {
+            return Err(ReplError::NoRepeatKey(name.into()));
         }
-        if trigram_stats.onehandout != 0.0 {
-            println!("Onehand Out:  {:.3}%", trigram_stats.onehandout);
+
+        let mut without = layout.clone();
+        without
+            .keys
+            .iter_mut()
+            .filter(|c| **c == REPEAT_KEY)
+            .for_each(|c| *c = REPLACEMENT_CHAR);
+
+        let a_with = self.analyzer(layout)?;
+        let a_without = self.analyzer(&without)?;
+
+        let with = a_with.stats(layout);
+        let without_stats = a_without.stats(&without);
+
+        let repeat_usage = a_with.data.get_char(REPEAT_KEY) as f64 / a_with.data.char_total;
+
+        println!("{:<17} {:>10} {:>10}", "", "without", "with");
+        println!(
+            "{:<17} {:>10} {:>10}",
+            "score:",
+            a_without.score(&without),
+            a_with.score(layout)
+        );
+
+        for (stat, s1, s2) in [
+            ("sfbs:", without_stats.sfbs, with.sfbs),
+            ("sfs:", without_stats.sfs, with.sfs),
+            ("lsbs:", without_stats.lsbs, with.lsbs),
+            ("scissors:", without_stats.scissors, with.scissors),
+            ("repeat key usage:", 0.0, repeat_usage),
+        ] {
+            println!("{stat:<17} {:>9.3}% {:>9.3}%", s1, s2);
         }
-        if trigram_stats.thumb != 0.0 {
-            println!("Thumb:        {:.3}%", trigram_stats.thumb);
+
+        Ok(())
+    }
+
+    fn magic(&self, name: &str) -> Result<()> {
+        let layout = self.layout(name)?;
+
+        if !layout.keys.contains(&MAGIC_KEY) {
+            return Err(ReplError::NoMagicKey(name.into()));
         }
-        if trigram_stats.invalid != 0.0 {
-            println!("Invalid:      {:.3}%", trigram_stats.invalid);
+
+        for (prev, output, sfbs) in self.a.magic_rule_sfbs(layout)? {
+            println!("{prev}{MAGIC_KEY} → {prev}{output}: removes {sfbs:.3}% sfbs");
         }
 
         Ok(())
     }
 
+    fn trigram_table(&self, category: Option<&str>) {
+        let table = &self.a.trigram_table;
+
+        for f1 in Finger::FINGERS {
+            for f2 in Finger::FINGERS {
+                for f3 in Finger::FINGERS {
+                    let c = table.category([f1, f2, f3]);
+
+                    if category.is_none_or(|category| category == c) {
+                        println!("{f1:?} {f2:?} {f3:?}: {c}");
+                    }
+                }
+            }
+        }
+    }
+
     pub fn reload(&mut self) -> Result<()> {
         let new = Self::with_config(&self.config_path)?;
 
-        self.a = new.a;
+        let mut a = Arc::unwrap_or_clone(new.a);
+        a.control = self.a.control.clone();
+
+        self.a = Arc::new(a);
+        self.analyzers = new.analyzers;
         self.layouts = new.layouts;
+        self.constraints = new.constraints;
 
         Ok(())
     }
@@ -248,10 +821,25 @@ impl Repl {
 
         match flags.subcommand {
             OxeylyzerCmd::Analyze(a) => self.analyze(&a.name)?,
-            OxeylyzerCmd::Rank(_) => self.rank(),
-            OxeylyzerCmd::Gen(g) => self.generate(&g.name, g.count, g.pins)?,
+            OxeylyzerCmd::Rank(_) => self.rank()?,
+            OxeylyzerCmd::Gen(g) => {
+                if let Some(front) = self.generate(g)? {
+                    self.front = Some(front);
+                }
+            }
+            OxeylyzerCmd::Resume(r) => {
+                if let Some(front) = self.resume(&r.path, r.force)? {
+                    self.front = Some(front);
+                }
+            }
+            OxeylyzerCmd::Front(_) => self.front()?,
+            OxeylyzerCmd::Pick(p) => self.pick(p.index, p.name)?,
             OxeylyzerCmd::Sfbs(s) => self.sfbs(&s.name, s.count)?,
+            OxeylyzerCmd::Scissors(s) => self.scissors(&s.name, s.count)?,
             OxeylyzerCmd::Trigrams(t) => self.trigrams(&t.name)?,
+            OxeylyzerCmd::Repeat(r) => self.repeat(&r.name)?,
+            OxeylyzerCmd::Magic(m) => self.magic(&m.name)?,
+            OxeylyzerCmd::TrigramTable(t) => self.trigram_table(t.category.as_deref()),
             OxeylyzerCmd::R(_) => self.reload()?,
             OxeylyzerCmd::Q(_) => return Ok(ReplStatus::Quit),
         }
@@ -262,6 +850,17 @@ impl Repl {
     pub fn run(&mut self) -> Result<()> {
         use ReplStatus::*;
 
+        let cancellation = self.a.control.cancellation.clone();
+        let progress = self.progress.clone();
+
+        // Ctrl-C stops generating layouts, and only quits when nothing is being generated.
+        if let Err(e) = ctrlc::set_handler(move || match progress.is_active() {
+            true => cancellation.cancel(),
+            false => std::process::exit(130),
+        }) {
+            println!("Generating layouts can't be stopped with Ctrl-C: {e}");
+        }
+
         loop {
             let line = readline()?;
             let line = line.trim();
@@ -315,6 +914,25 @@ fn load_layouts<P: AsRef<Path>>(path: P) -> Result<HashMap<String, Layout>> {
     }
 }
 
+fn print_front(front: &ParetoFront) {
+    for (i, entry) in front.entries().iter().enumerate() {
+        let mut layout = entry.layout.clone();
+        layout.name = "".into();
+
+        let values = front
+            .objectives()
+            .iter()
+            .zip(&entry.values)
+            .map(|(o, v)| format!("{}: {v:.3}", o.metric))
+            .join(", ");
+
+        println!(
+            "#{}, score: {}, seed: {}, found {} times\n{}{}",
+            i, entry.score, entry.seed, entry.found, values, layout
+        );
+    }
+}
+
 pub fn pin_positions(layout: &Layout, pin_chars: String) -> Vec<usize> {
     match pin_chars.len() {
         0 => vec![],
@@ -0,0 +1,126 @@
+use std::{
+    io::Write as _,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use oxeylyzer_core::progress::{OptimizationObserver, OptimizationProgress};
+
+/// Minimum time between two progress updates.
+const PRINT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Shows the progress of generating layouts on a single line that is updated while generating.
+#[derive(Debug)]
+pub struct GenerationProgress {
+    active: AtomicBool,
+    count: AtomicUsize,
+    time: Mutex<Option<Duration>>,
+    finished: AtomicUsize,
+    best_score: AtomicI64,
+    timing: Mutex<Timing>,
+}
+
+#[derive(Debug)]
+struct Timing {
+    start: Instant,
+    last_print: Option<Instant>,
+}
+
+impl GenerationProgress {
+    pub fn new() -> Self {
+        Self {
+            active: AtomicBool::new(false),
+            count: AtomicUsize::new(0),
+            time: Mutex::new(None),
+            finished: AtomicUsize::new(0),
+            best_score: AtomicI64::new(i64::MIN),
+            timing: Mutex::new(Timing {
+                start: Instant::now(),
+                last_print: None,
+            }),
+        }
+    }
+
+    /// Starts showing progress of generating `count` layouts, or as many as possible in `time`.
+    pub fn start(&self, count: Option<usize>, time: Option<Duration>) {
+        *self.timing.lock().unwrap() = Timing {
+            start: Instant::now(),
+            last_print: None,
+        };
+        *self.time.lock().unwrap() = time;
+        self.count.store(count.unwrap_or(0), Ordering::Relaxed);
+        self.finished.store(0, Ordering::Relaxed);
+        self.best_score.store(i64::MIN, Ordering::Relaxed);
+        self.active.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops showing progress, moving to the next line if any progress was shown.
+    pub fn finish(&self) {
+        self.active.store(false, Ordering::Relaxed);
+
+        if self.timing.lock().unwrap().last_print.is_some() {
+            println!();
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn run_finished(&self, score: i64) {
+        self.finished.fetch_add(1, Ordering::Relaxed);
+        self.update(score);
+    }
+
+    fn update(&self, score: i64) {
+        self.best_score.fetch_max(score, Ordering::Relaxed);
+
+        let Ok(mut timing) = self.timing.try_lock() else {
+            return;
+        };
+
+        if timing
+            .last_print
+            .is_some_and(|last| last.elapsed() < PRINT_INTERVAL)
+        {
+            return;
+        }
+        timing.last_print = Some(Instant::now());
+
+        let finished = match self.count.load(Ordering::Relaxed) {
+            0 => self.finished.load(Ordering::Relaxed).to_string(),
+            count => format!("{}/{count}", self.finished.load(Ordering::Relaxed)),
+        };
+        let elapsed = match *self.time.lock().unwrap() {
+            Some(time) => format!(
+                "{:.1}/{:.0}s",
+                timing.start.elapsed().as_secs_f64(),
+                time.as_secs_f64()
+            ),
+            None => format!("{:.1}s", timing.start.elapsed().as_secs_f64()),
+        };
+
+        print!(
+            "\r{finished} finished, best score: {}, {elapsed}   ",
+            self.best_score.load(Ordering::Relaxed),
+        );
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Default for GenerationProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OptimizationObserver for GenerationProgress {
+    fn progress(&self, progress: &OptimizationProgress) {
+        if self.is_active() {
+            self.update(progress.best_score);
+        }
+    }
+}
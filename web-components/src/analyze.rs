@@ -75,6 +75,7 @@ pub fn RenderDofAnalyzer(dof: Dof) -> impl IntoView {
         fingers,
         keyboard,
         shape,
+        ..
     } = Layout::from(dof.clone());
 
     let keys = keys
@@ -339,19 +340,29 @@ fn RenderAnalysis(data: Data, weights: impl Fn() -> GlobalWeights + 'static) ->
     let keys = expect_context::<LayoutKeys>().0;
     // let pins = use_context::<RwSignal<Pins>>();
 
-    let analyzer = create_memo(move |_| Analyzer::new(data.clone(), weights().into()));
+    let analyzer = create_memo(move |_| {
+        Analyzer::new(data.clone(), weights().into())
+            .expect("the bundled corpora have few enough chars")
+    });
     let layout_memo = create_memo(move |_| Layout {
         name: phys.name.clone(),
         keys: keys.iter().map(|s| s()).collect(),
         fingers: phys.fingers.clone(),
         keyboard: phys.keyboard.clone(),
         shape: phys.shape.clone(),
+        board: None,
+        layers: Default::default(),
+        magic_rules: Default::default(),
+        constraints: Default::default(),
     });
 
     let stats_memo = create_memo(move |_| analyzer.with(|a| layout_memo.with(|l| a.stats(l))));
 
     let sfbs = create_memo(move |_| stats_memo.with(|s| s.sfbs));
     let sfs = create_memo(move |_| stats_memo.with(|s| s.sfs));
+    let lsbs = create_memo(move |_| stats_memo.with(|s| s.lsbs));
+    let scissors = create_memo(move |_| stats_memo.with(|s| s.scissors));
+    let half_scissors = create_memo(move |_| stats_memo.with(|s| s.half_scissors));
     let score = create_memo(move |_| analyzer.with(|a| layout_memo.with(|l| a.score(l))));
 
     let finger_use =
@@ -375,16 +386,16 @@ fn RenderAnalysis(data: Data, weights: impl Fn() -> GlobalWeights + 'static) ->
         unweighted_finger_distance,
     };
 
-    let t_sft = create_memo(move |_| stats_memo.with(|s| s.trigrams.sft));
-    let t_sfb = create_memo(move |_| stats_memo.with(|s| s.trigrams.sfb));
-    let t_inroll = create_memo(move |_| stats_memo.with(|s| s.trigrams.inroll));
-    let t_outroll = create_memo(move |_| stats_memo.with(|s| s.trigrams.outroll));
-    let t_alternate = create_memo(move |_| stats_memo.with(|s| s.trigrams.alternate));
-    let t_redirect = create_memo(move |_| stats_memo.with(|s| s.trigrams.redirect));
-    let t_onehandin = create_memo(move |_| stats_memo.with(|s| s.trigrams.onehandin));
-    let t_onehandout = create_memo(move |_| stats_memo.with(|s| s.trigrams.onehandout));
-    let t_thumb = create_memo(move |_| stats_memo.with(|s| s.trigrams.thumb));
-    let t_invalid = create_memo(move |_| stats_memo.with(|s| s.trigrams.invalid));
+    let t_sft = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("sft")));
+    let t_sfb = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("sfb")));
+    let t_inroll = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("inroll")));
+    let t_outroll = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("outroll")));
+    let t_alternate = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("alternate")));
+    let t_redirect = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("redirect")));
+    let t_onehandin = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("onehandin")));
+    let t_onehandout = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("onehandout")));
+    let t_thumb = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("thumb")));
+    let t_invalid = create_memo(move |_| stats_memo.with(|s| s.trigrams.get("invalid")));
 
     // let is_window_lg = leptos_use::use_media_query("(min-width: 1024px)");
 
@@ -394,6 +405,9 @@ fn RenderAnalysis(data: Data, weights: impl Fn() -> GlobalWeights + 'static) ->
                 <StatGroup description="Bigrams">
                     <F64Stat name="sfbs:" stat=sfbs unit="%"/>
                     <F64Stat name="sfs:" stat=sfs unit="%"/>
+                    <F64Stat name="lsbs:" stat=lsbs unit="%"/>
+                    <F64Stat name="scissors:" stat=scissors unit="%"/>
+                    <F64Stat name="half scissors:" stat=half_scissors unit="%"/>
                 </StatGroup>
                 <StatGroup description="Trigrams">
                     <F64Stat name="sft:" stat=t_sft unit="%"/>
@@ -48,6 +48,8 @@ pub fn WeightSettings() -> impl IntoView {
     let GlobalWeights {
         sfbs,
         sfs,
+        lsbs,
+        scissors,
         sft,
         inroll,
         outroll,
@@ -77,6 +79,8 @@ pub fn WeightSettings() -> impl IntoView {
                 <SettingGroup header="Weights">
                     <ValueSetting description="sfbs:" affect=sfbs/>
                     <ValueSetting description="sfs:" affect=sfs/>
+                    <ValueSetting description="lsbs:" affect=lsbs/>
+                    <ValueSetting description="scissors:" affect=scissors/>
                     <ValueSetting description="sft:" affect=sft/>
                     <ValueSetting description="inroll:" affect=inroll/>
                     <ValueSetting description="outroll:" affect=outroll/>
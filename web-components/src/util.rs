@@ -40,6 +40,8 @@ impl Default for HeatmapTheme {
 pub struct GlobalWeights {
     pub sfbs: RwSignal<i64>,
     pub sfs: RwSignal<i64>,
+    pub lsbs: RwSignal<i64>,
+    pub scissors: RwSignal<i64>,
     pub sft: RwSignal<i64>,
     pub inroll: RwSignal<i64>,
     pub outroll: RwSignal<i64>,
@@ -70,6 +72,8 @@ impl Default for GlobalWeights {
         Self {
             sfbs: create_rw_signal(-7),
             sfs: create_rw_signal(-1),
+            lsbs: create_rw_signal(-5),
+            scissors: create_rw_signal(-5),
             sft: create_rw_signal(-12),
             inroll: create_rw_signal(5),
             outroll: create_rw_signal(4),
@@ -99,6 +103,8 @@ impl From<GlobalWeights> for Weights {
         Self {
             sfbs: w.sfbs.get(),
             sfs: w.sfs.get(),
+            lsbs: w.lsbs.get(),
+            scissors: w.scissors.get(),
             sft: w.sft.get(),
             inroll: w.inroll.get(),
             outroll: w.outroll.get(),
@@ -119,6 +125,10 @@ impl From<GlobalWeights> for Weights {
                 rr: w.fingers.rr.get(),
                 rp: w.fingers.rp.get(),
             },
+            finger_usage: Default::default(),
+            effort: Default::default(),
+            trigram_categories: Default::default(),
+            trigram_table: Default::default(),
         }
     }
 }